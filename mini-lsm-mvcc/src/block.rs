@@ -5,39 +5,120 @@ pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
-pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+use crate::key::{KeyVec, ValueType};
+
+const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// The default number of entries between two restart points.
+pub const DEFAULT_RESTART_INTERVAL: usize = 16;
+
+/// Set on the restart count when the block carries a hash index.
+const HASH_INDEX_FLAG: u32 = 1 << 31;
+/// A hash bucket that no key maps to.
+pub(crate) const HASH_BUCKET_EMPTY: u8 = u8::MAX;
+/// A hash bucket shared by keys from different restart runs.
+pub(crate) const HASH_BUCKET_COLLISION: u8 = u8::MAX - 1;
+/// Blocks with more restart points than this do not get a hash index, as the restart index of a bucket
+/// must not clash with the markers above.
+pub(crate) const HASH_INDEX_MAX_RESTARTS: usize = HASH_BUCKET_COLLISION as usize;
+
+/// Returns the hash bucket of a key hash.
+pub(crate) fn hash_bucket(key_hash: u32, num_buckets: usize) -> usize {
+    key_hash as usize % num_buckets
+}
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
+///
+/// Keys are delta-encoded against the key of the previous entry. Every `restart_interval` entries the
+/// full key is stored instead, and the offset of such an entry is recorded as a restart point, so that
+/// a seek can binary-search the restart points before scanning a few entries linearly.
+///
+/// A block may optionally carry a hash index, which maps the hash of each key to the restart point
+/// whose run holds the first version of the key, so that point lookups can skip the binary search.
 pub struct Block {
     pub(crate) data: Vec<u8>,
-    pub(crate) offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    pub(crate) offsets: Vec<u32>,
+    /// Hash index buckets, empty if the block has no hash index.
+    pub(crate) hash_buckets: Vec<u8>,
 }
 
 impl Block {
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
+        let mut restarts_len = self.offsets.len() as u32;
+        if !self.hash_buckets.is_empty() {
+            buf.extend_from_slice(&self.hash_buckets);
+            buf.put_u32(self.hash_buckets.len() as u32);
+            restarts_len |= HASH_INDEX_FLAG;
+        }
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            buf.put_u32(*offset);
         }
-        // Adds number of elements at the end of the block
-        buf.put_u16(offsets_len as u16);
+        // Adds number of restart points at the end of the block
+        buf.put_u32(restarts_len);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
-        // get number of elements in the block
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
-        // get offset array
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+        // get number of restart points in the block
+        let restarts_len = (&data[data.len() - SIZEOF_U32..]).get_u32();
+        let has_hash_index = restarts_len & HASH_INDEX_FLAG != 0;
+        let restarts_len = (restarts_len & !HASH_INDEX_FLAG) as usize;
+        let restarts_begin = data.len() - SIZEOF_U32 - restarts_len * SIZEOF_U32;
+        let restarts_raw = &data[restarts_begin..data.len() - SIZEOF_U32];
+        // get restart array
+        let restarts = restarts_raw
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
+        // get hash index, which sits between the entries and the restart array
+        let mut data_end = restarts_begin;
+        let mut hash_buckets = Vec::new();
+        if has_hash_index {
+            let num_buckets =
+                (&data[restarts_begin - SIZEOF_U32..restarts_begin]).get_u32() as usize;
+            data_end = restarts_begin - SIZEOF_U32 - num_buckets;
+            hash_buckets = data[data_end..restarts_begin - SIZEOF_U32].to_vec();
+        }
         // retrieve data
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Self {
+            data,
+            offsets: restarts,
+            hash_buckets,
+        }
+    }
+
+    /// Decode a block of an SST of format version 1, and re-encode its entries in the current layout. Version 1
+    /// blocks end with the offset (u16) of each entry and the number of entries (u16); an entry is overlap with
+    /// the first key (u16) | key_len (u16) | key | ts (u64) | value_len (u16) | value, and an empty value is a
+    /// deletion.
+    pub(crate) fn decode_v1(data: &[u8]) -> Self {
+        let num_entries = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        let offsets_begin = data.len() - SIZEOF_U16 - num_entries * SIZEOF_U16;
+        let mut builder = BlockBuilder::new(usize::MAX);
+        let mut first_key = KeyVec::new();
+        let mut key = KeyVec::new();
+        for mut offset in data[offsets_begin..data.len() - SIZEOF_U16].chunks(SIZEOF_U16) {
+            let mut entry = &data[offset.get_u16() as usize..];
+            let overlap_len = entry.get_u16() as usize;
+            let key_len = entry.get_u16() as usize;
+            key.clear();
+            key.append(&first_key.key_ref()[..overlap_len]);
+            key.append(&entry[..key_len]);
+            entry.advance(key_len);
+            key.set_ts(entry.get_u64());
+            let value_len = entry.get_u16() as usize;
+            let value = &entry[..value_len];
+            let value_type = ValueType::of_untyped_value(value);
+            assert!(builder.add_with_type(key.as_key_slice(), value_type, value));
+            if first_key.is_empty() {
+                first_key = key.clone();
+            }
+        }
+        builder.build()
     }
 }
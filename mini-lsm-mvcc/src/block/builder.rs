@@ -1,28 +1,43 @@
 use bytes::BufMut;
 
-use crate::key::{KeySlice, KeyVec};
+use crate::key::{KeySlice, KeyVec, ValueType};
 
-use super::{Block, SIZEOF_U16};
+use super::{
+    hash_bucket, Block, DEFAULT_RESTART_INTERVAL, HASH_BUCKET_COLLISION, HASH_BUCKET_EMPTY,
+    HASH_INDEX_MAX_RESTARTS, SIZEOF_U32,
+};
+use crate::varint::{put_varint, varint_len};
+
+/// Number of distinct keys per hash bucket.
+const HASH_INDEX_UTIL_RATIO: f64 = 0.75;
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    restarts: Vec<u32>,
     /// All serialized key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
-    /// The first key in the block
-    first_key: KeyVec,
+    /// Number of entries between two restart points.
+    restart_interval: usize,
+    /// Number of entries added to the block.
+    num_entries: usize,
+    /// The last key in the block, which the next key is delta-encoded against.
+    last_key: KeyVec,
+    /// Whether to build a hash index.
+    hash_index: bool,
+    /// Hash of each distinct key (without ts) and the restart point it first appears after.
+    hash_entries: Vec<(u32, u8)>,
 }
 
-fn compute_overlap(first_key: KeySlice, key: KeySlice) -> usize {
+fn compute_overlap(last_key: KeySlice, key: KeySlice) -> usize {
     let mut i = 0;
     loop {
-        if i >= first_key.key_len() || i >= key.key_len() {
+        if i >= last_key.key_len() || i >= key.key_len() {
             break;
         }
-        if first_key.key_ref()[i] != key.key_ref()[i] {
+        if last_key.key_ref()[i] != key.key_ref()[i] {
             break;
         }
         i += 1;
@@ -34,53 +49,117 @@ impl BlockBuilder {
     /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
         Self {
-            offsets: Vec::new(),
+            restarts: Vec::new(),
             data: Vec::new(),
             block_size,
-            first_key: KeyVec::new(),
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            num_entries: 0,
+            last_key: KeyVec::new(),
+            hash_index: false,
+            hash_entries: Vec::new(),
         }
     }
 
+    /// Set the number of entries between two restart points.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        self.restart_interval = restart_interval;
+        self
+    }
+
+    /// Build a hash index over the keys of the block for point lookups.
+    pub fn with_hash_index(mut self, hash_index: bool) -> Self {
+        self.hash_index = hash_index;
+        self
+    }
+
+    fn num_hash_buckets(&self) -> usize {
+        (self.hash_entries.len() as f64 / HASH_INDEX_UTIL_RATIO) as usize + 1
+    }
+
     fn estimated_size(&self) -> usize {
-        SIZEOF_U16 /* number of key-value pairs in the block */ +  self.offsets.len() * SIZEOF_U16 /* offsets */ + self.data.len()
-        // key-value pairs
+        let mut size = SIZEOF_U32 /* number of restart points in the block */ +  self.restarts.len() * SIZEOF_U32 /* restarts */ + self.data.len() /* key-value pairs */;
+        if self.hash_index {
+            size += SIZEOF_U32 /* number of buckets */ + self.num_hash_buckets();
+        }
+        size
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Adds a key-value pair to the block, an empty value being a deletion. Returns false when the block is
+    /// full.
     #[must_use]
     pub fn add(&mut self, key: KeySlice, value: &[u8]) -> bool {
+        self.add_with_type(key, ValueType::of_untyped_value(value), value)
+    }
+
+    /// Adds a key-value pair of the given value type to the block. Returns false when the block is full.
+    #[must_use]
+    pub fn add_with_type(&mut self, key: KeySlice, value_type: ValueType, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.raw_len() + value.len() + SIZEOF_U16 * 3 /* key_len, value_len and offset */ > self.block_size
-            && !self.is_empty()
-        {
+        let is_restart = self.num_entries.is_multiple_of(self.restart_interval);
+        let overlap = if is_restart {
+            // A restart point stores the full key.
+            0
+        } else {
+            compute_overlap(self.last_key.as_key_slice(), key)
+        };
+        let rest_len = key.key_len() - overlap;
+        let entry_size = varint_len(overlap as u32) + varint_len(rest_len as u32) + rest_len + std::mem::size_of::<u64>() /* ts */
+            + 1 /* value type */ + varint_len(value.len() as u32) + value.len()
+            + if is_restart { SIZEOF_U32 } else { 0 } /* restart */;
+        // An entry larger than the block size still goes into an empty block, so that it occupies a
+        // block of its own.
+        if self.estimated_size() + entry_size > self.block_size && !self.is_empty() {
             return false;
         }
-        // Add the offset of the data into the offset array.
-        self.offsets.push(self.data.len() as u16);
-        let overlap = compute_overlap(self.first_key.as_key_slice(), key);
+        if is_restart {
+            self.restarts.push(self.data.len() as u32);
+        }
         // Encode key overlap.
-        self.data.put_u16(overlap as u16);
+        put_varint(&mut self.data, overlap as u32);
         // Encode key length.
-        self.data.put_u16((key.key_len() - overlap) as u16);
+        put_varint(&mut self.data, rest_len as u32);
         // Encode key content.
         self.data.put(&key.key_ref()[overlap..]);
         // Encode key ts
         self.data.put_u64(key.ts());
+        // Encode value type.
+        self.data.put_u8(value_type.to_u8());
         // Encode value length.
-        self.data.put_u16(value.len() as u16);
+        put_varint(&mut self.data, value.len() as u32);
         // Encode value content.
         self.data.put(value);
 
-        if self.first_key.is_empty() {
-            self.first_key = key.to_key_vec();
+        if self.hash_index && (self.is_empty() || self.last_key.key_ref() != key.key_ref()) {
+            let restart_idx = (self.restarts.len() - 1).min(HASH_INDEX_MAX_RESTARTS) as u8;
+            self.hash_entries
+                .push((farmhash::fingerprint32(key.key_ref()), restart_idx));
         }
+        self.last_key.set_from_slice(key);
+        self.num_entries += 1;
 
         true
     }
 
     /// Check if there are no key-value pairs in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.num_entries == 0
+    }
+
+    fn build_hash_index(&self) -> Vec<u8> {
+        if !self.hash_index || self.restarts.len() > HASH_INDEX_MAX_RESTARTS {
+            return Vec::new();
+        }
+        let mut buckets = vec![HASH_BUCKET_EMPTY; self.num_hash_buckets()];
+        for (key_hash, restart_idx) in &self.hash_entries {
+            let bucket = &mut buckets[hash_bucket(*key_hash, self.num_hash_buckets())];
+            if *bucket == HASH_BUCKET_EMPTY || *bucket == *restart_idx {
+                *bucket = *restart_idx;
+            } else {
+                *bucket = HASH_BUCKET_COLLISION;
+            }
+        }
+        buckets
     }
 
     /// Finalize the block.
@@ -88,9 +167,11 @@ impl BlockBuilder {
         if self.is_empty() {
             panic!("block should not be empty");
         }
+        let hash_buckets = self.build_hash_index();
         Block {
             data: self.data,
-            offsets: self.offsets,
+            offsets: self.restarts,
+            hash_buckets,
         }
     }
 }
@@ -3,11 +3,11 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use crate::{
-    block::SIZEOF_U16,
-    key::{KeySlice, KeyVec},
+    key::{KeySlice, KeyVec, ValueType},
+    varint::get_varint,
 };
 
-use super::Block;
+use super::{hash_bucket, Block, HASH_BUCKET_COLLISION, HASH_BUCKET_EMPTY};
 
 /// Iterates on a block.
 pub struct BlockIterator {
@@ -15,33 +15,35 @@ pub struct BlockIterator {
     block: Arc<Block>,
     /// the current key at the iterator position
     key: KeyVec,
+    /// the offset of the current entry in the block
+    offset: usize,
     /// the value range from the block
     value_range: (usize, usize),
-    /// the current index at the iterator position
-    idx: usize,
-    /// the first key in the block
-    first_key: KeyVec,
+    /// the value type of the current entry
+    value_type: ValueType,
 }
 
 impl Block {
-    fn get_first_key(&self) -> KeyVec {
-        let mut buf = &self.data[..];
-        buf.get_u16();
-        let key_len = buf.get_u16() as usize;
+    /// Returns the full key stored at the `idx`-th restart point.
+    fn restart_key(&self, idx: usize) -> KeySlice<'_> {
+        let mut buf = &self.data[self.offsets[idx] as usize..];
+        let overlap_len = get_varint(&mut buf);
+        debug_assert_eq!(overlap_len, 0, "restart point must store the full key");
+        let key_len = get_varint(&mut buf) as usize;
         let key = &buf[..key_len];
         buf.advance(key_len);
-        KeyVec::from_vec_with_ts(key.to_vec(), buf.get_u64())
+        KeySlice::from_slice(key, buf.get_u64())
     }
 }
 
 impl BlockIterator {
     fn new(block: Arc<Block>) -> Self {
         Self {
-            first_key: block.get_first_key(),
             block,
             key: KeyVec::new(),
+            offset: 0,
             value_range: (0, 0),
-            idx: 0,
+            value_type: ValueType::Put,
         }
     }
 
@@ -52,6 +54,20 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the last key in the block.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
+    /// Creates a block iterator and seek to the last key that <= `key`.
+    pub fn create_and_seek_for_prev(block: Arc<Block>, key: KeySlice) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_for_prev(key);
+        iter
+    }
+
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
         let mut iter = Self::new(block);
@@ -59,6 +75,14 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the first key that >= `key`, consulting the hash index of the
+    /// block if there is one. Intended for point lookups.
+    pub fn create_and_seek_to_key_by_hash(block: Arc<Block>, key: KeySlice) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_key_by_hash(key);
+        iter
+    }
+
     /// Returns the key of the current entry.
     pub fn key(&self) -> KeySlice {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
@@ -71,6 +95,12 @@ impl BlockIterator {
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the value type of the current entry.
+    pub fn value_type(&self) -> ValueType {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.value_type
+    }
+
     /// Returns true if the iterator is valid.
     pub fn is_valid(&self) -> bool {
         !self.key.is_empty()
@@ -78,65 +108,145 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_restart(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        let Some(idx) = self.block.offsets.len().checked_sub(1) else {
+            self.invalidate();
+            return;
+        };
+        self.seek_to_restart(idx);
+        while self.value_range.1 < self.block.data.len() {
+            self.next();
+        }
+    }
+
+    /// Seeks to the idx-th restart point in the block.
+    fn seek_to_restart(&mut self, idx: usize) {
         if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value_range = (0, 0);
+            self.invalidate();
             return;
         }
         let offset = self.block.offsets[idx] as usize;
         self.seek_to_offset(offset);
-        self.idx = idx;
+    }
+
+    fn invalidate(&mut self) {
+        self.key.clear();
+        self.value_range = (0, 0);
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        // Entries are stored back to back, so the next entry starts right after the current value.
+        let offset = self.value_range.1;
+        if offset >= self.block.data.len() {
+            self.invalidate();
+            return;
+        }
+        self.seek_to_offset(offset);
     }
 
-    /// Seek to the specified position and update the current `key` and `value`
-    /// Index update will be handled by caller
+    /// Move to the previous key in the block.
+    pub fn prev(&mut self) {
+        // Keys can only be decoded forward from a restart point, so scan the run holding the previous entry
+        // until reaching the entry that ends where the current one starts.
+        let offset = self.offset;
+        let idx = self
+            .block
+            .offsets
+            .partition_point(|restart| (*restart as usize) < offset);
+        if idx == 0 {
+            self.invalidate();
+            return;
+        }
+        self.seek_to_restart(idx - 1);
+        while self.value_range.1 < offset {
+            self.next();
+        }
+    }
+
+    /// Decode the entry at the specified offset and update the current `key` and `value`. The entry's
+    /// key shares its prefix with the current key, so the caller must position the iterator on the
+    /// previous entry first unless `offset` is a restart point.
     fn seek_to_offset(&mut self, offset: usize) {
+        self.offset = offset;
         let mut entry = &self.block.data[offset..];
-        // Since `get_u16()` will automatically move the ptr 2 bytes ahead here,
+        // Since `get_varint()` will automatically move the ptr past the length here,
         // we don't need to manually advance it
-        let overlap_len = entry.get_u16() as usize;
-        let key_len = entry.get_u16() as usize;
+        let overlap_len = get_varint(&mut entry) as usize;
+        let key_len = get_varint(&mut entry) as usize;
         let key = &entry[..key_len];
-        self.key.clear();
-        self.key.append(&self.first_key.key_ref()[..overlap_len]);
+        self.key.truncate(overlap_len);
         self.key.append(key);
         entry.advance(key_len);
         let ts = entry.get_u64();
         self.key.set_ts(ts);
-        let value_len = entry.get_u16() as usize;
-        // REMEMBER TO CHANGE THIS every time you change the encoding!
-        let value_offset_begin =
-            offset + SIZEOF_U16 + SIZEOF_U16 + std::mem::size_of::<u64>() + key_len + SIZEOF_U16;
+        self.value_type = ValueType::from_u8(entry.get_u8());
+        let value_len = get_varint(&mut entry) as usize;
+        // Lengths are variable-sized, so derive the value position from what is left of the block.
+        let value_offset_begin = self.block.data.len() - entry.len();
         let value_offset_end = value_offset_begin + value_len;
         self.value_range = (value_offset_begin, value_offset_end);
-        entry.advance(value_len);
     }
 
     /// Seek to the first key that is >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) {
+        // Find the first restart point whose key is >= `key`. The target lies either at that restart
+        // point or within the run of entries following the previous one.
         let mut low = 0;
         let mut high = self.block.offsets.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
-            assert!(self.is_valid());
-            match self.key().cmp(&key) {
+            match self.block.restart_key(mid).cmp(&key) {
                 std::cmp::Ordering::Less => low = mid + 1,
                 std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+                std::cmp::Ordering::Equal => {
+                    self.seek_to_restart(mid);
+                    return;
+                }
             }
         }
-        self.seek_to(low);
+        self.seek_to_restart(low.saturating_sub(1));
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
+    }
+
+    /// Seek to the last key that is <= `key`.
+    pub fn seek_for_prev(&mut self, key: KeySlice) {
+        self.seek_to_key(key);
+        if !self.is_valid() {
+            self.seek_to_last();
+        } else if self.key() != key {
+            self.prev();
+        }
+    }
+
+    /// Seek to the first key that is >= `key`. The restart point is looked up in the hash index instead of
+    /// binary-searching the restart array. Falls back to `seek_to_key` if the block has no hash index, the
+    /// bucket is shared with keys of another restart run, or the key is not in the block.
+    pub fn seek_to_key_by_hash(&mut self, key: KeySlice) {
+        let buckets = &self.block.hash_buckets;
+        if buckets.is_empty() {
+            self.seek_to_key(key);
+            return;
+        }
+        let bucket = buckets[hash_bucket(farmhash::fingerprint32(key.key_ref()), buckets.len())];
+        if bucket == HASH_BUCKET_EMPTY || bucket == HASH_BUCKET_COLLISION {
+            self.seek_to_key(key);
+            return;
+        }
+        self.seek_to_restart(bucket as usize);
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
+        // The bucket may belong to another key that hashes the same; only trust the scan if it found the
+        // key it was looking for.
+        if !self.is_valid() || self.key().key_ref() != key.key_ref() {
+            self.seek_to_key(key);
+        }
     }
 }
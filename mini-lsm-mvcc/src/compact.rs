@@ -3,10 +3,11 @@ mod simple_leveled;
 mod tiered;
 
 use std::collections::HashSet;
+use std::ops::Bound;
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
@@ -18,12 +19,235 @@ use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
-use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::key::{KeySlice, ValueType, TS_RANGE_BEGIN};
+use crate::lsm_storage::{range_overlap, CompactionFilter, LsmStorageInner, LsmStorageState};
 use crate::manifest::ManifestRecord;
+use crate::range_tombstone::{max_covering_ts, RangeTombstone};
 use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::value_log::ValuePointer;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A compaction whose upper SSTs overlap nothing in the lower level, applied by relinking the SSTs into the
+/// lower level without reading or rewriting them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrivialMove {
+    // if upper_level is `None`, the SSTs are moved from L0
+    pub upper_level: Option<usize>,
+    pub lower_level: usize,
+    pub sst_ids: Vec<usize>,
+}
+
+impl TrivialMove {
+    /// Relink the SSTs into the lower level. In recovery, the key ranges of the SSTs are not known yet, so
+    /// the lower level is left unsorted.
+    pub(crate) fn apply(&self, snapshot: &LsmStorageState, in_recovery: bool) -> LsmStorageState {
+        let mut snapshot = snapshot.clone();
+        let mut moved = self.sst_ids.iter().copied().collect::<HashSet<_>>();
+        let upper_level_ssts = match self.upper_level {
+            Some(upper_level) => &mut snapshot.levels[upper_level - 1].1,
+            None => &mut snapshot.l0_sstables,
+        };
+        upper_level_ssts.retain(|id| !moved.remove(id));
+        assert!(moved.is_empty(), "moved SSTs not in the upper level");
+        let lower_level_ssts = &mut snapshot.levels[self.lower_level - 1].1;
+        lower_level_ssts.extend(&self.sst_ids);
+        if !in_recovery {
+            lower_level_ssts.sort_by(|x, y| {
+                snapshot.sstables[x]
+                    .first_key()
+                    .cmp(snapshot.sstables[y].first_key())
+            });
+        }
+        snapshot
+    }
+}
+
+/// A compaction task being run. Tasks run concurrently as long as they do not conflict.
+pub(crate) struct RunningCompaction {
+    input_sst_ids: Vec<usize>,
+    // first and last levels read or written, 0 being L0, `None` for tiered compaction which may renumber every
+    // tier
+    levels: Option<(usize, usize)>,
+    // user key range of the inputs, `None` if the task owns its levels as a whole
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RunningCompaction {
+    pub(crate) fn new(task: &CompactionTask, snapshot: &LsmStorageState) -> Self {
+        let input_sst_ids = task.input_sst_ids();
+        let (levels, key_range) = match task {
+            CompactionTask::Leveled(task) => {
+                let first_key = input_sst_ids
+                    .iter()
+                    .map(|id| snapshot.sstables[id].first_key().key_ref())
+                    .min();
+                let last_key = input_sst_ids
+                    .iter()
+                    .map(|id| snapshot.sstables[id].last_key().key_ref())
+                    .max();
+                (
+                    Some((task.upper_level.unwrap_or(0), task.lower_level)),
+                    first_key
+                        .zip(last_key)
+                        .map(|(first_key, last_key)| (first_key.to_vec(), last_key.to_vec())),
+                )
+            }
+            // Simple leveled compaction rewrites whole levels.
+            CompactionTask::Simple(task) => (
+                Some((task.upper_level.unwrap_or(0), task.lower_level)),
+                None,
+            ),
+            CompactionTask::ForceFullCompaction { .. } => (Some((0, 1)), None),
+            CompactionTask::Manual(task) => (
+                Some((0, task.target_level)),
+                Some((task.first_key.clone(), task.last_key.clone())),
+            ),
+            CompactionTask::Tiered(_) => (None, None),
+        };
+        Self {
+            input_sst_ids,
+            levels,
+            key_range,
+        }
+    }
+
+    /// Two tasks conflict if they share an input SST, or if they touch a common level in overlapping key
+    /// ranges, as the output of one would then overlap the inputs or the output of the other.
+    pub(crate) fn conflicts_with(&self, other: &RunningCompaction) -> bool {
+        if self
+            .input_sst_ids
+            .iter()
+            .any(|id| other.input_sst_ids.contains(id))
+        {
+            return true;
+        }
+        let (Some(levels), Some(other_levels)) = (self.levels, other.levels) else {
+            return true;
+        };
+        if levels.1 < other_levels.0 || other_levels.1 < levels.0 {
+            return false;
+        }
+        match (&self.key_range, &other.key_range) {
+            (Some((first_key, last_key)), Some((other_first_key, other_last_key))) => {
+                first_key <= other_last_key && other_first_key <= last_key
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Removes a compaction task from the running ones once it is done or failed.
+struct RunningCompactionGuard<'a> {
+    storage: &'a LsmStorageInner,
+    input_sst_ids: Vec<usize>,
+}
+
+impl Drop for RunningCompactionGuard<'_> {
+    fn drop(&mut self) {
+        self.storage
+            .running_compactions
+            .lock()
+            .retain(|running| running.input_sst_ids != self.input_sst_ids);
+    }
+}
+
+type SubcompactionJob = Box<dyn FnOnce() + Send>;
+
+/// The threads running the subcompactions of all compaction tasks, so that concurrent tasks run no more than
+/// `max_subcompactions` of them at once.
+pub(crate) struct SubcompactionPool {
+    sender: crossbeam_channel::Sender<SubcompactionJob>,
+}
+
+impl SubcompactionPool {
+    pub(crate) fn new(num_threads: usize) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<SubcompactionJob>();
+        for _ in 0..num_threads {
+            let receiver = receiver.clone();
+            // The threads stop once the pool is dropped.
+            std::thread::spawn(move || {
+                for job in receiver {
+                    // A panicking job drops its result sender, which its task reports.
+                    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+                }
+            });
+        }
+        Self { sender }
+    }
+
+    fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        self.sender
+            .send(Box::new(job))
+            .expect("subcompaction threads stopped");
+    }
+}
+
+/// Removes the files of the output SSTs of a compaction unless they are kept to be installed. Failing to remove
+/// them only leaks them, so errors are logged rather than replacing the one that failed the compaction.
+struct UninstalledSsts<'a> {
+    storage: &'a LsmStorageInner,
+    ssts: Vec<Arc<SsTable>>,
+}
+
+impl<'a> UninstalledSsts<'a> {
+    fn new(storage: &'a LsmStorageInner) -> Self {
+        Self {
+            storage,
+            ssts: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, sst: Arc<SsTable>) {
+        self.ssts.push(sst);
+    }
+
+    fn extend(&mut self, ssts: Vec<Arc<SsTable>>) {
+        self.ssts.extend(ssts);
+    }
+
+    fn keep(mut self) -> Vec<Arc<SsTable>> {
+        std::mem::take(&mut self.ssts)
+    }
+}
+
+impl Drop for UninstalledSsts<'_> {
+    fn drop(&mut self) {
+        for sst in &self.ssts {
+            if let Err(e) = std::fs::remove_file(self.storage.path_of_sst(sst.sst_id())) {
+                eprintln!("failed to remove SST {}: {}", sst.sst_id(), e);
+            }
+        }
+    }
+}
+
+/// Counters of the compactions run since the storage was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Compactions that rewrote their input SSTs.
+    pub compactions: u64,
+    pub input_ssts: u64,
+    pub input_bytes: u64,
+    pub output_ssts: u64,
+    pub output_bytes: u64,
+    /// Compactions applied as trivial moves.
+    pub trivial_moves: u64,
+    pub moved_ssts: u64,
+    pub moved_bytes: u64,
+}
+
+/// A compaction of a key range requested by the user, from L0 down to a target level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManualCompactionTask {
+    pub l0_sst_ids: Vec<usize>,
+    // the SSTs of L1 down to the target level, by level
+    pub level_sst_ids: Vec<(usize, Vec<usize>)>,
+    pub target_level: usize,
+    // user key range of the input SSTs
+    pub first_key: Vec<u8>,
+    pub last_key: Vec<u8>,
+    pub is_target_level_bottom_level: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CompactionTask {
     Leveled(LeveledCompactionTask),
     Tiered(TieredCompactionTask),
@@ -32,6 +256,7 @@ pub enum CompactionTask {
         l0_sstables: Vec<usize>,
         l1_sstables: Vec<usize>,
     },
+    Manual(ManualCompactionTask),
 }
 
 impl CompactionTask {
@@ -41,6 +266,107 @@ impl CompactionTask {
             CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
             CompactionTask::Simple(task) => task.is_lower_level_bottom_level,
             CompactionTask::Tiered(task) => task.bottom_tier_included,
+            CompactionTask::Manual(task) => task.is_target_level_bottom_level,
+        }
+    }
+
+    /// The level the task writes to, 0 being L0, to pick the filter policy of its output. Tiered compaction
+    /// writes a new tier, taken as L1, or as the last level when the bottom tier is included.
+    fn output_level(&self) -> usize {
+        match self {
+            CompactionTask::ForceFullCompaction { .. } => 1,
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            CompactionTask::Tiered(task) if task.bottom_tier_included => usize::MAX,
+            CompactionTask::Tiered(_) => 1,
+            CompactionTask::Manual(task) => task.target_level,
+        }
+    }
+
+    /// The task as a trivial move, if its upper SSTs overlap neither each other nor any SST of the lower level.
+    /// SSTs sharing a user key at their boundaries are taken as overlapping, so that the versions of a key are
+    /// never split across the SSTs of a level.
+    pub(crate) fn trivial_move(&self, snapshot: &LsmStorageState) -> Option<TrivialMove> {
+        let (upper_level, upper_level_sst_ids, lower_level) = match self {
+            CompactionTask::Leveled(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+            ),
+            CompactionTask::Simple(task) => (
+                task.upper_level,
+                &task.upper_level_sst_ids,
+                task.lower_level,
+            ),
+            CompactionTask::Tiered(_)
+            | CompactionTask::ForceFullCompaction { .. }
+            | CompactionTask::Manual(_) => return None,
+        };
+        let disjoint = |x: &SsTable, y: &SsTable| {
+            x.last_key().key_ref() < y.first_key().key_ref()
+                || y.last_key().key_ref() < x.first_key().key_ref()
+        };
+        let mut upper_ssts = upper_level_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].as_ref())
+            .collect::<Vec<_>>();
+        upper_ssts.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+        if upper_ssts
+            .windows(2)
+            .any(|pair| !disjoint(pair[0], pair[1]))
+        {
+            return None;
+        }
+        let overlaps_lower_level = snapshot.levels[lower_level - 1].1.iter().any(|id| {
+            upper_ssts
+                .iter()
+                .any(|upper_sst| !disjoint(upper_sst, &snapshot.sstables[id]))
+        });
+        if overlaps_lower_level {
+            return None;
+        }
+        Some(TrivialMove {
+            upper_level,
+            lower_level,
+            sst_ids: upper_level_sst_ids.clone(),
+        })
+    }
+
+    /// The SSTs the task reads from.
+    pub(crate) fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => l0_sstables.iter().chain(l1_sstables).copied().collect(),
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => upper_level_sst_ids
+                .iter()
+                .chain(lower_level_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => tiers
+                .iter()
+                .flat_map(|(_, tier_sst_ids)| tier_sst_ids)
+                .copied()
+                .collect(),
+            CompactionTask::Manual(ManualCompactionTask {
+                l0_sst_ids,
+                level_sst_ids,
+                ..
+            }) => l0_sst_ids
+                .iter()
+                .chain(level_sst_ids.iter().flat_map(|(_, sst_ids)| sst_ids))
+                .copied()
+                .collect(),
         }
     }
 }
@@ -53,16 +379,21 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
-    pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
+    /// Generates a compaction task that does not touch the SSTs in `compacting`.
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting: &HashSet<usize>,
+    ) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, compacting)
                 .map(CompactionTask::Leveled),
             CompactionController::Simple(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, compacting)
                 .map(CompactionTask::Simple),
             CompactionController::Tiered(ctrl) => ctrl
-                .generate_compaction_task(snapshot)
+                .generate_compaction_task_excluding(snapshot, compacting)
                 .map(CompactionTask::Tiered),
             CompactionController::NoCompaction => unreachable!(),
         }
@@ -84,6 +415,54 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (
+                _,
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                },
+            ) => {
+                let mut snapshot = snapshot.clone();
+                let l0_sstables_set = l0_sstables.iter().collect::<HashSet<_>>();
+                snapshot
+                    .l0_sstables
+                    .retain(|x| !l0_sstables_set.contains(x));
+                assert_eq!(&snapshot.levels[0].1, l1_sstables);
+                snapshot.levels[0].1 = output.to_vec();
+                let files_to_remove = l0_sstables.iter().chain(l1_sstables).copied().collect();
+                (snapshot, files_to_remove)
+            }
+            (_, CompactionTask::Manual(task)) => {
+                let mut snapshot = snapshot.clone();
+                let mut compacted = task
+                    .l0_sst_ids
+                    .iter()
+                    .chain(task.level_sst_ids.iter().flat_map(|(_, sst_ids)| sst_ids))
+                    .copied()
+                    .collect::<HashSet<_>>();
+                let files_to_remove = compacted.iter().copied().collect();
+                snapshot.l0_sstables.retain(|x| !compacted.remove(x));
+                for (level, _) in &task.level_sst_ids {
+                    snapshot.levels[level - 1]
+                        .1
+                        .retain(|x| !compacted.remove(x));
+                }
+                assert!(compacted.is_empty(), "sst mismatched");
+                let target_level = &mut snapshot.levels[task.target_level - 1].1;
+                target_level.extend(output);
+                // The SSTs are not opened yet in recovery, which sorts the levels once they are.
+                if target_level
+                    .iter()
+                    .all(|id| snapshot.sstables.contains_key(id))
+                {
+                    target_level.sort_by(|x, y| {
+                        snapshot.sstables[x]
+                            .first_key()
+                            .cmp(snapshot.sstables[y].first_key())
+                    });
+                }
+                (snapshot, files_to_remove)
+            }
             _ => unreachable!(),
         }
     }
@@ -112,20 +491,103 @@ pub enum CompactionOptions {
 }
 
 impl LsmStorageInner {
+    /// Split the range tombstones of the compaction inputs into the ones to write to the output and the ones
+    /// below the watermark, whose covered versions are invisible to every reader and can be dropped.
+    fn compaction_range_tombstones(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        watermark: u64,
+    ) -> (Vec<RangeTombstone>, Vec<RangeTombstone>) {
+        let input_sst_ids = task.input_sst_ids();
+        let mut output = Vec::new();
+        let mut below_watermark = Vec::new();
+        for id in &input_sst_ids {
+            for tombstone in &snapshot.sstables[id].range_tombstones {
+                if tombstone.ts <= watermark {
+                    below_watermark.push(tombstone.clone());
+                    // At the bottom level, the tombstone can go away with the versions it covers, unless
+                    // an SST outside of the compaction may hold some of them.
+                    let overlaps_other_ssts = snapshot
+                        .sstables
+                        .iter()
+                        .filter(|(id, _)| !input_sst_ids.contains(id))
+                        .any(|(_, table)| {
+                            range_overlap(
+                                Bound::Included(&tombstone.begin),
+                                Bound::Excluded(&tombstone.end),
+                                table.first_key().as_key_slice(),
+                                table.last_key().as_key_slice(),
+                            )
+                        });
+                    if task.compact_to_bottom_level() && !overlaps_other_ssts {
+                        continue;
+                    }
+                }
+                output.push(tombstone.clone());
+            }
+        }
+        (output, below_watermark)
+    }
+
+    /// Add the range tombstones clipped to `[lower, upper)` to an output SST, so that the key ranges of the
+    /// output SSTs do not overlap.
+    fn add_range_tombstones(
+        builder: &mut SsTableBuilder,
+        range_tombstones: &[RangeTombstone],
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) {
+        for tombstone in range_tombstones {
+            if let Some(tombstone) = tombstone.clip(lower, upper) {
+                builder.add_range_tombstone(tombstone);
+            }
+        }
+    }
+
+    /// Record the value of a dropped entry as garbage if it is in a blob file.
+    fn drop_blob_value(
+        value_type: ValueType,
+        value: &[u8],
+        blob_garbage: &mut Vec<ValuePointer>,
+    ) -> Result<()> {
+        if value_type == ValueType::BlobIndex {
+            blob_garbage.push(ValuePointer::decode(value)?);
+        }
+        Ok(())
+    }
+
+    /// Write the entries of `iter` to the output SSTs, and return them with the blob values of the dropped entries.
+    /// On error, the SSTs written so far are removed.
     fn compact_generate_sst_from_iter(
         &self,
         mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
-        compact_to_bottom_level: bool,
-    ) -> Result<Vec<Arc<SsTable>>> {
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<(Vec<Arc<SsTable>>, Vec<ValuePointer>)> {
+        let compact_to_bottom_level = task.compact_to_bottom_level();
+        let output_level = task.output_level();
         let mut builder = None;
-        let mut new_sst = Vec::new();
-        let watermark = self.mvcc().watermark();
+        let mut new_sst = UninstalledSsts::new(self);
+        let mut blob_garbage = Vec::new();
+        let watermark = self.mvcc().gc_watermark();
+        let (range_tombstones, range_tombstones_below_watermark) =
+            self.compaction_range_tombstones(task, snapshot, watermark);
+        // The first key of the current output SST.
+        let mut lower = lower.map(<[u8]>::to_vec);
         let mut last_key = Vec::<u8>::new();
         let mut first_key_below_watermark = false;
         let compaction_filters = self.compaction_filters.lock().clone();
         'outer: while iter.is_valid() {
+            if let Some(upper) = upper {
+                if iter.key().key_ref() >= upper {
+                    break;
+                }
+            }
             if builder.is_none() {
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(output_level));
             }
 
             let same_as_last_key = iter.key().key_ref() == last_key;
@@ -133,10 +595,24 @@ impl LsmStorageInner {
                 first_key_below_watermark = true;
             }
 
+            let covering_ts =
+                max_covering_ts(&range_tombstones_below_watermark, iter.key().key_ref());
+            if covering_ts > Some(iter.key().ts()) {
+                // Deleted by a range tombstone for every reader, and so are the older versions.
+                if !same_as_last_key {
+                    last_key.clear();
+                    last_key.extend(iter.key().key_ref());
+                }
+                Self::drop_blob_value(iter.value_type(), iter.value(), &mut blob_garbage)?;
+                iter.next()?;
+                first_key_below_watermark = false;
+                continue;
+            }
+
             if compact_to_bottom_level
                 && !same_as_last_key
                 && iter.key().ts() <= watermark
-                && iter.value().is_empty()
+                && iter.value_type() == ValueType::Delete
             {
                 last_key.clear();
                 last_key.extend(iter.key().key_ref());
@@ -147,6 +623,7 @@ impl LsmStorageInner {
 
             if iter.key().ts() <= watermark {
                 if same_as_last_key && !first_key_below_watermark {
+                    Self::drop_blob_value(iter.value_type(), iter.value(), &mut blob_garbage)?;
                     iter.next()?;
                     continue;
                 }
@@ -158,6 +635,11 @@ impl LsmStorageInner {
                         match filter {
                             CompactionFilter::Prefix(x) => {
                                 if iter.key().key_ref().starts_with(x) {
+                                    Self::drop_blob_value(
+                                        iter.value_type(),
+                                        iter.value(),
+                                        &mut blob_garbage,
+                                    )?;
                                     iter.next()?;
                                     continue 'outer;
                                 }
@@ -171,18 +653,25 @@ impl LsmStorageInner {
 
             if builder_inner.estimated_size() >= self.options.target_sst_size && !same_as_last_key {
                 let sst_id = self.next_sst_id();
-                let old_builder = builder.take().unwrap();
+                let mut old_builder = builder.take().unwrap();
+                Self::add_range_tombstones(
+                    &mut old_builder,
+                    &range_tombstones,
+                    lower.as_deref(),
+                    Some(iter.key().key_ref()),
+                );
+                lower = Some(iter.key().key_ref().to_vec());
                 let sst = Arc::new(old_builder.build(
                     sst_id,
                     Some(self.block_cache.clone()),
                     self.path_of_sst(sst_id),
                 )?);
                 new_sst.push(sst);
-                builder = Some(SsTableBuilder::new(self.options.block_size));
+                builder = Some(self.new_sst_builder(output_level));
             }
 
             let builder_inner = builder.as_mut().unwrap();
-            builder_inner.add(iter.key(), iter.value());
+            builder_inner.add_with_type(iter.key(), iter.value_type(), iter.value());
 
             if !same_as_last_key {
                 last_key.clear();
@@ -191,7 +680,12 @@ impl LsmStorageInner {
 
             iter.next()?;
         }
-        if let Some(builder) = builder {
+        let mut builder = match builder {
+            Some(builder) => builder,
+            None => self.new_sst_builder(output_level),
+        };
+        Self::add_range_tombstones(&mut builder, &range_tombstones, lower.as_deref(), upper);
+        if !builder.is_empty() {
             let sst_id = self.next_sst_id(); // lock dropped here
             let sst = Arc::new(builder.build(
                 sst_id,
@@ -200,34 +694,139 @@ impl LsmStorageInner {
             )?);
             new_sst.push(sst);
         }
-        Ok(new_sst)
+        Ok((new_sst.keep(), blob_garbage))
+    }
+
+    /// Split a compaction task into at most `max_subcompactions` key ranges, at the first keys of its input SSTs
+    /// so that each range has about as many input SSTs. The versions of a key are always in one range.
+    fn subcompaction_boundaries(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+    ) -> Vec<Vec<u8>> {
+        let mut keys = task
+            .input_sst_ids()
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key().key_ref().to_vec())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        // The smallest key would start an empty range.
+        if !keys.is_empty() {
+            keys.remove(0);
+        }
+        let num_subcompactions = self.options.max_subcompactions.clamp(1, keys.len() + 1);
+        (1..num_subcompactions)
+            .map(|idx| keys[idx * keys.len() / num_subcompactions].clone())
+            .collect()
     }
 
-    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+    /// Run a compaction task, as subcompactions of the key ranges between `boundaries` on the subcompaction
+    /// threads if it is split. The output SSTs are in key order, and returned with the blob values of the dropped
+    /// entries.
+    fn compact(
+        self: &Arc<Self>,
+        task: &CompactionTask,
+        boundaries: Vec<Vec<u8>>,
+    ) -> Result<(Vec<Arc<SsTable>>, Vec<ValuePointer>)> {
         let snapshot = {
             let state = self.state.read();
             state.clone()
         };
+        let subcompaction_pool = match &self.subcompaction_pool {
+            Some(subcompaction_pool) if !boundaries.is_empty() => subcompaction_pool,
+            _ => return self.compact_subrange(task, &snapshot, None, None),
+        };
+        let ranges = std::iter::once(None)
+            .chain(boundaries.iter().cloned().map(Some))
+            .zip(
+                boundaries
+                    .iter()
+                    .cloned()
+                    .map(Some)
+                    .chain(std::iter::once(None)),
+            )
+            .collect::<Vec<_>>();
+        let num_subcompactions = ranges.len();
+        let task = Arc::new(task.clone());
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for (idx, (lower, upper)) in ranges.into_iter().enumerate() {
+            let (this, task, snapshot, tx) =
+                (self.clone(), task.clone(), snapshot.clone(), tx.clone());
+            subcompaction_pool.spawn(move || {
+                let output =
+                    this.compact_subrange(&task, &snapshot, lower.as_deref(), upper.as_deref());
+                tx.send((idx, output)).ok();
+            });
+        }
+        drop(tx);
+        let mut outputs = (0..num_subcompactions).map(|_| None).collect::<Vec<_>>();
+        for (idx, output) in rx.iter() {
+            outputs[idx] = Some(output);
+        }
+        // On error, the SSTs of the other subcompactions are removed, a failed one already removed its own.
+        let mut new_sst = UninstalledSsts::new(self);
+        let mut blob_garbage = Vec::new();
+        let mut result = Ok(());
+        for output in outputs {
+            match output.unwrap_or_else(|| Err(anyhow!("subcompaction panicked"))) {
+                Ok((ssts, garbage)) => {
+                    new_sst.extend(ssts);
+                    blob_garbage.extend(garbage);
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+        result?;
+        Ok((new_sst.keep(), blob_garbage))
+    }
+
+    /// Compact the user keys of a task within `[lower, upper)`.
+    fn compact_subrange(
+        &self,
+        task: &CompactionTask,
+        snapshot: &LsmStorageState,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Result<(Vec<Arc<SsTable>>, Vec<ValuePointer>)> {
+        let table_iter = |id: &usize| {
+            let table = snapshot.sstables[id].clone();
+            match lower {
+                Some(lower) => SsTableIterator::create_and_seek_to_key(
+                    table,
+                    KeySlice::from_slice(lower, TS_RANGE_BEGIN),
+                ),
+                None => SsTableIterator::create_and_seek_to_first(table),
+            }
+            .map(Box::new)
+        };
+        let concat_iter = |ids: &[usize]| {
+            let tables = ids
+                .iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>();
+            match lower {
+                Some(lower) => SstConcatIterator::create_and_seek_to_key(
+                    tables,
+                    KeySlice::from_slice(lower, TS_RANGE_BEGIN),
+                ),
+                None => SstConcatIterator::create_and_seek_to_first(tables),
+            }
+        };
         match task {
             CompactionTask::ForceFullCompaction {
                 l0_sstables,
                 l1_sstables,
             } => {
-                let mut l0_iters = Vec::with_capacity(l0_sstables.len());
-                for id in l0_sstables.iter() {
-                    l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                        snapshot.sstables.get(id).unwrap().clone(),
-                    )?));
-                }
-                let mut l1_iters = Vec::with_capacity(l1_sstables.len());
-                for id in l1_sstables.iter() {
-                    l1_iters.push(snapshot.sstables.get(id).unwrap().clone());
-                }
+                let l0_iters = l0_sstables
+                    .iter()
+                    .map(table_iter)
+                    .collect::<Result<Vec<_>>>()?;
                 let iter = TwoMergeIterator::create(
                     MergeIterator::create(l0_iters),
-                    SstConcatIterator::create_and_seek_to_first(l1_iters)?,
+                    concat_iter(l1_sstables)?,
                 )?;
-                self.compact_generate_sst_from_iter(iter, task.compact_to_bottom_level())
+                self.compact_generate_sst_from_iter(iter, task, snapshot, lower, upper)
             }
             CompactionTask::Simple(SimpleLeveledCompactionTask {
                 upper_level,
@@ -243,59 +842,74 @@ impl LsmStorageInner {
                 lower_level_sst_ids,
                 ..
             }) => match upper_level {
-                Some(_) => {
-                    let mut upper_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let upper_iter = SstConcatIterator::create_and_seek_to_first(upper_ssts)?;
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
-                    self.compact_generate_sst_from_iter(
-                        TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
-                    )
-                }
+                Some(_) => self.compact_generate_sst_from_iter(
+                    TwoMergeIterator::create(
+                        concat_iter(upper_level_sst_ids)?,
+                        concat_iter(lower_level_sst_ids)?,
+                    )?,
+                    task,
+                    snapshot,
+                    lower,
+                    upper,
+                ),
                 None => {
-                    let mut upper_iters = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in upper_level_sst_ids.iter() {
-                        upper_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(
-                            snapshot.sstables.get(id).unwrap().clone(),
-                        )?));
-                    }
-                    let upper_iter = MergeIterator::create(upper_iters);
-                    let mut lower_ssts = Vec::with_capacity(upper_level_sst_ids.len());
-                    for id in lower_level_sst_ids.iter() {
-                        lower_ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    let lower_iter = SstConcatIterator::create_and_seek_to_first(lower_ssts)?;
+                    let upper_iters = upper_level_sst_ids
+                        .iter()
+                        .map(table_iter)
+                        .collect::<Result<Vec<_>>>()?;
                     self.compact_generate_sst_from_iter(
-                        TwoMergeIterator::create(upper_iter, lower_iter)?,
-                        task.compact_to_bottom_level(),
+                        TwoMergeIterator::create(
+                            MergeIterator::create(upper_iters),
+                            concat_iter(lower_level_sst_ids)?,
+                        )?,
+                        task,
+                        snapshot,
+                        lower,
+                        upper,
                     )
                 }
             },
+            CompactionTask::Manual(ManualCompactionTask {
+                l0_sst_ids,
+                level_sst_ids,
+                ..
+            }) => {
+                let l0_iters = l0_sst_ids
+                    .iter()
+                    .map(table_iter)
+                    .collect::<Result<Vec<_>>>()?;
+                let level_iters = level_sst_ids
+                    .iter()
+                    .map(|(_, sst_ids)| concat_iter(sst_ids).map(Box::new))
+                    .collect::<Result<Vec<_>>>()?;
+                self.compact_generate_sst_from_iter(
+                    TwoMergeIterator::create(
+                        MergeIterator::create(l0_iters),
+                        MergeIterator::create(level_iters),
+                    )?,
+                    task,
+                    snapshot,
+                    lower,
+                    upper,
+                )
+            }
             CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
-                let mut iters = Vec::with_capacity(tiers.len());
-                for (_, tier_sst_ids) in tiers {
-                    let mut ssts = Vec::with_capacity(tier_sst_ids.len());
-                    for id in tier_sst_ids.iter() {
-                        ssts.push(snapshot.sstables.get(id).unwrap().clone());
-                    }
-                    iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
-                }
+                let iters = tiers
+                    .iter()
+                    .map(|(_, tier_sst_ids)| concat_iter(tier_sst_ids).map(Box::new))
+                    .collect::<Result<Vec<_>>>()?;
                 self.compact_generate_sst_from_iter(
                     MergeIterator::create(iters),
-                    task.compact_to_bottom_level(),
+                    task,
+                    snapshot,
+                    lower,
+                    upper,
                 )
             }
         }
     }
 
-    pub fn force_full_compaction(&self) -> Result<()> {
+    pub fn force_full_compaction(self: &Arc<Self>) -> Result<()> {
         let CompactionOptions::NoCompaction = self.options.compaction_options else {
             panic!("full compaction can only be called with compaction is not enabled")
         };
@@ -312,9 +926,15 @@ impl LsmStorageInner {
             l1_sstables: l1_sstables.clone(),
         };
 
-        println!("force full compaction: {:?}", compaction_task);
+        let boundaries = self.subcompaction_boundaries(&compaction_task, &snapshot);
+        println!(
+            "force full compaction: {:?}, {} subcompactions",
+            compaction_task,
+            boundaries.len() + 1
+        );
 
-        let sstables = self.compact(&compaction_task)?;
+        let (sstables, blob_garbage) = self.compact(&compaction_task, boundaries)?;
+        self.record_compaction_stats(&snapshot, &compaction_task, &sstables);
         let mut ids = Vec::with_capacity(sstables.len());
 
         {
@@ -341,9 +961,10 @@ impl LsmStorageInner {
             assert!(l0_sstables_map.is_empty());
             *self.state.write() = Arc::new(state);
             self.sync_dir()?;
+            self.value_log.add_garbage(&blob_garbage);
             self.manifest.as_ref().unwrap().add_record(
                 &state_lock,
-                ManifestRecord::Compaction(compaction_task, ids.clone()),
+                ManifestRecord::Compaction(compaction_task, ids.clone(), blob_garbage),
             )?;
         }
         for sst in l0_sstables.iter().chain(l1_sstables.iter()) {
@@ -355,20 +976,219 @@ impl LsmStorageInner {
         Ok(())
     }
 
-    fn trigger_compaction(&self) -> Result<()> {
-        let snapshot = {
-            let state = self.state.read();
-            state.clone()
+    /// Compact the SSTs overlapping `[begin, end]` down to `target_level`, or to the last level if `None`, once
+    /// the running compaction tasks in the way are done. Under tiered compaction, the tiers from the newest
+    /// one overlapping the range are merged into the bottom tier.
+    pub fn compact_range(
+        self: &Arc<Self>,
+        begin: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<()> {
+        let (snapshot, task, _guard) = loop {
+            {
+                let mut running_compactions = self.running_compactions.lock();
+                let snapshot = {
+                    let state = self.state.read();
+                    state.clone()
+                };
+                let Some(task) =
+                    self.manual_compaction_task(&snapshot, begin, end, target_level)?
+                else {
+                    return Ok(());
+                };
+                let running = RunningCompaction::new(&task, &snapshot);
+                if !running_compactions
+                    .iter()
+                    .any(|other| running.conflicts_with(other))
+                {
+                    let guard = RunningCompactionGuard {
+                        storage: self,
+                        input_sst_ids: running.input_sst_ids.clone(),
+                    };
+                    running_compactions.push(running);
+                    break (snapshot, task, guard);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(50));
         };
-        let task = self
-            .compaction_controller
-            .generate_compaction_task(&snapshot);
-        let Some(task) = task else {
-            return Ok(());
+        self.dump_structure();
+        self.run_compaction_task(&snapshot, task)
+    }
+
+    /// The task compacting the SSTs overlapping `[begin, end]`, with the SSTs overlapping them in the levels
+    /// above the target one, so that no newer version of a key is left above an older one.
+    fn manual_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        begin: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<Option<CompactionTask>> {
+        let overlaps = |id: &usize, begin: Bound<&[u8]>, end: Bound<&[u8]>| {
+            let table = &snapshot.sstables[id];
+            range_overlap(
+                begin,
+                end,
+                table.first_key().as_key_slice(),
+                table.last_key().as_key_slice(),
+            )
+        };
+        if let CompactionOptions::Tiered(_) = self.options.compaction_options {
+            if target_level.is_some() {
+                bail!("tiered compaction has no target level");
+            }
+            let Some(first_tier) = snapshot
+                .levels
+                .iter()
+                .position(|(_, tier)| tier.iter().any(|id| overlaps(id, begin, end)))
+            else {
+                return Ok(None);
+            };
+            return Ok(Some(CompactionTask::Tiered(TieredCompactionTask {
+                tiers: snapshot.levels[first_tier..].to_vec(),
+                bottom_tier_included: true,
+            })));
+        }
+
+        let num_levels = snapshot.levels.len();
+        let target_level = target_level.unwrap_or(num_levels);
+        if !(1..=num_levels).contains(&target_level) {
+            bail!("target level {} is not in 1..={}", target_level, num_levels);
+        }
+        let sst_ids = || {
+            snapshot.l0_sstables.iter().chain(
+                snapshot.levels[..target_level]
+                    .iter()
+                    .flat_map(|(_, sst_ids)| sst_ids),
+            )
+        };
+        let mut selected = sst_ids()
+            .filter(|id| overlaps(id, begin, end))
+            .copied()
+            .collect::<HashSet<_>>();
+        if selected.is_empty() {
+            return Ok(None);
+        }
+        let (first_key, last_key) = loop {
+            let first_key = selected
+                .iter()
+                .map(|id| snapshot.sstables[id].first_key().key_ref())
+                .min()
+                .unwrap()
+                .to_vec();
+            let last_key = selected
+                .iter()
+                .map(|id| snapshot.sstables[id].last_key().key_ref())
+                .max()
+                .unwrap()
+                .to_vec();
+            let overlapping = sst_ids()
+                .filter(|id| {
+                    !selected.contains(id)
+                        && overlaps(id, Bound::Included(&first_key), Bound::Included(&last_key))
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            if overlapping.is_empty() {
+                break (first_key, last_key);
+            }
+            selected.extend(overlapping);
+        };
+        let is_target_level_bottom_level = !snapshot.levels[target_level..]
+            .iter()
+            .flat_map(|(_, sst_ids)| sst_ids)
+            .any(|id| overlaps(id, Bound::Included(&first_key), Bound::Included(&last_key)));
+        Ok(Some(CompactionTask::Manual(ManualCompactionTask {
+            l0_sst_ids: snapshot
+                .l0_sstables
+                .iter()
+                .filter(|id| selected.contains(id))
+                .copied()
+                .collect(),
+            level_sst_ids: (1..=target_level)
+                .map(|level| {
+                    let sst_ids = snapshot.levels[level - 1]
+                        .1
+                        .iter()
+                        .filter(|id| selected.contains(id))
+                        .copied()
+                        .collect::<Vec<_>>();
+                    (level, sst_ids)
+                })
+                .filter(|(_, sst_ids)| !sst_ids.is_empty())
+                .collect(),
+            target_level,
+            first_key,
+            last_key,
+            is_target_level_bottom_level,
+        })))
+    }
+
+    /// Pick a compaction task not conflicting with the running ones and run it. Several threads may run this
+    /// at once.
+    pub(crate) fn trigger_compaction(self: &Arc<Self>) -> Result<()> {
+        // The SSTs of the candidate tasks that conflicted with a running one.
+        let mut excluded = HashSet::new();
+        let (snapshot, task, _guard) = loop {
+            let mut running_compactions = self.running_compactions.lock();
+            let snapshot = {
+                let state = self.state.read();
+                state.clone()
+            };
+            let compacting = running_compactions
+                .iter()
+                .flat_map(|running| running.input_sst_ids.iter().copied())
+                .chain(excluded.iter().copied())
+                .collect::<HashSet<_>>();
+            let task = self
+                .compaction_controller
+                .generate_compaction_task(&snapshot, &compacting);
+            let Some(task) = task else {
+                return Ok(());
+            };
+            let running = RunningCompaction::new(&task, &snapshot);
+            if running_compactions
+                .iter()
+                .any(|other| running.conflicts_with(other))
+            {
+                // Ask for the next candidate, unless the controller offers the same SSTs again.
+                let num_excluded = excluded.len();
+                excluded.extend(running.input_sst_ids);
+                if excluded.len() == num_excluded {
+                    return Ok(());
+                }
+                continue;
+            }
+            // Trivial moves are registered as well, so that no other task picks their SSTs until they are applied.
+            let guard = RunningCompactionGuard {
+                storage: self,
+                input_sst_ids: running.input_sst_ids.clone(),
+            };
+            running_compactions.push(running);
+            break (snapshot, task, guard);
         };
         self.dump_structure();
-        println!("running compaction task: {:?}", task);
-        let sstables = self.compact(&task)?;
+        if let Some(trivial_move) = task.trivial_move(&snapshot) {
+            return self.apply_trivial_move(trivial_move);
+        }
+        self.run_compaction_task(&snapshot, task)
+    }
+
+    /// Run a compaction task registered as running, and install its output.
+    fn run_compaction_task(
+        self: &Arc<Self>,
+        snapshot: &LsmStorageState,
+        task: CompactionTask,
+    ) -> Result<()> {
+        let boundaries = self.subcompaction_boundaries(&task, snapshot);
+        println!(
+            "running compaction task: {:?}, {} subcompactions",
+            task,
+            boundaries.len() + 1
+        );
+        let (sstables, blob_garbage) = self.compact(&task, boundaries)?;
+        self.record_compaction_stats(snapshot, &task, &sstables);
         let output = sstables.iter().map(|x| x.sst_id()).collect::<Vec<_>>();
         let ssts_to_remove = {
             let state_lock = self.state_lock.lock();
@@ -392,8 +1212,11 @@ impl LsmStorageInner {
             *state = Arc::new(snapshot);
             drop(state);
             self.sync_dir()?;
-            self.manifest()
-                .add_record(&state_lock, ManifestRecord::Compaction(task, new_sst_ids))?;
+            self.value_log.add_garbage(&blob_garbage);
+            self.manifest().add_record(
+                &state_lock,
+                ManifestRecord::Compaction(task, new_sst_ids, blob_garbage),
+            )?;
             ssts_to_remove
         };
         println!(
@@ -410,6 +1233,47 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Relink the SSTs of a trivial move into the lower level.
+    fn apply_trivial_move(&self, trivial_move: TrivialMove) -> Result<()> {
+        println!("running trivial move: {:?}", trivial_move);
+        {
+            let state_lock = self.state_lock.lock();
+            let snapshot = self.state.read().clone();
+            let moved_bytes = trivial_move
+                .sst_ids
+                .iter()
+                .map(|id| snapshot.sstables[id].table_size())
+                .sum::<u64>();
+            *self.state.write() = Arc::new(trivial_move.apply(&snapshot, false));
+            let mut stats = self.compaction_stats.lock();
+            stats.trivial_moves += 1;
+            stats.moved_ssts += trivial_move.sst_ids.len() as u64;
+            stats.moved_bytes += moved_bytes;
+            drop(stats);
+            self.manifest()
+                .add_record(&state_lock, ManifestRecord::TrivialMove(trivial_move))?;
+        }
+        Ok(())
+    }
+
+    fn record_compaction_stats(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        output: &[Arc<SsTable>],
+    ) {
+        let input_sst_ids = task.input_sst_ids();
+        let mut stats = self.compaction_stats.lock();
+        stats.compactions += 1;
+        stats.input_ssts += input_sst_ids.len() as u64;
+        stats.input_bytes += input_sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum::<u64>();
+        stats.output_ssts += output.len() as u64;
+        stats.output_bytes += output.iter().map(|sst| sst.table_size()).sum::<u64>();
+    }
+
     pub(crate) fn spawn_compaction_thread(
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
@@ -419,16 +1283,42 @@ impl LsmStorageInner {
         | CompactionOptions::Tiered(_) = self.options.compaction_options
         {
             let this = self.clone();
+            let num_threads = self.options.num_compaction_threads.max(1);
+            // The blob garbage collector runs next to the compaction workers.
+            let gc_ticker = if self.options.value_log_threshold.is_some() {
+                crossbeam_channel::tick(Duration::from_millis(50))
+            } else {
+                crossbeam_channel::never()
+            };
             let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
+                // The workers stop once the sender is dropped.
+                let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+                std::thread::scope(|scope| {
+                    for _ in 0..num_threads {
+                        let this = &this;
+                        let stop_rx = stop_rx.clone();
+                        scope.spawn(move || {
+                            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                            loop {
+                                crossbeam_channel::select! {
+                                    recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
+                                        eprintln!("compaction failed: {}", e);
+                                    },
+                                    recv(stop_rx) -> _ => return
+                                }
+                            }
+                        });
                     }
-                }
+                    loop {
+                        crossbeam_channel::select! {
+                            recv(gc_ticker) -> _ => if let Err(e) = this.gc_value_log() {
+                                eprintln!("value log gc failed: {}", e);
+                            },
+                            recv(rx) -> _ => break
+                        }
+                    }
+                    drop(stop_tx);
+                });
             });
             return Ok(Some(handle));
         }
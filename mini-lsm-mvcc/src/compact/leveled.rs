@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -64,19 +64,23 @@ impl LeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task that does not touch the SSTs in `compacting`, the inputs of the compaction
+    /// tasks being run.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting: &HashSet<usize>,
     ) -> Option<LeveledCompactionTask> {
         // step 1: compute target level size
         let mut target_level_size = (0..self.options.max_levels).map(|_| 0).collect::<Vec<_>>(); // exclude level 0
         let mut real_level_size = Vec::with_capacity(self.options.max_levels);
         let mut base_level = self.options.max_levels;
         for i in 0..self.options.max_levels {
-            real_level_size.push(
-                snapshot.levels[i]
-                    .1
-                    .iter()
-                    .map(|x| snapshot.sstables.get(x).unwrap().table_size())
-                    .sum::<u64>() as usize,
-            );
+            real_level_size.push(snapshot.table_size_of(&snapshot.levels[i].1) as usize);
         }
         let base_level_size_bytes = self.options.base_level_size_mb * 1024 * 1024;
 
@@ -95,19 +99,25 @@ impl LeveledCompactionController {
         }
 
         // Flush L0 SST is the top priority
-        if snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
-            println!("flush L0 SST to base level {}", base_level);
-            return Some(LeveledCompactionTask {
-                upper_level: None,
-                upper_level_sst_ids: snapshot.l0_sstables.clone(),
-                lower_level: base_level,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &snapshot.l0_sstables,
-                    base_level,
-                ),
-                is_lower_level_bottom_level: base_level == self.options.max_levels,
-            });
+        let l0_sstables = snapshot
+            .l0_sstables
+            .iter()
+            .filter(|id| !compacting.contains(id))
+            .copied()
+            .collect::<Vec<_>>();
+        if l0_sstables.len() >= self.options.level0_file_num_compaction_trigger {
+            let lower_level_sst_ids =
+                self.find_overlapping_ssts(snapshot, &l0_sstables, base_level);
+            if !lower_level_sst_ids.iter().any(|id| compacting.contains(id)) {
+                println!("flush L0 SST to base level {}", base_level);
+                return Some(LeveledCompactionTask {
+                    upper_level: None,
+                    upper_level_sst_ids: l0_sstables,
+                    lower_level: base_level,
+                    lower_level_sst_ids,
+                    is_lower_level_bottom_level: base_level == self.options.max_levels,
+                });
+            }
         }
 
         let mut priorities = Vec::with_capacity(self.options.max_levels);
@@ -119,40 +129,45 @@ impl LeveledCompactionController {
         }
         priorities.sort_by(|a, b| a.partial_cmp(b).unwrap().reverse());
 
-        let priority = priorities.first();
-        if let Some((_, level)) = priority {
-            println!(
-                "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
-                target_level_size
-                    .iter()
-                    .map(|x| format!("{:.3}MB", *x as f64 / 1024.0 / 1024.0))
-                    .collect::<Vec<_>>(),
-                real_level_size
-                    .iter()
-                    .map(|x| format!("{:.3}MB", *x as f64 / 1024.0 / 1024.0))
-                    .collect::<Vec<_>>(),
-                base_level,
-            );
-
-            let level = *level;
-            let selected_sst = snapshot.levels[level - 1].1.iter().min().copied().unwrap(); // select the oldest sst to compact
-            println!(
-                "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
-                priorities
-            );
-            return Some(LeveledCompactionTask {
-                upper_level: Some(level),
-                upper_level_sst_ids: vec![selected_sst],
-                lower_level: level + 1,
-                lower_level_sst_ids: self.find_overlapping_ssts(
-                    snapshot,
-                    &[selected_sst],
-                    level + 1,
-                ),
-                is_lower_level_bottom_level: level + 1 == self.options.max_levels,
-            });
-        }
-        None
+        // select the oldest sst to compact, among the ones whose task would not touch an SST being compacted
+        let (level, selected_sst, lower_level_sst_ids) =
+            priorities.iter().find_map(|&(_, level)| {
+                let mut candidates = snapshot.levels[level - 1].1.clone();
+                candidates.sort();
+                candidates
+                    .into_iter()
+                    .filter(|id| !compacting.contains(id))
+                    .find_map(|selected_sst| {
+                        let lower_level_sst_ids =
+                            self.find_overlapping_ssts(snapshot, &[selected_sst], level + 1);
+                        let is_compacting =
+                            lower_level_sst_ids.iter().any(|id| compacting.contains(id));
+                        (!is_compacting).then_some((level, selected_sst, lower_level_sst_ids))
+                    })
+            })?;
+        println!(
+            "target level sizes: {:?}, real level sizes: {:?}, base_level: {}",
+            target_level_size
+                .iter()
+                .map(|x| format!("{:.3}MB", *x as f64 / 1024.0 / 1024.0))
+                .collect::<Vec<_>>(),
+            real_level_size
+                .iter()
+                .map(|x| format!("{:.3}MB", *x as f64 / 1024.0 / 1024.0))
+                .collect::<Vec<_>>(),
+            base_level,
+        );
+        println!(
+            "compaction triggered by priority: {level} out of {:?}, select {selected_sst} for compaction",
+            priorities
+        );
+        Some(LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![selected_sst],
+            lower_level: level + 1,
+            lower_level_sst_ids,
+            is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+        })
     }
 
     pub fn apply_compaction_result(
@@ -11,7 +11,7 @@ pub struct SimpleLeveledCompactionOptions {
     pub max_levels: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SimpleLeveledCompactionTask {
     // if upper_level is `None`, then it is L0 compaction
     pub upper_level: Option<usize>,
@@ -36,11 +36,21 @@ impl SimpleLeveledCompactionController {
     pub fn generate_compaction_task(
         &self,
         snapshot: &LsmStorageState,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task that does not touch the SSTs in `compacting`, the inputs of the compaction
+    /// tasks being run. Tasks take whole levels, so a level with an SST being compacted is skipped.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting: &HashSet<usize>,
     ) -> Option<SimpleLeveledCompactionTask> {
         let mut level_sizes = Vec::new();
-        level_sizes.push(snapshot.l0_sstables.len());
+        level_sizes.push(snapshot.table_size_of(&snapshot.l0_sstables));
         for (_, files) in &snapshot.levels {
-            level_sizes.push(files.len());
+            level_sizes.push(snapshot.table_size_of(files));
         }
 
         for i in 0..self.options.max_levels {
@@ -53,11 +63,7 @@ impl SimpleLeveledCompactionController {
             let lower_level = i + 1;
             let size_ratio = level_sizes[lower_level] as f64 / level_sizes[i] as f64;
             if size_ratio < self.options.size_ratio_percent as f64 / 100.0 {
-                println!(
-                    "compaction triggered at level {} and {} with size ratio {}",
-                    i, lower_level, size_ratio
-                );
-                return Some(SimpleLeveledCompactionTask {
+                let task = SimpleLeveledCompactionTask {
                     upper_level: if i == 0 { None } else { Some(i) },
                     upper_level_sst_ids: if i == 0 {
                         snapshot.l0_sstables.clone()
@@ -67,7 +73,20 @@ impl SimpleLeveledCompactionController {
                     lower_level,
                     lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
                     is_lower_level_bottom_level: lower_level == self.options.max_levels,
-                });
+                };
+                if task
+                    .upper_level_sst_ids
+                    .iter()
+                    .chain(&task.lower_level_sst_ids)
+                    .any(|id| compacting.contains(id))
+                {
+                    continue;
+                }
+                println!(
+                    "compaction triggered at level {} and {} with size ratio {}",
+                    i, lower_level, size_ratio
+                );
+                return Some(task);
             }
         }
         None
@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TieredCompactionTask {
     pub tiers: Vec<(usize, Vec<usize>)>,
     pub bottom_tier_included: bool,
@@ -31,6 +31,23 @@ impl TieredCompactionController {
         &self,
         snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
+        self.generate_compaction_task_excluding(snapshot, &HashSet::new())
+    }
+
+    /// Generates a compaction task if no SST is in `compacting`, the inputs of the compaction tasks being run.
+    /// Tasks start from the newest tiers and may renumber the others, so only one runs at a time.
+    pub fn generate_compaction_task_excluding(
+        &self,
+        snapshot: &LsmStorageState,
+        compacting: &HashSet<usize>,
+    ) -> Option<TieredCompactionTask> {
+        if snapshot
+            .levels
+            .iter()
+            .any(|(_, tier)| tier.iter().any(|id| compacting.contains(id)))
+        {
+            return None;
+        }
         assert!(
             snapshot.l0_sstables.is_empty(),
             "should not add l0 ssts in tiered compaction"
@@ -41,10 +58,11 @@ impl TieredCompactionController {
         // compaction triggered by space amplification ratio
         let mut size = 0;
         for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
+            size += snapshot.table_size_of(&snapshot.levels[id].1);
         }
-        let space_amp_ratio =
-            (size as f64) / (snapshot.levels.last().unwrap().1.len() as f64) * 100.0;
+        let space_amp_ratio = (size as f64)
+            / (snapshot.table_size_of(&snapshot.levels.last().unwrap().1) as f64)
+            * 100.0;
         if space_amp_ratio >= self.options.max_size_amplification_percent as f64 {
             println!(
                 "compaction triggered by space amplification ratio: {}",
@@ -59,8 +77,8 @@ impl TieredCompactionController {
         // compaction triggered by size ratio
         let mut size = 0;
         for id in 0..(snapshot.levels.len() - 1) {
-            size += snapshot.levels[id].1.len();
-            let next_level_size = snapshot.levels[id + 1].1.len();
+            size += snapshot.table_size_of(&snapshot.levels[id].1);
+            let next_level_size = snapshot.table_size_of(&snapshot.levels[id + 1].1);
             let current_size_ratio = size as f64 / next_level_size as f64;
             if current_size_ratio >= size_ratio_trigger && id + 2 >= self.options.min_merge_width {
                 println!(
@@ -121,9 +139,11 @@ impl TieredCompactionController {
                 levels.push((*tier_id, files.clone()));
             }
             if tier_to_remove.is_empty() && !new_tier_added {
-                // add the compacted tier to the LSM tree
+                // add the compacted tier to the LSM tree, unless everything was deleted
                 new_tier_added = true;
-                levels.push((output[0], output.to_vec()));
+                if let Some(&tier_id) = output.first() {
+                    levels.push((tier_id, output.to_vec()));
+                }
             }
         }
         if !tier_to_remove.is_empty() {
@@ -2,14 +2,30 @@ pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+use anyhow::bail;
+
+use crate::key::ValueType;
+
+/// The direction an iterator that can move both ways is currently moving in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Forward,
+    Backward,
+}
+
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + Copy
     where
         Self: 'a;
 
     /// Get the current value.
     fn value(&self) -> &[u8];
 
+    /// Get the type of the current value. Iterators that never see deletions or blob pointers yield `Put`.
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
+    }
+
     /// Get the current key.
     fn key(&self) -> Self::KeyType<'_>;
 
@@ -19,6 +35,32 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Move to the previous position. Once an iterator runs past either end, it must be repositioned by one
+    /// of the seeks before moving the other way. Iterators that only move forward return an error.
+    fn prev(&mut self) -> anyhow::Result<()> {
+        bail!("backward iteration is not supported")
+    }
+
+    /// Move to the first position whose key is >= `key`.
+    fn seek(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        bail!("seeking is not supported")
+    }
+
+    /// Move to the first position.
+    fn seek_to_first(&mut self) -> anyhow::Result<()> {
+        bail!("seeking is not supported")
+    }
+
+    /// Move to the last position.
+    fn seek_to_last(&mut self) -> anyhow::Result<()> {
+        bail!("seeking is not supported")
+    }
+
+    /// Move to the last position whose key is <= `key`.
+    fn seek_for_prev(&mut self, _key: Self::KeyType<'_>) -> anyhow::Result<()> {
+        bail!("seeking is not supported")
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
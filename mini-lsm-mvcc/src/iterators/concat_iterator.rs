@@ -3,8 +3,8 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use crate::{
-    key::KeySlice,
-    table::{SsTable, SsTableIterator},
+    key::{KeySlice, ValueType},
+    table::{BlockReadOptions, SsTable, SsTableIterator},
 };
 
 use super::StorageIterator;
@@ -15,6 +15,7 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    options: BlockReadOptions,
 }
 
 impl SstConcatIterator {
@@ -24,55 +25,105 @@ impl SstConcatIterator {
         }
         if !sstables.is_empty() {
             for i in 0..(sstables.len() - 1) {
-                assert!(sstables[i].last_key() < sstables[i + 1].first_key());
+                // A range tombstone ends right before the smallest key of its end, which may also start the
+                // next SST.
+                assert!(sstables[i].last_key() <= sstables[i + 1].first_key());
             }
         }
     }
 
     pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_options(sstables, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the first key-value pair.
+    pub fn create_and_seek_to_first_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
         Self::check_sst_valid(&sstables);
         if sstables.is_empty() {
             return Ok(Self {
                 current: None,
                 next_sst_idx: 0,
                 sstables,
+                options,
             });
         }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_first(
+            current: Some(SsTableIterator::create_and_seek_to_first_with_options(
                 sstables[0].clone(),
+                options,
             )?),
             next_sst_idx: 1,
             sstables,
+            options,
         };
         iter.move_until_valid()?;
         Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(sstables, key, false, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key_with_options(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice<'_>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(sstables, key, false, options)
+    }
+
+    /// Seek to the first key-value pair which >= `key`, using the hash index of the data block when
+    /// available. Intended for point lookups.
+    pub fn create_and_seek_to_key_by_hash(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        Self::create_and_seek_to_key_inner(sstables, key, true, options)
+    }
+
+    fn create_and_seek_to_key_inner(
+        sstables: Vec<Arc<SsTable>>,
+        key: KeySlice,
+        by_hash: bool,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
         Self::check_sst_valid(&sstables);
-        let idx: usize = sstables
-            .partition_point(|table| table.first_key().as_key_slice() <= key)
-            .saturating_sub(1);
-        if idx >= sstables.len() {
-            return Ok(Self {
-                current: None,
-                next_sst_idx: sstables.len(),
-                sstables,
-            });
-        }
         let mut iter = Self {
-            current: Some(SsTableIterator::create_and_seek_to_key(
-                sstables[idx].clone(),
-                key,
-            )?),
-            next_sst_idx: idx + 1,
+            current: None,
+            next_sst_idx: 0,
             sstables,
+            options,
         };
-        iter.move_until_valid()?;
+        iter.seek_to_key_inner(key, by_hash)?;
         Ok(iter)
     }
 
+    /// Binary-search the SST that may contain `key`, and seek to the first key-value pair which >= `key` from it.
+    fn seek_to_key_inner(&mut self, key: KeySlice, by_hash: bool) -> Result<()> {
+        let idx: usize = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key)
+            .saturating_sub(1);
+        if idx >= self.sstables.len() {
+            self.current = None;
+            self.next_sst_idx = self.sstables.len();
+            return Ok(());
+        }
+        let table = self.sstables[idx].clone();
+        self.current = Some(if by_hash {
+            SsTableIterator::create_and_seek_to_key_by_hash_with_options(table, key, self.options)?
+        } else {
+            SsTableIterator::create_and_seek_to_key_with_options(table, key, self.options)?
+        });
+        self.next_sst_idx = idx + 1;
+        self.move_until_valid()
+    }
+
     fn move_until_valid(&mut self) -> Result<()> {
         while let Some(iter) = self.current.as_mut() {
             if iter.is_valid() {
@@ -81,14 +132,35 @@ impl SstConcatIterator {
             if self.next_sst_idx >= self.sstables.len() {
                 self.current = None;
             } else {
-                self.current = Some(SsTableIterator::create_and_seek_to_first(
+                self.current = Some(SsTableIterator::create_and_seek_to_first_with_options(
                     self.sstables[self.next_sst_idx].clone(),
+                    self.options,
                 )?);
                 self.next_sst_idx += 1;
             }
         }
         Ok(())
     }
+
+    /// Move back through the SSTs before the current one until the iterator is valid.
+    fn move_back_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_ref() {
+            if iter.is_valid() {
+                break;
+            }
+            // The current SST is the one right before `next_sst_idx`.
+            if self.next_sst_idx <= 1 {
+                self.current = None;
+            } else {
+                self.next_sst_idx -= 1;
+                self.current = Some(SsTableIterator::create_and_seek_to_last_with_options(
+                    self.sstables[self.next_sst_idx - 1].clone(),
+                    self.options,
+                )?);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl StorageIterator for SstConcatIterator {
@@ -102,6 +174,10 @@ impl StorageIterator for SstConcatIterator {
         self.current.as_ref().unwrap().value()
     }
 
+    fn value_type(&self) -> ValueType {
+        self.current.as_ref().unwrap().value_type()
+    }
+
     fn is_valid(&self) -> bool {
         if let Some(current) = &self.current {
             assert!(current.is_valid());
@@ -117,6 +193,62 @@ impl StorageIterator for SstConcatIterator {
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().prev()?;
+        self.move_back_until_valid()?;
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key_inner(key, false)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        if self.sstables.is_empty() {
+            self.current = None;
+            self.next_sst_idx = 0;
+            return Ok(());
+        }
+        self.current = Some(SsTableIterator::create_and_seek_to_first_with_options(
+            self.sstables[0].clone(),
+            self.options,
+        )?);
+        self.next_sst_idx = 1;
+        self.move_until_valid()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        if self.sstables.is_empty() {
+            self.current = None;
+            self.next_sst_idx = 0;
+            return Ok(());
+        }
+        self.current = Some(SsTableIterator::create_and_seek_to_last_with_options(
+            self.sstables[self.sstables.len() - 1].clone(),
+            self.options,
+        )?);
+        self.next_sst_idx = self.sstables.len();
+        self.move_back_until_valid()
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let idx = self
+            .sstables
+            .partition_point(|table| table.first_key().as_key_slice() <= key);
+        if idx == 0 {
+            self.current = None;
+            self.next_sst_idx = 0;
+            return Ok(());
+        }
+        self.current = Some(SsTableIterator::create_and_seek_for_prev_with_options(
+            self.sstables[idx - 1].clone(),
+            key,
+            self.options,
+        )?);
+        self.next_sst_idx = idx;
+        self.move_back_until_valid()
+    }
+
     fn num_active_iterators(&self) -> usize {
         1
     }
@@ -4,11 +4,12 @@ use std::collections::BinaryHeap;
 
 use anyhow::Result;
 
-use crate::key::KeySlice;
+use crate::key::{KeySlice, ValueType};
 
-use super::StorageIterator;
+use super::{Direction, StorageIterator};
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator with its index, ordered so that the heap top is the next one to yield in the direction.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub Direction);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -21,12 +22,20 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     #[allow(clippy::non_canonical_partial_ord_impl)]
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(&other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
+        // Iterators with a smaller index win ties in both directions.
+        match self.2 {
+            Direction::Forward => match self.1.key().cmp(&other.1.key()) {
+                cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
+                cmp::Ordering::Less => Some(cmp::Ordering::Less),
+                cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
+            }
+            .map(|x| x.reverse()),
+            Direction::Backward => match self.1.key().cmp(&other.1.key()) {
+                cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
+                cmp::Ordering::Less => Some(cmp::Ordering::Less),
+                cmp::Ordering::Equal => other.0.partial_cmp(&self.0),
+            },
         }
-        .map(|x| x.reverse())
     }
 }
 
@@ -40,40 +49,68 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
 /// iterators, prefer the one with smaller index.
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
+    /// `None` if every iterator is exhausted.
     current: Option<HeapWrapper<I>>,
+    /// Iterators that ran out in the current direction, kept to be repositioned when it changes.
+    exhausted: Vec<HeapWrapper<I>>,
+    direction: Direction,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+            direction: Direction::Forward,
+        };
+        iter.rebuild(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, Direction::Forward))
+                .collect(),
+        );
+        iter
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+    /// Take out every iterator, for them to be repositioned.
+    fn take_all(&mut self) -> Vec<HeapWrapper<I>> {
+        let mut iters = std::mem::take(&mut self.exhausted);
+        iters.extend(self.current.take());
+        iters.extend(std::mem::take(&mut self.iters));
+        iters
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Order the iterators for the current direction and select the current one.
+    fn rebuild(&mut self, iters: Vec<HeapWrapper<I>>) {
+        for mut iter in iters {
+            iter.2 = self.direction;
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
+        self.current = self.iters.pop();
+    }
 
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
+    /// Apply `f` to every iterator, then rebuild for `direction`. Every iterator is kept on an error.
+    fn reposition(
+        &mut self,
+        direction: Direction,
+        mut f: impl FnMut(&mut I) -> Result<()>,
+    ) -> Result<()> {
+        let mut iters = self.take_all();
+        let mut result = Ok(());
+        for iter in &mut iters {
+            if result.is_ok() {
+                result = f(&mut iter.1);
+            }
         }
+        self.direction = direction;
+        self.rebuild(iters);
+        result
     }
 }
 
@@ -90,6 +127,10 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         self.current.as_ref().unwrap().1.value()
     }
 
+    fn value_type(&self) -> ValueType {
+        self.current.as_ref().unwrap().1.value_type()
+    }
+
     fn is_valid(&self) -> bool {
         self.current
             .as_ref()
@@ -98,6 +139,9 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.direction == Direction::Backward {
+            return self.switch_direction(Direction::Forward);
+        }
         let current = self.current.as_mut().unwrap();
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
@@ -114,7 +158,7 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
@@ -125,9 +169,8 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
-            if let Some(iter) = self.iters.pop() {
-                *current = iter;
-            }
+            let current = std::mem::replace(&mut self.current, self.iters.pop()).unwrap();
+            self.exhausted.push(current);
             return Ok(());
         }
 
@@ -141,6 +184,63 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            return self.switch_direction(Direction::Backward);
+        }
+        let current = self.current.as_mut().unwrap();
+        // Move the iterators that lost the tie on the current key past it as well.
+        while let Some(mut inner_iter) = self.iters.peek_mut() {
+            debug_assert!(
+                inner_iter.1.key() <= current.1.key(),
+                "heap invariant violated"
+            );
+            if inner_iter.1.key() == current.1.key() {
+                if let e @ Err(_) = inner_iter.1.prev() {
+                    self.exhausted.push(PeekMut::pop(inner_iter));
+                    return e;
+                }
+                if !inner_iter.1.is_valid() {
+                    self.exhausted.push(PeekMut::pop(inner_iter));
+                }
+            } else {
+                break;
+            }
+        }
+
+        current.1.prev()?;
+
+        if !current.1.is_valid() {
+            let current = std::mem::replace(&mut self.current, self.iters.pop()).unwrap();
+            self.exhausted.push(current);
+            return Ok(());
+        }
+
+        if let Some(mut inner_iter) = self.iters.peek_mut() {
+            if *current < *inner_iter {
+                std::mem::swap(&mut *inner_iter, current);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.reposition(Direction::Forward, |iter| iter.seek(key))
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.reposition(Direction::Forward, |iter| iter.seek_to_first())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.reposition(Direction::Backward, |iter| iter.seek_to_last())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.reposition(Direction::Backward, |iter| iter.seek_for_prev(key))
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.iters
             .iter()
@@ -153,3 +253,40 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
                 .unwrap_or(0)
     }
 }
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Turn around on the current key: every iterator, the current one included, is moved to the nearest key
+    /// past it in `direction`. Iterators exhausted in the old direction start over from the other end.
+    fn switch_direction(&mut self, direction: Direction) -> Result<()> {
+        let Some(current) = self.current.as_ref() else {
+            self.direction = direction;
+            return Ok(());
+        };
+        let key = current.1.key().to_key_vec();
+        let from = self.direction;
+        self.reposition(direction, |iter| {
+            Self::move_past(iter, key.as_key_slice(), from, direction)
+        })
+    }
+
+    fn move_past(iter: &mut I, key: KeySlice, from: Direction, to: Direction) -> Result<()> {
+        match (iter.is_valid(), from) {
+            (false, Direction::Forward) => iter.seek_to_last()?,
+            (false, Direction::Backward) => iter.seek_to_first()?,
+            _ => {}
+        }
+        match to {
+            Direction::Forward => {
+                while iter.is_valid() && iter.key() <= key {
+                    iter.next()?;
+                }
+            }
+            Direction::Backward => {
+                while iter.is_valid() && iter.key() >= key {
+                    iter.prev()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
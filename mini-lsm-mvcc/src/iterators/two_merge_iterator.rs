@@ -1,6 +1,7 @@
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{Direction, StorageIterator};
+use crate::key::ValueType;
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
@@ -8,6 +9,7 @@ pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    direction: Direction,
 }
 
 impl<
@@ -15,19 +17,25 @@ impl<
         B: 'static + for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
     > TwoMergeIterator<A, B>
 {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, direction: Direction) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        match direction {
+            Direction::Forward => a.key() < b.key(),
+            Direction::Backward => a.key() > b.key(),
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() && self.b.is_valid() && self.b.key() == self.a.key() {
-            self.b.next()?;
+            match self.direction {
+                Direction::Forward => self.b.next()?,
+                Direction::Backward => self.b.prev()?,
+            }
         }
         Ok(())
     }
@@ -37,11 +45,55 @@ impl<
             choose_a: false,
             a,
             b,
+            direction: Direction::Forward,
         };
-        iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.select()?;
         Ok(iter)
     }
+
+    /// Skip the entry of B shadowed by A and select the iterator to yield from.
+    fn select(&mut self) -> Result<()> {
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.direction);
+        Ok(())
+    }
+
+    /// Turn around on the current key: the iterator not on it is moved to the nearest key past it in
+    /// `direction`, starting over from the other end if it was exhausted, and the current one steps once.
+    fn switch_direction(&mut self, direction: Direction) -> Result<()> {
+        if self.choose_a {
+            Self::move_past(&mut self.b, &self.a, self.direction, direction)?;
+        } else {
+            Self::move_past(&mut self.a, &self.b, self.direction, direction)?;
+        }
+        self.direction = direction;
+        Ok(())
+    }
+
+    fn move_past<O, C>(other: &mut O, current: &C, from: Direction, to: Direction) -> Result<()>
+    where
+        O: for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
+        C: for<'a> StorageIterator<KeyType<'a> = A::KeyType<'a>>,
+    {
+        match (other.is_valid(), from) {
+            (false, Direction::Forward) => other.seek_to_last()?,
+            (false, Direction::Backward) => other.seek_to_first()?,
+            _ => {}
+        }
+        match to {
+            Direction::Forward => {
+                while other.is_valid() && other.key() <= current.key() {
+                    other.next()?;
+                }
+            }
+            Direction::Backward => {
+                while other.is_valid() && other.key() >= current.key() {
+                    other.prev()?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<
@@ -69,6 +121,14 @@ impl<
         }
     }
 
+    fn value_type(&self) -> ValueType {
+        if self.choose_a {
+            self.a.value_type()
+        } else {
+            self.b.value_type()
+        }
+    }
+
     fn is_valid(&self) -> bool {
         if self.choose_a {
             self.a.is_valid()
@@ -78,14 +138,55 @@ impl<
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.direction == Direction::Backward {
+            self.switch_direction(Direction::Forward)?;
+        }
         if self.choose_a {
             self.a.next()?;
         } else {
             self.b.next()?;
         }
-        self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
-        Ok(())
+        self.select()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            self.switch_direction(Direction::Backward)?;
+        }
+        if self.choose_a {
+            self.a.prev()?;
+        } else {
+            self.b.prev()?;
+        }
+        self.select()
+    }
+
+    fn seek(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.direction = Direction::Forward;
+        self.select()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.a.seek_to_first()?;
+        self.b.seek_to_first()?;
+        self.direction = Direction::Forward;
+        self.select()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.a.seek_to_last()?;
+        self.b.seek_to_last()?;
+        self.direction = Direction::Backward;
+        self.select()
+    }
+
+    fn seek_for_prev(&mut self, key: A::KeyType<'_>) -> Result<()> {
+        self.a.seek_for_prev(key)?;
+        self.b.seek_for_prev(key)?;
+        self.direction = Direction::Backward;
+        self.select()
     }
 
     fn num_active_iterators(&self) -> usize {
@@ -19,6 +19,51 @@ pub const TS_MIN: u64 = std::u64::MIN;
 pub const TS_RANGE_BEGIN: u64 = std::u64::MAX;
 pub const TS_RANGE_END: u64 = std::u64::MIN;
 
+/// The kind of entry stored along with a key in memtables, WALs and SSTs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ValueType {
+    /// The value is stored inline. It may be empty.
+    #[default]
+    Put,
+    /// The value lives in a blob file, and the stored value is a pointer to it.
+    BlobIndex,
+    /// The key is deleted, and the stored value is empty.
+    Delete,
+}
+
+impl ValueType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            ValueType::Put => 0,
+            ValueType::BlobIndex => 1,
+            ValueType::Delete => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(tag: u8) -> Self {
+        Self::try_from_u8(tag).unwrap_or_else(|| panic!("unknown value type {}", tag))
+    }
+
+    /// The value type of an entry written without one, as in the original formats, where an empty value marks
+    /// a deletion.
+    pub(crate) fn of_untyped_value(value: &[u8]) -> Self {
+        if value.is_empty() {
+            ValueType::Delete
+        } else {
+            ValueType::Put
+        }
+    }
+
+    pub(crate) fn try_from_u8(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(ValueType::Put),
+            1 => Some(ValueType::BlobIndex),
+            2 => Some(ValueType::Delete),
+            _ => None,
+        }
+    }
+}
+
 impl<T: AsRef<[u8]>> Key<T> {
     pub fn into_inner(self) -> T {
         self.0
@@ -56,6 +101,11 @@ impl Key<Vec<u8>> {
         self.0.clear()
     }
 
+    /// Shortens the key to the first `len` bytes, keeping the ts.
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
+
     /// Append a slice to the end of the key
     pub fn append(&mut self, data: &[u8]) {
         self.0.extend(data)
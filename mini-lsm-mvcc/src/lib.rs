@@ -3,12 +3,17 @@ pub mod compact;
 pub mod debug;
 pub mod iterators;
 pub mod key;
+mod lookup;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
 pub mod mvcc;
+pub mod prefix_extractor;
+pub mod range_tombstone;
 pub mod table;
+pub mod value_log;
+mod varint;
 pub mod wal;
 
 #[cfg(test)]
@@ -0,0 +1,298 @@
+//! Point lookups that read the versions of keys directly from the memtables and SSTs, without building the
+//! merge iterators of a scan.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::block::{Block, BlockIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{self, KeySlice, ValueType};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::mem_table::MemTable;
+use crate::range_tombstone::{max_covering_ts, RangeTombstone};
+use crate::table::{BlockReadOptions, SsTable, SsTableIterator};
+use crate::value_log::ValuePointer;
+
+/// The ts, value type and value of a version of a key.
+type Version = (u64, ValueType, Bytes);
+
+/// A key being looked up, and the newest version of it visible at the read ts once found.
+struct KeyLookup<'a> {
+    key: &'a [u8],
+    fingerprint: u32,
+    version: Option<Version>,
+}
+
+/// Whether `table` may have a version of `key`, by its key range and filter.
+fn table_may_contain(table: &SsTable, key: &[u8], fingerprint: u32) -> bool {
+    table.first_key().key_ref() <= key
+        && key <= table.last_key().key_ref()
+        && table
+            .filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(fingerprint))
+}
+
+/// The newest version of `key` in `memtable` visible at `read_ts`.
+fn memtable_version(memtable: &MemTable, key: &[u8], read_ts: u64) -> Option<Version> {
+    // The versions of a key are ordered by descending ts, the first one at or below the read ts is the newest
+    // visible.
+    let iter = memtable.scan(
+        Bound::Included(KeySlice::from_slice(key, read_ts)),
+        Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
+    );
+    iter.is_valid().then(|| {
+        (
+            iter.key().ts(),
+            iter.value_type(),
+            Bytes::copy_from_slice(iter.value()),
+        )
+    })
+}
+
+/// The newest version of `key` in `table` visible at `read_ts`.
+fn table_version(
+    table: &Arc<SsTable>,
+    key: &[u8],
+    fingerprint: u32,
+    read_ts: u64,
+    options: BlockReadOptions,
+) -> Result<Option<Version>> {
+    if !table_may_contain(table, key, fingerprint) {
+        return Ok(None);
+    }
+    let iter = SsTableIterator::create_and_seek_to_key_by_hash_with_options(
+        table.clone(),
+        KeySlice::from_slice(key, read_ts),
+        options,
+    )?;
+    if iter.is_valid() && iter.key().key_ref() == key {
+        return Ok(Some((
+            iter.key().ts(),
+            iter.value_type(),
+            Bytes::copy_from_slice(iter.value()),
+        )));
+    }
+    Ok(None)
+}
+
+impl LsmStorageInner {
+    /// Get a key from the storage at `read_ts`.
+    ///
+    /// The sources are probed newest first: the active memtable, the immutable memtables, the L0 SSTs from
+    /// newest to oldest, then the one SST of each level whose key range may hold the key. Older sources only
+    /// hold older versions of the key, so the first version visible at `read_ts` is the one read.
+    pub(crate) fn get_with_ts(
+        &self,
+        key: &[u8],
+        read_ts: u64,
+        options: BlockReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        let version = Self::find_version(&snapshot, key, read_ts, options)?;
+        let Some(version) = version else {
+            return Ok(None);
+        };
+        let range_tombstones =
+            snapshot.range_tombstones(Bound::Included(key), Bound::Included(key), read_ts);
+        self.visible_value(&range_tombstones, key, version)
+    }
+
+    /// Find the newest version of `key` visible at `read_ts`.
+    fn find_version(
+        snapshot: &LsmStorageState,
+        key: &[u8],
+        read_ts: u64,
+        options: BlockReadOptions,
+    ) -> Result<Option<Version>> {
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            if let Some(version) = memtable_version(memtable, key, read_ts) {
+                return Ok(Some(version));
+            }
+        }
+        let fingerprint = farmhash::fingerprint32(key);
+        for table in snapshot.l0_sstables.iter() {
+            let table = &snapshot.sstables[table];
+            if let Some(version) = table_version(table, key, fingerprint, read_ts, options)? {
+                return Ok(Some(version));
+            }
+        }
+        for (_, level_sst_ids) in &snapshot.levels {
+            // The SSTs of a level are sorted by key range, the first one ending at or after the version sought is
+            // the only one that may hold it. The range of an SST may end at the first key of the next one when
+            // it ends with a range tombstone, so the ts is compared as well.
+            let idx = level_sst_ids.partition_point(|table| {
+                snapshot.sstables[table].last_key().as_key_slice()
+                    < KeySlice::from_slice(key, read_ts)
+            });
+            let Some(table) = level_sst_ids.get(idx) else {
+                continue;
+            };
+            let table = &snapshot.sstables[table];
+            if let Some(version) = table_version(table, key, fingerprint, read_ts, options)? {
+                return Ok(Some(version));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The value of `version` of `key`, or `None` if it is a delete or deleted by a range tombstone.
+    fn visible_value(
+        &self,
+        range_tombstones: &[RangeTombstone],
+        key: &[u8],
+        (ts, value_type, value): Version,
+    ) -> Result<Option<Bytes>> {
+        if value_type == ValueType::Delete
+            || max_covering_ts(range_tombstones, key).is_some_and(|range_ts| ts < range_ts)
+        {
+            return Ok(None);
+        }
+        Ok(Some(match value_type {
+            ValueType::BlobIndex => self.value_log.read(&ValuePointer::decode(&value)?)?,
+            _ => value,
+        }))
+    }
+
+    /// Get several keys from one snapshot of the storage at `read_ts`, in the order of `keys`.
+    ///
+    /// The keys are sorted and looked up together: the sources are visited newest first, each SST only for
+    /// the keys its filter may contain, and the blocks of an SST in key order so that a block shared
+    /// by several keys is read once. A key is no longer looked up once a version of it is found, as older
+    /// sources only hold older versions.
+    pub(crate) fn multi_get_with_ts(
+        &self,
+        keys: &[&[u8]],
+        read_ts: u64,
+        options: BlockReadOptions,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+
+        let mut sorted_keys = keys.to_vec();
+        sorted_keys.sort_unstable();
+        sorted_keys.dedup();
+        let (Some(first_key), Some(last_key)) = (sorted_keys.first(), sorted_keys.last()) else {
+            return Ok(Vec::new());
+        };
+        let range_tombstones = snapshot.range_tombstones(
+            Bound::Included(first_key),
+            Bound::Included(last_key),
+            read_ts,
+        );
+        let mut lookups = sorted_keys
+            .iter()
+            .map(|&key| KeyLookup {
+                key,
+                fingerprint: farmhash::fingerprint32(key),
+                version: None,
+            })
+            .collect::<Vec<_>>();
+        // Indices of the lookups without a version found yet, in key order.
+        let mut pending = (0..lookups.len()).collect::<Vec<_>>();
+
+        Self::lookup_memtables(&snapshot, &mut lookups, &mut pending, read_ts);
+        for table in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            if pending.is_empty() {
+                break;
+            }
+            let table = &snapshot.sstables[table];
+            Self::lookup_table(table, &mut lookups, &pending, read_ts, options)?;
+            pending.retain(|&idx| lookups[idx].version.is_none());
+        }
+
+        let mut values = Vec::with_capacity(lookups.len());
+        for lookup in lookups {
+            values.push(match lookup.version {
+                Some(version) => self.visible_value(&range_tombstones, lookup.key, version)?,
+                None => None,
+            });
+        }
+        Ok(keys
+            .iter()
+            .map(|key| values[sorted_keys.binary_search(key).unwrap()].clone())
+            .collect())
+    }
+
+    /// Look up the pending keys in the active memtable, then in the immutable ones.
+    fn lookup_memtables(
+        snapshot: &LsmStorageState,
+        lookups: &mut [KeyLookup],
+        pending: &mut Vec<usize>,
+        read_ts: u64,
+    ) {
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            for &idx in pending.iter() {
+                let lookup = &mut lookups[idx];
+                lookup.version = memtable_version(memtable, lookup.key, read_ts);
+            }
+            pending.retain(|&idx| lookups[idx].version.is_none());
+        }
+    }
+
+    /// Look up the pending keys in one SST. The keys go through the key range and filter of the table
+    /// first, and the remaining ones are found in key order, each block being read at most once.
+    fn lookup_table(
+        table: &SsTable,
+        lookups: &mut [KeyLookup],
+        pending: &[usize],
+        read_ts: u64,
+        options: BlockReadOptions,
+    ) -> Result<()> {
+        let begin = pending.partition_point(|&idx| lookups[idx].key < table.first_key().key_ref());
+        let end = pending.partition_point(|&idx| lookups[idx].key <= table.last_key().key_ref());
+        let mut block: Option<(usize, Arc<Block>)> = None;
+        for &idx in &pending[begin..end] {
+            let lookup = &mut lookups[idx];
+            if !table_may_contain(table, lookup.key, lookup.fingerprint) {
+                continue;
+            }
+            let seek_key = KeySlice::from_slice(lookup.key, read_ts);
+            // A key past the end of the block it maps to is in the next block, and so are the keys after it.
+            let mut block_idx = table.find_block_idx(seek_key);
+            if let Some((current_idx, _)) = block {
+                block_idx = block_idx.max(current_idx);
+            }
+            loop {
+                let current = match &block {
+                    Some((current_idx, current)) if *current_idx == block_idx => current.clone(),
+                    _ => {
+                        let current = table.read_block_with_options(block_idx, options)?;
+                        block = Some((block_idx, current.clone()));
+                        current
+                    }
+                };
+                let iter = BlockIterator::create_and_seek_to_key_by_hash(current, seek_key);
+                if !iter.is_valid() {
+                    if block_idx + 1 < table.num_of_blocks() {
+                        block_idx += 1;
+                        continue;
+                    }
+                    break;
+                }
+                if iter.key().key_ref() == lookup.key {
+                    lookup.version = Some((
+                        iter.key().ts(),
+                        iter.value_type(),
+                        Bytes::copy_from_slice(iter.value()),
+                    ));
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+}
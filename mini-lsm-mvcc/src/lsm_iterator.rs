@@ -1,4 +1,5 @@
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use bytes::Bytes;
@@ -6,53 +7,79 @@ use bytes::Bytes;
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{Direction, StorageIterator};
+use crate::key::{KeySlice, ValueType, TS_RANGE_BEGIN, TS_RANGE_END};
 use crate::mem_table::MemTableIterator;
+use crate::range_tombstone::{max_covering_ts, RangeTombstone};
 use crate::table::SsTableIterator;
+use crate::value_log::{ValueLog, ValuePointer};
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the tutorial for multiple times.
-type LsmIteratorInner = TwoMergeIterator<
+pub(crate) type LsmIteratorInner = TwoMergeIterator<
     TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
     MergeIterator<SstConcatIterator>,
 >;
 
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
     end_bound: Bound<Bytes>,
     is_valid: bool,
     read_ts: u64,
+    /// The current key.
     prev_key: Vec<u8>,
+    value_log: Arc<ValueLog>,
+    /// The value of the current entry if it is not read from `inner`: it is stored in the value log, or `inner`
+    /// has already moved past it backward.
+    value: Option<Bytes>,
+    /// Range tombstones visible at `read_ts`.
+    range_tombstones: Vec<RangeTombstone>,
+    direction: Direction,
 }
 
 impl LsmIterator {
     pub(crate) fn new(
         iter: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
         end_bound: Bound<Bytes>,
         read_ts: u64,
+        value_log: Arc<ValueLog>,
+        range_tombstones: Vec<RangeTombstone>,
     ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             inner: iter,
+            start_bound,
             end_bound,
             read_ts,
             prev_key: Vec::new(),
+            value_log,
+            value: None,
+            range_tombstones,
+            direction: Direction::Forward,
         };
+        iter.check_end_bound();
         iter.move_to_key()?;
         Ok(iter)
     }
 
     fn next_inner(&mut self) -> Result<()> {
         self.inner.next()?;
+        self.check_end_bound();
+        Ok(())
+    }
+
+    /// The inner iterator may start past the end bound, e.g. when the range is empty.
+    fn check_end_bound(&mut self) {
         if !self.inner.is_valid() {
             self.is_valid = false;
-            return Ok(());
+            return;
         }
         match self.end_bound.as_ref() {
             Bound::Unbounded => {}
             Bound::Included(key) => self.is_valid = self.inner.key().key_ref() <= key.as_ref(),
             Bound::Excluded(key) => self.is_valid = self.inner.key().key_ref() < key.as_ref(),
         }
-        Ok(())
     }
 
     fn move_to_key(&mut self) -> Result<()> {
@@ -77,12 +104,90 @@ impl LsmIterator {
             if self.inner.key().key_ref() != self.prev_key {
                 continue;
             }
-            if !self.inner.value().is_empty() {
+            let key = self.inner.key();
+            if self.inner.value_type() != ValueType::Delete
+                && !self.is_range_deleted(key.key_ref(), key.ts())
+            {
                 break;
             }
         }
+        self.load_blob_value()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by a range tombstone.
+    fn is_range_deleted(&self, key: &[u8], ts: u64) -> bool {
+        max_covering_ts(&self.range_tombstones, key).is_some_and(|range_ts| ts < range_ts)
+    }
+
+    /// Dereference the value pointer of the current entry, if any.
+    fn load_blob_value(&mut self) -> Result<()> {
+        self.value = None;
+        if self.is_valid && self.inner.is_valid() && self.inner.value_type() == ValueType::BlobIndex
+        {
+            let pointer = ValuePointer::decode(self.inner.value())?;
+            self.value = Some(self.value_log.read(&pointer)?);
+        }
         Ok(())
     }
+
+    /// Move forward to the first visible key from the entry `inner` was seeked to.
+    fn move_forward_from_inner(&mut self) -> Result<()> {
+        self.direction = Direction::Forward;
+        self.prev_key.clear();
+        self.check_end_bound();
+        self.move_to_key()
+    }
+
+    /// Whether the current entry of `inner` is within the start bound.
+    fn inner_within_start_bound(&self) -> bool {
+        if !self.inner.is_valid() {
+            return false;
+        }
+        match self.start_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() >= key.as_ref(),
+            Bound::Excluded(key) => self.inner.key().key_ref() > key.as_ref(),
+        }
+    }
+
+    /// Move backward to the previous visible key, with `inner` on the oldest version of a key. Versions come in
+    /// ascending ts order backward, so every version of a key is walked past to find the newest one visible at
+    /// `read_ts`, which leaves `inner` on the key before it.
+    fn move_to_key_backward(&mut self) -> Result<()> {
+        loop {
+            if !self.inner_within_start_bound() {
+                self.is_valid = false;
+                self.value = None;
+                return Ok(());
+            }
+            self.prev_key.clear();
+            self.prev_key.extend(self.inner.key().key_ref());
+            let mut visible = None;
+            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+                let ts = self.inner.key().ts();
+                if ts <= self.read_ts {
+                    visible = Some((
+                        ts,
+                        self.inner.value_type(),
+                        Bytes::copy_from_slice(self.inner.value()),
+                    ));
+                }
+                self.inner.prev()?;
+            }
+            let Some((ts, value_type, value)) = visible else {
+                continue;
+            };
+            if value_type == ValueType::Delete || self.is_range_deleted(&self.prev_key, ts) {
+                continue;
+            }
+            self.value = Some(match value_type {
+                ValueType::BlobIndex => self.value_log.read(&ValuePointer::decode(&value)?)?,
+                _ => value,
+            });
+            self.is_valid = true;
+            return Ok(());
+        }
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -93,19 +198,297 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().key_ref()
+        &self.prev_key
     }
 
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.value {
+            Some(value) => value,
+            None => self.inner.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
+        if self.direction == Direction::Backward {
+            // Come back to the oldest version of the current key, which `move_to_key` then skips.
+            self.inner
+                .seek(KeySlice::from_slice(&self.prev_key, TS_RANGE_END))?;
+            self.direction = Direction::Forward;
+            self.check_end_bound();
+            return self.move_to_key();
+        }
         self.next_inner()?;
         self.move_to_key()?;
         Ok(())
     }
 
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            // The newer versions of the current key come before the one `inner` is on.
+            while self.inner.is_valid() && self.inner.key().key_ref() == self.prev_key {
+                self.inner.prev()?;
+            }
+            self.direction = Direction::Backward;
+        }
+        self.move_to_key_backward()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // The newest version of a key comes first. Keys before the start bound are not seeked to, and an
+        // excluded start key is stepped over.
+        let key = match &self.start_bound {
+            Bound::Included(start) | Bound::Excluded(start) if key < start.as_ref() => start,
+            _ => key,
+        };
+        self.inner.seek(KeySlice::from_slice(key, TS_RANGE_BEGIN))?;
+        while self.inner.is_valid() && !self.inner_within_start_bound() {
+            self.inner.next()?;
+        }
+        self.move_forward_from_inner()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        match self.start_bound.clone() {
+            Bound::Included(key) | Bound::Excluded(key) => self.seek(&key),
+            Bound::Unbounded => {
+                self.inner.seek_to_first()?;
+                self.move_forward_from_inner()
+            }
+        }
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        match self.end_bound.clone() {
+            Bound::Included(key) => self
+                .inner
+                .seek_for_prev(KeySlice::from_slice(&key, TS_RANGE_END))?,
+            Bound::Excluded(key) => {
+                self.inner
+                    .seek_for_prev(KeySlice::from_slice(&key, TS_RANGE_BEGIN))?;
+                while self.inner.is_valid() && self.inner.key().key_ref() == key {
+                    self.inner.prev()?;
+                }
+            }
+            Bound::Unbounded => self.inner.seek_to_last()?,
+        }
+        self.direction = Direction::Backward;
+        self.move_to_key_backward()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let within_end_bound = match self.end_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(end) => key <= end.as_ref(),
+            Bound::Excluded(end) => key < end.as_ref(),
+        };
+        if !within_end_bound {
+            return self.seek_to_last();
+        }
+        self.inner
+            .seek_for_prev(KeySlice::from_slice(key, TS_RANGE_END))?;
+        self.direction = Direction::Backward;
+        self.move_to_key_backward()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
+
+/// Yields every version of the keys in a range within a ts window, newest first for each key. Deletions are
+/// yielded as `ValueType::Delete` entries with an empty value, including the range deletions covering a key
+/// that has any version.
+pub struct VersionIterator {
+    inner: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
+    end_bound: Bound<Bytes>,
+    min_ts: u64,
+    max_ts: u64,
+    value_log: Arc<ValueLog>,
+    /// Range tombstones with a ts within the window.
+    range_tombstones: Vec<RangeTombstone>,
+    /// The user key of the current version.
+    key: Vec<u8>,
+    /// The ts of the range deletions of `key` not yielded yet, in ascending order.
+    range_deletes: Vec<u64>,
+    /// Whether the current version is the last of `range_deletes` rather than the current entry of `inner`.
+    at_range_delete: bool,
+    /// The value of the current entry if it is stored in the value log.
+    blob_value: Option<Bytes>,
+    is_valid: bool,
+}
+
+impl VersionIterator {
+    pub(crate) fn new(
+        inner: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
+        end_bound: Bound<Bytes>,
+        min_ts: u64,
+        max_ts: u64,
+        value_log: Arc<ValueLog>,
+        range_tombstones: Vec<RangeTombstone>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            inner,
+            start_bound,
+            end_bound,
+            min_ts,
+            max_ts,
+            value_log,
+            range_tombstones,
+            key: Vec::new(),
+            range_deletes: Vec::new(),
+            at_range_delete: false,
+            blob_value: None,
+            is_valid: false,
+        };
+        iter.move_to_version()?;
+        Ok(iter)
+    }
+
+    fn inner_within_start_bound(&self) -> bool {
+        if !self.inner.is_valid() {
+            return false;
+        }
+        match self.start_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() >= key.as_ref(),
+            Bound::Excluded(key) => self.inner.key().key_ref() > key.as_ref(),
+        }
+    }
+
+    fn inner_within_bound(&self) -> bool {
+        if !self.inner.is_valid() {
+            return false;
+        }
+        match self.end_bound.as_ref() {
+            Bound::Unbounded => true,
+            Bound::Included(key) => self.inner.key().key_ref() <= key.as_ref(),
+            Bound::Excluded(key) => self.inner.key().key_ref() < key.as_ref(),
+        }
+    }
+
+    /// Pick the newer of the current entry of `inner` and the last range deletion of the current key.
+    fn move_to_version(&mut self) -> Result<()> {
+        self.blob_value = None;
+        loop {
+            // A key with no version in the window may still have range deletions in it.
+            while self.inner_within_bound()
+                && self.inner.key().key_ref() == self.key
+                && !(self.min_ts..=self.max_ts).contains(&self.inner.key().ts())
+            {
+                self.inner.next()?;
+            }
+            let within_bound = self.inner_within_bound();
+            let same_key = within_bound && self.inner.key().key_ref() == self.key;
+            if let Some(&ts) = self.range_deletes.last() {
+                if !same_key || ts > self.inner.key().ts() {
+                    self.at_range_delete = true;
+                    self.is_valid = true;
+                    return Ok(());
+                }
+            }
+            if same_key {
+                self.at_range_delete = false;
+                self.is_valid = true;
+                if self.inner.value_type() == ValueType::BlobIndex {
+                    let pointer = ValuePointer::decode(self.inner.value())?;
+                    self.blob_value = Some(self.value_log.read(&pointer)?);
+                }
+                return Ok(());
+            }
+            if !within_bound {
+                self.is_valid = false;
+                return Ok(());
+            }
+            // Move on to the next key, with the range deletions covering it.
+            self.key.clear();
+            self.key.extend(self.inner.key().key_ref());
+            self.load_range_deletes(u64::MAX);
+        }
+    }
+
+    /// Collect the range deletions of the current key up to `max_ts`.
+    fn load_range_deletes(&mut self, max_ts: u64) {
+        self.range_deletes = self
+            .range_tombstones
+            .iter()
+            .filter(|tombstone| tombstone.ts <= max_ts && tombstone.contains(&self.key))
+            .map(|tombstone| tombstone.ts)
+            .collect();
+        self.range_deletes.sort_unstable();
+    }
+}
+
+impl StorageIterator for VersionIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        if self.at_range_delete {
+            KeySlice::from_slice(&self.key, *self.range_deletes.last().unwrap())
+        } else {
+            self.inner.key()
+        }
+    }
+
+    fn value(&self) -> &[u8] {
+        match &self.blob_value {
+            Some(value) => value,
+            None if self.at_range_delete => &[],
+            None => self.inner.value(),
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        if self.at_range_delete {
+            return ValueType::Delete;
+        }
+        match self.inner.value_type() {
+            ValueType::BlobIndex => ValueType::Put,
+            value_type => value_type,
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.at_range_delete {
+            self.range_deletes.pop();
+        } else {
+            self.inner.next()?;
+        }
+        self.move_to_version()
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let key = match &self.start_bound {
+            Bound::Included(start) | Bound::Excluded(start) if key.key_ref() < start.as_ref() => {
+                KeySlice::from_slice(start, TS_RANGE_BEGIN)
+            }
+            _ => key,
+        }
+        .to_key_vec();
+        let key = key.as_key_slice();
+        self.inner
+            .seek(KeySlice::from_slice(key.key_ref(), TS_RANGE_BEGIN))?;
+        while self.inner.is_valid() && !self.inner_within_start_bound() {
+            self.inner.next()?;
+        }
+        self.key.clear();
+        self.range_deletes.clear();
+        // Only the versions of the key at or before `key` are yielded, range deletions included.
+        if self.inner_within_bound() && self.inner.key().key_ref() == key.key_ref() {
+            self.key.extend(key.key_ref());
+            self.load_range_deletes(key.ts());
+            while self.inner.is_valid() && self.inner.key() < key {
+                self.inner.next()?;
+            }
+        }
+        self.move_to_version()
+    }
+
     fn num_active_iterators(&self) -> usize {
         self.inner.num_active_iterators()
     }
@@ -126,6 +509,18 @@ impl<I: StorageIterator> FusedIterator<I> {
             has_errored: false,
         }
     }
+
+    /// Move the underlying iterator with `f` unless it has errored, marking it errored if `f` fails.
+    fn move_with(&mut self, f: impl FnOnce(&mut I) -> Result<()>) -> Result<()> {
+        if self.has_errored {
+            bail!("the iterator is tainted");
+        }
+        if let Err(e) = f(&mut self.iter) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
 }
 
 impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
@@ -149,18 +544,42 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.value()
     }
 
+    fn value_type(&self) -> ValueType {
+        if !self.is_valid() {
+            panic!("invalid access to the underlying iterator");
+        }
+        self.iter.value_type()
+    }
+
     fn next(&mut self) -> Result<()> {
         // only move when the iterator is valid and not errored
-        if self.has_errored {
-            bail!("the iterator is tainted");
-        }
-        if self.iter.is_valid() {
-            if let Err(e) = self.iter.next() {
-                self.has_errored = true;
-                return Err(e);
-            }
-        }
-        Ok(())
+        self.move_with(|iter| match iter.is_valid() {
+            true => iter.next(),
+            false => Ok(()),
+        })
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.move_with(|iter| match iter.is_valid() {
+            true => iter.prev(),
+            false => Ok(()),
+        })
+    }
+
+    fn seek(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        self.move_with(|iter| iter.seek(key))
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.move_with(|iter| iter.seek_to_first())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.move_with(|iter| iter.seek_to_last())
+    }
+
+    fn seek_for_prev(&mut self, key: Self::KeyType<'_>) -> Result<()> {
+        self.move_with(|iter| iter.seek_for_prev(key))
     }
 
     fn num_active_iterators(&self) -> usize {
@@ -1,30 +1,40 @@
 use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::ops::Bound;
+use std::ops::{Bound, RangeInclusive};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use crate::block::Block;
+use crate::block::{Block, DEFAULT_RESTART_INTERVAL};
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, CompactionStats, LeveledCompactionController,
+    LeveledCompactionOptions, RunningCompaction, SimpleLeveledCompactionController,
+    SimpleLeveledCompactionOptions, SubcompactionPool, TieredCompactionController,
 };
 use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
 use crate::iterators::StorageIterator;
-use crate::key::{self, KeySlice};
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::key::{self, KeySlice, ValueType};
+use crate::lsm_iterator::{FusedIterator, LsmIterator, LsmIteratorInner, VersionIterator};
 use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, map_key_bound_plus_ts, MemTable};
+use crate::mvcc::history::{History, HistoryRetention};
+use crate::mvcc::snapshot::Snapshot;
 use crate::mvcc::txn::{Transaction, TxnIterator};
 use crate::mvcc::LsmMvccInner;
-use crate::table::{FileObject, SsTable, SsTableBuilder, SsTableIterator};
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::filter::{BloomFilterPolicy, FilterPolicy};
+use crate::table::{
+    BlockReadOptions, CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator,
+};
+use crate::value_log::{BlobFileBuilder, ValueLog};
+use crate::wal::{WalRecoveryMode, WalRecoveryReport};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
@@ -49,6 +59,77 @@ pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Del(T),
 }
 
+/// Options of a single write.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions {
+    /// Fsync the WAL before the write returns. If the fsync fails, the write returns an error but is already
+    /// visible, and may or may not survive a crash.
+    pub sync: bool,
+    /// Skip the WAL. The write is lost on a crash before its memtable is flushed.
+    pub disable_wal: bool,
+}
+
+/// Options of a single read.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Insert the blocks read from disk into the block cache. Turn it off for one-off scans so that they do
+    /// not evict the blocks of hot keys. Blocks read without verifying their checksum are never inserted.
+    pub fill_cache: bool,
+    /// Check the checksum of the blocks read from disk.
+    pub verify_checksums: bool,
+    /// Scans start no lower than this bound, in addition to the bound passed to `scan`.
+    pub iterate_lower_bound: Bound<Bytes>,
+    /// Scans end no higher than this bound, in addition to the bound passed to `scan`.
+    pub iterate_upper_bound: Bound<Bytes>,
+    /// Read the data as of this timestamp instead of the latest commit. Versions below the watermark may
+    /// already be compacted away. Transactions always read at their own timestamp.
+    pub read_ts: Option<u64>,
+    /// Read the data as of this snapshot, which keeps the versions it sees from being compacted away.
+    pub snapshot: Option<Arc<Snapshot>>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            verify_checksums: true,
+            iterate_lower_bound: Bound::Unbounded,
+            iterate_upper_bound: Bound::Unbounded,
+            read_ts: None,
+            snapshot: None,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// The timestamp to read at given by `read_ts` or `snapshot`, if any.
+    pub(crate) fn pinned_read_ts(&self) -> Result<Option<u64>> {
+        match (self.read_ts, &self.snapshot) {
+            (Some(read_ts), Some(snapshot)) if read_ts != snapshot.read_ts() => bail!(
+                "read ts {} differs from the snapshot ts {}",
+                read_ts,
+                snapshot.read_ts()
+            ),
+            (read_ts, snapshot) => Ok(read_ts.or(snapshot.as_ref().map(|s| s.read_ts()))),
+        }
+    }
+
+    pub(crate) fn block_read_options(&self) -> BlockReadOptions {
+        BlockReadOptions {
+            fill_cache: self.fill_cache,
+            verify_checksums: self.verify_checksums,
+        }
+    }
+}
+
+/// A batch waiting in the write queue to be committed by a group leader.
+pub(crate) struct PendingWrite {
+    entries: Vec<(Bytes, ValueType, Bytes)>,
+    options: WriteOptions,
+    /// Set by the leader to the commit ts of the batch, or to the error that failed the group.
+    result: Mutex<Option<std::result::Result<u64, String>>>,
+}
+
 impl LsmStorageState {
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
@@ -61,13 +142,48 @@ impl LsmStorageState {
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
-            memtable: Arc::new(MemTable::create(0)),
+            memtable: Arc::new(
+                MemTable::create(0).with_prefix_extractor(options.prefix_extractor.clone()),
+            ),
             imm_memtables: Vec::new(),
             l0_sstables: Vec::new(),
             levels,
             sstables: Default::default(),
         }
     }
+
+    /// The size in bytes of the SSTs, which the compaction controllers compare levels and tiers by.
+    pub fn table_size_of(&self, sst_ids: &[usize]) -> u64 {
+        sst_ids
+            .iter()
+            .map(|id| self.sstables[id].table_size())
+            .sum()
+    }
+
+    /// Collect the range tombstones visible at `read_ts` that overlap with the user key range. Only the SSTs
+    /// whose key range, which covers their range tombstones, overlaps with it are looked at.
+    pub(crate) fn range_tombstones(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Vec<RangeTombstone> {
+        let memtables = std::iter::once(&self.memtable).chain(self.imm_memtables.iter());
+        let tables = self.sstables.values().filter(|table| {
+            !table.range_tombstones.is_empty()
+                && range_overlap(
+                    lower,
+                    upper,
+                    table.first_key().as_key_slice(),
+                    table.last_key().as_key_slice(),
+                )
+        });
+        memtables
+            .flat_map(|memtable| memtable.range_tombstones())
+            .chain(tables.flat_map(|table| table.range_tombstones.iter().cloned()))
+            .filter(|tombstone| tombstone.ts <= read_ts && tombstone.overlaps(lower, upper))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -81,6 +197,50 @@ pub struct LsmStorageOptions {
     pub compaction_options: CompactionOptions,
     pub enable_wal: bool,
     pub serializable: bool,
+    // Codec used to compress data blocks of newly-written SSTs
+    pub compression: CompressionType,
+    // Number of entries between two restart points in data blocks
+    pub block_restart_interval: usize,
+    // Build a hash index in data blocks to speed up point lookups
+    pub block_hash_index: bool,
+    // Values of at least this many bytes are moved to blob files on flush, `None` disables the value log
+    pub value_log_threshold: Option<usize>,
+    // Blob files whose live bytes fall below this ratio of their size are rewritten by the garbage collector
+    pub value_log_gc_ratio: f64,
+    // How to deal with incomplete or corrupted WAL records on recovery
+    pub wal_recovery_mode: WalRecoveryMode,
+    // Extracts the prefixes added to the filters of SSTs and memtables, `None` disables prefix filtering
+    pub prefix_extractor: Option<PrefixExtractor>,
+    // Filter policy of the SSTs written to each level, starting with L0, `None` builds no filter. Levels past the
+    // end use the last policy, and an empty list builds no filter at all
+    pub filter_policies: Vec<Option<Arc<dyn FilterPolicy>>>,
+    // Maximum number of key ranges a compaction task is split into, and of subcompactions running at once
+    pub max_subcompactions: usize,
+    // Number of threads running compaction tasks, each picking tasks that do not conflict with the running ones
+    pub num_compaction_threads: usize,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20, // 2MB
+            compaction_options: CompactionOptions::NoCompaction,
+            enable_wal: false,
+            num_memtable_limit: 3,
+            serializable: false,
+            compression: CompressionType::None,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            block_hash_index: false,
+            value_log_threshold: None,
+            value_log_gc_ratio: 0.5,
+            wal_recovery_mode: WalRecoveryMode::default(),
+            prefix_extractor: None,
+            filter_policies: vec![Some(Arc::new(BloomFilterPolicy::default()))],
+            max_subcompactions: 1,
+            num_compaction_threads: 1,
+        }
+    }
 }
 
 impl LsmStorageOptions {
@@ -92,6 +252,16 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 50,
             serializable: false,
+            compression: CompressionType::None,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            block_hash_index: false,
+            value_log_threshold: None,
+            value_log_gc_ratio: 0.5,
+            wal_recovery_mode: WalRecoveryMode::default(),
+            prefix_extractor: None,
+            filter_policies: vec![Some(Arc::new(BloomFilterPolicy::default()))],
+            max_subcompactions: 1,
+            num_compaction_threads: 1,
         }
     }
 
@@ -103,6 +273,16 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            block_hash_index: false,
+            value_log_threshold: None,
+            value_log_gc_ratio: 0.5,
+            wal_recovery_mode: WalRecoveryMode::default(),
+            prefix_extractor: None,
+            filter_policies: vec![Some(Arc::new(BloomFilterPolicy::default()))],
+            max_subcompactions: 1,
+            num_compaction_threads: 1,
         }
     }
 
@@ -114,11 +294,30 @@ impl LsmStorageOptions {
             enable_wal: false,
             num_memtable_limit: 2,
             serializable: false,
+            compression: CompressionType::None,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            block_hash_index: false,
+            value_log_threshold: None,
+            value_log_gc_ratio: 0.5,
+            wal_recovery_mode: WalRecoveryMode::default(),
+            prefix_extractor: None,
+            filter_policies: vec![Some(Arc::new(BloomFilterPolicy::default()))],
+            max_subcompactions: 1,
+            num_compaction_threads: 1,
         }
     }
+
+    /// The filter policy of the SSTs written to `level`, 0 being L0.
+    pub fn filter_policy(&self, level: usize) -> Option<Arc<dyn FilterPolicy>> {
+        self.filter_policies
+            .get(level)
+            .or(self.filter_policies.last())
+            .cloned()
+            .flatten()
+    }
 }
 
-fn range_overlap(
+pub(crate) fn range_overlap(
     user_begin: Bound<&[u8]>,
     user_end: Bound<&[u8]>,
     table_begin: KeySlice,
@@ -145,8 +344,34 @@ fn range_overlap(
     true
 }
 
-fn key_within(user_key: &[u8], table_begin: KeySlice, table_end: KeySlice) -> bool {
-    table_begin.key_ref() <= user_key && user_key <= table_end.key_ref()
+/// The tighter of two lower bounds.
+pub(crate) fn tighter_lower_bound<'a>(a: Bound<&'a [u8]>, b: Bound<&'a [u8]>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(y) {
+                std::cmp::Ordering::Greater => a,
+                std::cmp::Ordering::Less => b,
+                std::cmp::Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+                std::cmp::Ordering::Equal => b,
+            }
+        }
+    }
+}
+
+/// The tighter of two upper bounds.
+pub(crate) fn tighter_upper_bound<'a>(a: Bound<&'a [u8]>, b: Bound<&'a [u8]>) -> Bound<&'a [u8]> {
+    match (a, b) {
+        (Bound::Unbounded, bound) | (bound, Bound::Unbounded) => bound,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match x.cmp(y) {
+                std::cmp::Ordering::Less => a,
+                std::cmp::Ordering::Greater => b,
+                std::cmp::Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+                std::cmp::Ordering::Equal => b,
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -166,6 +391,21 @@ pub(crate) struct LsmStorageInner {
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    pub(crate) compaction_stats: Mutex<CompactionStats>,
+    /// The compaction tasks being run, whose SSTs no other task may pick. This is the only record of the SSTs
+    /// being compacted: the controllers are told to exclude them when generating a task. It is kept out of
+    /// `LsmStorageState` because it is not part of the LSM tree, which readers snapshot and the manifest
+    /// replays, and a task registers and releases its SSTs without publishing a new state.
+    pub(crate) running_compactions: Mutex<Vec<RunningCompaction>>,
+    pub(crate) value_log: Arc<ValueLog>,
+    /// What was dropped from each WAL replayed on open, by memtable id.
+    wal_recovery_reports: Vec<(usize, WalRecoveryReport)>,
+    /// Batches waiting for the next write group.
+    pub(crate) write_queue: Mutex<Vec<Arc<PendingWrite>>>,
+    /// Number of WAL fsyncs done by write groups.
+    pub(crate) num_wal_syncs: AtomicUsize,
+    /// Runs the subcompactions of split compaction tasks, `None` if tasks are never split.
+    pub(crate) subcompaction_pool: Option<SubcompactionPool>,
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -253,22 +493,62 @@ impl MiniLsm {
         self.inner.add_compaction_filter(compaction_filter)
     }
 
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.inner.compaction_stats()
+    }
+
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
         self.inner.get(key)
     }
 
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.inner.get_with_options(key, options)
+    }
+
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get(keys)
+    }
+
+    pub fn multi_get_with_options(
+        &self,
+        keys: &[&[u8]],
+        options: &ReadOptions,
+    ) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get_with_options(keys, options)
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
 
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.inner.write_batch_with_options(batch, options)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
 
+    pub fn put_with_options(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.put_with_options(key, value, options)
+    }
+
     pub fn delete(&self, key: &[u8]) -> Result<()> {
         self.inner.delete(key)
     }
 
+    pub fn delete_with_options(&self, key: &[u8], options: &WriteOptions) -> Result<()> {
+        self.inner.delete_with_options(key, options)
+    }
+
+    pub fn delete_range(&self, begin: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range(begin, end)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
@@ -277,10 +557,84 @@ impl MiniLsm {
         self.inner.new_txn()
     }
 
+    /// Pin the current state for consistent reads, without the bookkeeping of a transaction.
+    pub fn get_snapshot(&self) -> Arc<Snapshot> {
+        self.inner.get_snapshot()
+    }
+
+    pub fn set_history_retention(&self, retention: Option<HistoryRetention>) -> Result<()> {
+        self.inner.set_history_retention(retention)
+    }
+
+    pub fn history_retention(&self) -> Option<HistoryRetention> {
+        self.inner.history_retention()
+    }
+
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, ts)
+    }
+
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_at(lower, upper, ts)
+    }
+
+    /// Iterate over every retained version of a key with a ts in `ts_window`, newest first.
+    pub fn key_versions(
+        &self,
+        key: &[u8],
+        ts_window: RangeInclusive<u64>,
+    ) -> Result<FusedIterator<VersionIterator>> {
+        self.inner
+            .scan_versions(Bound::Included(key), Bound::Included(key), ts_window)
+    }
+
+    /// Iterate over every retained version of the keys in a range with a ts in `ts_window`, newest first for
+    /// each key.
+    pub fn scan_versions(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts_window: RangeInclusive<u64>,
+    ) -> Result<FusedIterator<VersionIterator>> {
+        self.inner.scan_versions(lower, upper, ts_window)
+    }
+
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
         self.inner.scan(lower, upper)
     }
 
+    /// Create an iterator over a range of keys positioned at the last key, to be walked with `prev`.
+    pub fn scan_reverse(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.inner.scan_reverse(lower, upper)
+    }
+
+    pub fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_with_options(lower, upper, options)
+    }
+
+    /// Iterate over every key starting with `prefix`.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<TxnIterator> {
+        self.inner.scan_prefix(prefix)
+    }
+
+    pub fn scan_prefix_with_options(
+        &self,
+        prefix: &[u8],
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.inner.scan_prefix_with_options(prefix, options)
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -296,6 +650,33 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    /// Compact the keys in `[begin, end]` down to `target_level`, or to the last level if `None`, e.g. to reclaim
+    /// the space of deleted keys. The memtables are flushed first, and the call returns once the range is
+    /// compacted.
+    pub fn compact_range(
+        &self,
+        begin: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        target_level: Option<usize>,
+    ) -> Result<()> {
+        if !self.inner.state.read().memtable.is_empty() {
+            self.inner
+                .force_freeze_memtable(&self.inner.state_lock.lock())?;
+        }
+        while !self.inner.state.read().imm_memtables.is_empty() {
+            self.inner.force_flush_next_imm_memtable()?;
+        }
+        self.inner.compact_range(begin, end, target_level)
+    }
+
+    pub fn gc_value_log(&self) -> Result<()> {
+        self.inner.gc_value_log()
+    }
+
+    pub fn wal_recovery_reports(&self) -> &[(usize, WalRecoveryReport)] {
+        self.inner.wal_recovery_reports()
+    }
 }
 
 impl LsmStorageInner {
@@ -315,6 +696,10 @@ impl LsmStorageInner {
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let gc_ratio = options.value_log_gc_ratio;
+        if !(gc_ratio > 0.0 && gc_ratio <= 1.0) {
+            bail!("value_log_gc_ratio must be in (0, 1], got {}", gc_ratio);
+        }
         let mut state = LsmStorageState::create(&options);
         let path = path.as_ref();
         let mut next_sst_id = 1;
@@ -339,12 +724,19 @@ impl LsmStorageInner {
         }
         let manifest_path = path.join("MANIFEST");
         let mut last_commit_ts = 0;
+        let mut wal_recovery_reports = Vec::new();
+        let mut history = History::default();
+        let mut commit_times = Vec::new();
+        let mut blob_garbage = Vec::new();
         if !manifest_path.exists() {
             if options.enable_wal {
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    state.memtable.id(),
-                    Self::path_of_wal_static(path, state.memtable.id()),
-                )?);
+                state.memtable = Arc::new(
+                    MemTable::create_with_wal(
+                        state.memtable.id(),
+                        Self::path_of_wal_static(path, state.memtable.id()),
+                    )?
+                    .with_prefix_extractor(options.prefix_extractor.clone()),
+                );
             }
             manifest = Manifest::create(&manifest_path).context("failed to create manifest")?;
             manifest.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
@@ -352,8 +744,12 @@ impl LsmStorageInner {
             let (m, records) = Manifest::recover(&manifest_path)?;
             let mut memtables = BTreeSet::new();
             for record in records {
+                if let ManifestRecord::FlushWithCommitTime(_, time, ts) = record {
+                    commit_times.push((time, ts));
+                }
                 match record {
-                    ManifestRecord::Flush(sst_id) => {
+                    ManifestRecord::Flush(sst_id)
+                    | ManifestRecord::FlushWithCommitTime(sst_id, _, _) => {
                         let res = memtables.remove(&sst_id);
                         assert!(res, "memtable not exist?");
                         if compaction_controller.flush_to_l0() {
@@ -367,16 +763,27 @@ impl LsmStorageInner {
                         next_sst_id = next_sst_id.max(x);
                         memtables.insert(x);
                     }
-                    ManifestRecord::Compaction(task, output) => {
+                    ManifestRecord::Compaction(task, output, garbage) => {
                         let (new_state, _) =
                             compaction_controller.apply_compaction_result(&state, &task, &output);
                         // TODO: apply remove again
                         state = new_state;
                         next_sst_id =
                             next_sst_id.max(output.iter().max().copied().unwrap_or_default());
+                        blob_garbage.extend(garbage);
+                    }
+                    ManifestRecord::TrivialMove(trivial_move) => {
+                        state = trivial_move.apply(&state, true);
+                    }
+                    ManifestRecord::HistoryRetention(retention) => {
+                        history.retention = retention;
                     }
                 }
             }
+            // The samples are only kept as needed by the final retention.
+            for (time, ts) in commit_times {
+                history.record_commit(time, ts);
+            }
 
             let mut sst_cnt = 0;
             // recover SSTs
@@ -397,6 +804,14 @@ impl LsmStorageInner {
                 sst_cnt += 1;
             }
             println!("{} SSTs opened", sst_cnt);
+            // Trivial moves were replayed without the key ranges of the SSTs.
+            for (_, files) in &mut state.levels {
+                files.sort_by(|x, y| {
+                    state.sstables[x]
+                        .first_key()
+                        .cmp(state.sstables[y].first_key())
+                });
+            }
 
             next_sst_id += 1;
 
@@ -404,12 +819,26 @@ impl LsmStorageInner {
             if options.enable_wal {
                 let mut wal_cnt = 0;
                 for id in memtables.iter() {
-                    let memtable =
-                        MemTable::recover_from_wal(*id, Self::path_of_wal_static(path, *id))?;
+                    let (memtable, report) = MemTable::recover_from_wal_with_mode(
+                        *id,
+                        Self::path_of_wal_static(path, *id),
+                        options.wal_recovery_mode,
+                    )?;
+                    let memtable = memtable.with_prefix_extractor(options.prefix_extractor.clone());
+                    if !report.dropped.is_empty() {
+                        println!(
+                            "WAL {} recovered, {} bytes dropped: {:?}",
+                            id,
+                            report.dropped_bytes(),
+                            report.dropped
+                        );
+                    }
+                    wal_recovery_reports.push((*id, report));
                     let max_ts = memtable
                         .map
                         .iter()
                         .map(|x| x.key().ts())
+                        .chain(memtable.range_tombstones().iter().map(|x| x.ts))
                         .max()
                         .unwrap_or_default();
                     last_commit_ts = last_commit_ts.max(max_ts);
@@ -419,18 +848,30 @@ impl LsmStorageInner {
                     }
                 }
                 println!("{} WALs recovered", wal_cnt);
-                state.memtable = Arc::new(MemTable::create_with_wal(
-                    next_sst_id,
-                    Self::path_of_wal_static(path, next_sst_id),
-                )?);
+                state.memtable = Arc::new(
+                    MemTable::create_with_wal(
+                        next_sst_id,
+                        Self::path_of_wal_static(path, next_sst_id),
+                    )?
+                    .with_prefix_extractor(options.prefix_extractor.clone()),
+                );
             } else {
-                state.memtable = Arc::new(MemTable::create(next_sst_id));
+                state.memtable = Arc::new(
+                    MemTable::create(next_sst_id)
+                        .with_prefix_extractor(options.prefix_extractor.clone()),
+                );
             }
             m.add_record_when_init(ManifestRecord::NewMemtable(state.memtable.id()))?;
             next_sst_id += 1;
             manifest = m;
         };
 
+        let mvcc = LsmMvccInner::new(last_commit_ts);
+        *mvcc.history.lock() = history;
+        let value_log = ValueLog::open(path)?;
+        value_log.add_garbage(&blob_garbage);
+        let subcompaction_pool = (options.max_subcompactions > 1)
+            .then(|| SubcompactionPool::new(options.max_subcompactions));
         let storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
@@ -440,14 +881,31 @@ impl LsmStorageInner {
             compaction_controller,
             manifest: Some(manifest),
             options: options.into(),
-            mvcc: Some(LsmMvccInner::new(last_commit_ts)),
+            mvcc: Some(mvcc),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            compaction_stats: Mutex::new(CompactionStats::default()),
+            running_compactions: Mutex::new(Vec::new()),
+            value_log: Arc::new(value_log),
+            wal_recovery_reports,
+            write_queue: Mutex::new(Vec::new()),
+            num_wal_syncs: AtomicUsize::new(0),
+            subcompaction_pool,
         };
         storage.sync_dir()?;
 
         Ok(storage)
     }
 
+    /// What was replayed and dropped from each WAL on open, by memtable id.
+    pub fn wal_recovery_reports(&self) -> &[(usize, WalRecoveryReport)] {
+        &self.wal_recovery_reports
+    }
+
+    /// Counters of the compactions run since the storage was opened.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction_stats.lock().clone()
+    }
+
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
         let mut compaction_filters = self.compaction_filters.lock();
         compaction_filters.push(compaction_filter);
@@ -459,130 +917,170 @@ impl LsmStorageInner {
 
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(self: &Arc<Self>, key: &[u8]) -> Result<Option<Bytes>> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.get(key)
+        self.get_with_options(key, &ReadOptions::default())
     }
 
-    pub(crate) fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.state.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
-
-        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
-            Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
-                Bound::Included(KeySlice::from_slice(key, key::TS_RANGE_END)),
-            )));
-        }
-        let memtable_iter = MergeIterator::create(memtable_iters);
+    pub fn get_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let txn = self.new_read_txn(options)?;
+        txn.get_with_options(key, options)
+    }
 
-        let mut l0_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+    /// Get several keys at once, reading all of them from the same snapshot.
+    pub fn multi_get(self: &Arc<Self>, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.multi_get_with_options(keys, &ReadOptions::default())
+    }
 
-        let keep_table = |key: &[u8], table: &SsTable| {
-            if key_within(
-                key,
-                table.first_key().as_key_slice(),
-                table.last_key().as_key_slice(),
-            ) {
-                if let Some(bloom) = &table.bloom {
-                    if bloom.may_contain(farmhash::fingerprint32(key)) {
-                        return true;
-                    }
-                } else {
-                    return true;
-                }
-            }
-            false
-        };
+    pub fn multi_get_with_options(
+        self: &Arc<Self>,
+        keys: &[&[u8]],
+        options: &ReadOptions,
+    ) -> Result<Vec<Option<Bytes>>> {
+        let txn = self.new_read_txn(options)?;
+        txn.multi_get_with_options(keys, options)
+    }
 
-        for table in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table].clone();
-            if keep_table(key, &table) {
-                l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                    table,
-                    KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-                )?));
+    /// Create a transaction reading at the timestamp of `options`, or at the latest commit.
+    fn new_read_txn(self: &Arc<Self>, options: &ReadOptions) -> Result<Arc<Transaction>> {
+        match options.pinned_read_ts()? {
+            Some(read_ts) => {
+                self.mvcc()
+                    .new_txn_at(self.clone(), read_ts, self.options.serializable)
             }
+            None => Ok(self.mvcc().new_txn(self.clone(), self.options.serializable)),
         }
-        let l0_iter = MergeIterator::create(l0_iters);
-        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
-        for (_, level_sst_ids) in &snapshot.levels {
-            let mut level_ssts = Vec::with_capacity(snapshot.levels[0].1.len());
-            for table in level_sst_ids {
-                let table = snapshot.sstables[table].clone();
-                if keep_table(key, &table) {
-                    level_ssts.push(table);
-                }
-            }
-            let level_iter = SstConcatIterator::create_and_seek_to_key(
-                level_ssts,
-                KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
-            )?;
-            level_iters.push(Box::new(level_iter));
-        }
-
-        let iter = LsmIterator::new(
-            TwoMergeIterator::create(
-                TwoMergeIterator::create(memtable_iter, l0_iter)?,
-                MergeIterator::create(level_iters),
-            )?,
-            Bound::Unbounded,
-            read_ts,
-        )?;
+    }
 
-        if iter.is_valid() && iter.key() == key && !iter.value().is_empty() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+    /// Commit a batch with a new commit ts, returns the ts.
+    ///
+    /// Concurrent writers are committed in groups: each writer queues its batch and waits for the write lock.
+    /// The writer that gets the lock leads the group of all queued batches, writing them with a single fsync of
+    /// the WAL, and the other writers of the group find their batch committed once they get the lock.
+    pub fn write_batch_inner<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
+    ) -> Result<u64> {
+        let entries = batch
+            .iter()
+            .map(|record| {
+                let (key, value_type, value) = match record {
+                    WriteBatchRecord::Del(key) => (key.as_ref(), ValueType::Delete, &b""[..]),
+                    WriteBatchRecord::Put(key, value) => {
+                        (key.as_ref(), ValueType::Put, value.as_ref())
+                    }
+                };
+                assert!(!key.is_empty(), "key cannot be empty");
+                (
+                    Bytes::copy_from_slice(key),
+                    value_type,
+                    Bytes::copy_from_slice(value),
+                )
+            })
+            .collect();
+        let write = Arc::new(PendingWrite {
+            entries,
+            options: *options,
+            result: Mutex::new(None),
+        });
+        self.write_queue.lock().push(write.clone());
+        let _lck = self.mvcc().write_lock.lock();
+        if let Some(result) = write.result.lock().take() {
+            return result.map_err(|e| anyhow::anyhow!(e));
+        }
+        let group = std::mem::take(&mut *self.write_queue.lock());
+        let results = self.commit_write_group(&group);
+        for (pending, result) in group.iter().zip(results) {
+            *pending.result.lock() = Some(result.map_err(|e| format!("{:#}", e)));
         }
-        Ok(None)
+        let result = write.result.lock().take().unwrap();
+        result.map_err(|e| anyhow::anyhow!(e))
     }
 
-    pub fn write_batch_inner<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<u64> {
-        let _lck = self.mvcc().write_lock.lock();
-        let ts = self.mvcc().latest_commit_ts() + 1;
-        for record in batch {
-            match record {
-                WriteBatchRecord::Del(key) => {
-                    let key = key.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    let size;
-                    {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), b"")?;
-                        size = guard.memtable.approximate_size();
+    /// Write a group of batches, each with its own commit ts, and return the commit ts of each batch. Must be
+    /// called with the write lock held. The commit ts is advanced past each batch once it is written, so the
+    /// batches before a failed one are committed, and the failed one and the ones after it are not written.
+    ///
+    /// If the WAL sync fails, the batches that asked for it get an error, but they are already committed and
+    /// visible: the error only means that they may not survive a crash.
+    fn commit_write_group(&self, group: &[Arc<PendingWrite>]) -> Vec<Result<u64>> {
+        let mut results = Vec::with_capacity(group.len());
+        // Each batch goes into the same memtable, and so the same WAL record.
+        let size;
+        {
+            let guard = self.state.read();
+            for write in group {
+                let ts = self.mvcc().latest_commit_ts() + 1;
+                let entries = write
+                    .entries
+                    .iter()
+                    .map(|(key, value_type, value)| (&key[..], *value_type, &value[..]))
+                    .collect::<Vec<_>>();
+                if let Err(e) =
+                    guard
+                        .memtable
+                        .put_batch_inner(ts, &entries, write.options.disable_wal)
+                {
+                    let message = format!("{:#}", e);
+                    results.push(Err(e));
+                    while results.len() < group.len() {
+                        results.push(Err(anyhow!(
+                            "an earlier batch of the write group failed: {}",
+                            message
+                        )));
                     }
-                    self.try_freeze(size)?;
+                    break;
                 }
-                WriteBatchRecord::Put(key, value) => {
-                    let key = key.as_ref();
-                    let value = value.as_ref();
-                    assert!(!key.is_empty(), "key cannot be empty");
-                    assert!(!value.is_empty(), "value cannot be empty");
-                    let size;
+                self.mvcc().update_commit_ts(ts);
+                results.push(Ok(ts));
+            }
+            let needs_sync = group
+                .iter()
+                .zip(&results)
+                .map(|(write, result)| {
+                    result.is_ok() && write.options.sync && !write.options.disable_wal
+                })
+                .collect::<Vec<_>>();
+            if needs_sync.contains(&true) {
+                if let Err(e) = guard.memtable.sync_wal() {
+                    let message = format!("{:#}", e);
+                    for (result, _) in results
+                        .iter_mut()
+                        .zip(&needs_sync)
+                        .filter(|(_, sync)| **sync)
                     {
-                        let guard = self.state.read();
-                        guard.memtable.put(KeySlice::from_slice(key, ts), value)?;
-                        size = guard.memtable.approximate_size();
+                        *result = Err(anyhow!("failed to sync the WAL: {}", message));
                     }
-                    self.try_freeze(size)?;
+                } else {
+                    self.num_wal_syncs.fetch_add(1, Ordering::Relaxed);
                 }
             }
+            size = guard.memtable.approximate_size();
         }
-        self.mvcc().update_commit_ts(ts);
-        Ok(ts)
+        // The batches are committed whether or not the memtable is frozen, the next write tries again.
+        if let Err(e) = self.try_freeze(size) {
+            eprintln!("failed to freeze the memtable: {}", e);
+        }
+        results
     }
 
     pub fn write_batch<T: AsRef<[u8]>>(
         self: &Arc<Self>,
         batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.write_batch_with_options(batch, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options<T: AsRef<[u8]>>(
+        self: &Arc<Self>,
+        batch: &[WriteBatchRecord<T>],
+        options: &WriteOptions,
     ) -> Result<()> {
         if !self.options.serializable {
-            self.write_batch_inner(batch)?;
+            self.write_batch_inner(batch, options)?;
         } else {
             let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
             for record in batch {
@@ -595,32 +1093,49 @@ impl LsmStorageInner {
                     }
                 }
             }
-            txn.commit()?;
+            txn.commit_with_options(options)?;
         }
         Ok(())
     }
 
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(self: &Arc<Self>, key: &[u8], value: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Put(key, value)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.put(key, value);
-            txn.commit()?;
-        }
-        Ok(())
+        self.put_with_options(key, value, &WriteOptions::default())
     }
 
-    /// Remove a key from the storage by writing an empty value.
+    pub fn put_with_options(
+        self: &Arc<Self>,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<()> {
+        self.write_batch_with_options(&[WriteBatchRecord::Put(key, value)], options)
+    }
+
+    /// Remove a key from the storage by writing a deletion marker.
     pub fn delete(self: &Arc<Self>, key: &[u8]) -> Result<()> {
-        if !self.options.serializable {
-            self.write_batch_inner(&[WriteBatchRecord::Del(key)])?;
-        } else {
-            let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-            txn.delete(key);
-            txn.commit()?;
+        self.delete_with_options(key, &WriteOptions::default())
+    }
+
+    pub fn delete_with_options(self: &Arc<Self>, key: &[u8], options: &WriteOptions) -> Result<()> {
+        self.write_batch_with_options(&[WriteBatchRecord::Del(key)], options)
+    }
+
+    /// Delete every key in `[begin, end)` by writing a range tombstone into the current memtable.
+    pub fn delete_range(&self, begin: &[u8], end: &[u8]) -> Result<()> {
+        if begin >= end {
+            bail!("range cannot be empty");
         }
+        let _lck = self.mvcc().write_lock.lock();
+        let ts = self.mvcc().latest_commit_ts() + 1;
+        let size;
+        {
+            let guard = self.state.read();
+            guard.memtable.delete_range(begin, end, ts)?;
+            size = guard.memtable.approximate_size();
+        }
+        self.try_freeze(size)?;
+        self.mvcc().update_commit_ts(ts);
         Ok(())
     }
 
@@ -678,13 +1193,12 @@ impl LsmStorageInner {
     pub fn force_freeze_memtable(&self, state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
         let memtable_id = self.next_sst_id();
         let memtable = if self.options.enable_wal {
-            Arc::new(MemTable::create_with_wal(
-                memtable_id,
-                self.path_of_wal(memtable_id),
-            )?)
+            MemTable::create_with_wal(memtable_id, self.path_of_wal(memtable_id))?
         } else {
-            Arc::new(MemTable::create(memtable_id))
+            MemTable::create(memtable_id)
         };
+        let memtable =
+            Arc::new(memtable.with_prefix_extractor(self.options.prefix_extractor.clone()));
 
         self.freeze_memtable_with_memtable(memtable)?;
 
@@ -697,6 +1211,16 @@ impl LsmStorageInner {
         Ok(())
     }
 
+    /// Create an SST builder configured with the storage options, for an SST written to `level`.
+    pub(crate) fn new_sst_builder(&self, level: usize) -> SsTableBuilder {
+        SsTableBuilder::new(self.options.block_size)
+            .with_compression(self.options.compression)
+            .with_restart_interval(self.options.block_restart_interval)
+            .with_hash_index(self.options.block_hash_index)
+            .with_prefix_extractor(self.options.prefix_extractor.clone())
+            .with_filter_policy(self.options.filter_policy(level))
+    }
+
     /// Force flush the earliest-created immutable memtable to disk
     pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
         let state_lock = self.state_lock.lock();
@@ -705,16 +1229,25 @@ impl LsmStorageInner {
 
         {
             let guard = self.state.read();
-            flush_memtable = guard
-                .imm_memtables
-                .last()
-                .expect("no imm memtables!")
-                .clone();
+            // The memtable could have been flushed by another thread since the caller checked.
+            let Some(memtable) = guard.imm_memtables.last() else {
+                return Ok(());
+            };
+            flush_memtable = memtable.clone();
         }
 
-        let mut builder = SsTableBuilder::new(self.options.block_size);
-        flush_memtable.flush(&mut builder)?;
+        let mut builder = self.new_sst_builder(0);
         let sst_id = flush_memtable.id();
+        if let Some(threshold) = self.options.value_log_threshold {
+            let mut blob_builder = BlobFileBuilder::new(sst_id);
+            flush_memtable.flush_with_value_log(&mut builder, &mut blob_builder, threshold)?;
+            if !blob_builder.is_empty() {
+                let blob_file = blob_builder.build(self.value_log.path_of_blob(sst_id, 0))?;
+                self.value_log.add_file(blob_file);
+            }
+        } else {
+            flush_memtable.flush(&mut builder)?;
+        }
         let sst = Arc::new(builder.build(
             sst_id,
             Some(self.block_cache.clone()),
@@ -746,8 +1279,17 @@ impl LsmStorageInner {
             std::fs::remove_file(self.path_of_wal(sst_id))?;
         }
 
-        self.manifest()
-            .add_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        // Persist when the flushed data was committed, so that a retention duration resolves after a restart.
+        let record = {
+            let history = self.mvcc().history.lock();
+            match (history.retention, history.last_commit_time()) {
+                (Some(HistoryRetention::Duration(_)), Some((time, ts))) => {
+                    ManifestRecord::FlushWithCommitTime(sst_id, time, ts)
+                }
+                _ => ManifestRecord::Flush(sst_id),
+            }
+        };
+        self.manifest().add_record(&state_lock, record)?;
 
         self.sync_dir()?;
 
@@ -758,65 +1300,221 @@ impl LsmStorageInner {
         Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
     }
 
+    pub fn get_snapshot(self: &Arc<Self>) -> Arc<Snapshot> {
+        self.mvcc().new_snapshot(self.clone())
+    }
+
+    /// Set how far back compaction keeps the versions of keys. Lowering the floor does not bring back the
+    /// versions already compacted away.
+    pub fn set_history_retention(&self, retention: Option<HistoryRetention>) -> Result<()> {
+        let state_lock = self.state_lock.lock();
+        self.manifest()
+            .add_record(&state_lock, ManifestRecord::HistoryRetention(retention))?;
+        self.mvcc().history.lock().retention = retention;
+        Ok(())
+    }
+
+    pub fn history_retention(&self) -> Option<HistoryRetention> {
+        self.mvcc().history.lock().retention
+    }
+
+    /// Get the value of a key as of `ts`.
+    pub fn get_at(self: &Arc<Self>, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        let options = ReadOptions {
+            read_ts: Some(ts),
+            ..Default::default()
+        };
+        self.get_with_options(key, &options)
+    }
+
+    /// Create an iterator over a range of keys as of `ts`.
+    pub fn scan_at(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<TxnIterator> {
+        let options = ReadOptions {
+            read_ts: Some(ts),
+            ..Default::default()
+        };
+        self.scan_with_options(lower, upper, &options)
+    }
+
     /// Create an iterator over a range of keys.
     pub fn scan<'a>(
         self: &'a Arc<Self>,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<TxnIterator> {
-        let txn = self.mvcc().new_txn(self.clone(), self.options.serializable);
-        txn.scan(lower, upper)
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let txn = self.new_read_txn(options)?;
+        txn.scan_with_options(lower, upper, options)
+    }
+
+    /// Create an iterator over a range of keys positioned at the last key, to be walked with `prev`.
+    pub fn scan_reverse(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<TxnIterator> {
+        let mut iter = self.scan(lower, upper)?;
+        iter.seek_to_last()?;
+        Ok(iter)
     }
 
+    /// Create an iterator over every key starting with `prefix`, skipping the memtables and SSTs whose prefix
+    /// filter rules the prefix out.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        self.scan_prefix_with_options(prefix, &ReadOptions::default())
+    }
+
+    pub fn scan_prefix_with_options(
+        self: &Arc<Self>,
+        prefix: &[u8],
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let txn = self.new_read_txn(options)?;
+        txn.scan_prefix_with_options(prefix, options)
+    }
+
+    /// Create an iterator over a range of keys at `read_ts`. If all the keys of the range start with `prefix`,
+    /// the sources without keys of that prefix are skipped.
     pub(crate) fn scan_with_ts(
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
         read_ts: u64,
+        options: BlockReadOptions,
     ) -> Result<FusedIterator<LsmIterator>> {
         let snapshot = {
             let guard = self.state.read();
             Arc::clone(&guard)
         }; // drop global lock here
 
+        // The keys starting with `prefix` share its extracted prefix, if it has one.
+        let prefix_filter = prefix.and_then(|prefix| {
+            let prefix_extractor = self.options.prefix_extractor.as_ref()?;
+            let prefix = prefix_extractor.extract(prefix)?;
+            Some((prefix_extractor, farmhash::fingerprint32(prefix)))
+        });
+        Ok(FusedIterator::new(LsmIterator::new(
+            Self::scan_inner(&snapshot, lower, upper, prefix_filter, options)?,
+            map_bound(lower),
+            map_bound(upper),
+            read_ts,
+            self.value_log.clone(),
+            snapshot.range_tombstones(lower, upper, read_ts),
+        )?))
+    }
+
+    /// Create an iterator over every version of the keys in a range with a ts in `ts_window`, newest first for
+    /// each key. Versions already compacted away are not yielded.
+    pub fn scan_versions(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts_window: RangeInclusive<u64>,
+    ) -> Result<FusedIterator<VersionIterator>> {
+        let snapshot = {
+            let guard = self.state.read();
+            Arc::clone(&guard)
+        }; // drop global lock here
+           // Versions above the latest commit may not be committed yet.
+        let (min_ts, max_ts) = (
+            *ts_window.start(),
+            (*ts_window.end()).min(self.mvcc().latest_commit_ts()),
+        );
+        let range_tombstones = snapshot
+            .range_tombstones(lower, upper, max_ts)
+            .into_iter()
+            .filter(|tombstone| tombstone.ts >= min_ts)
+            .collect();
+
+        Ok(FusedIterator::new(VersionIterator::new(
+            Self::scan_inner(&snapshot, lower, upper, None, BlockReadOptions::default())?,
+            map_bound(lower),
+            map_bound(upper),
+            min_ts,
+            max_ts,
+            self.value_log.clone(),
+            range_tombstones,
+        )?))
+    }
+
+    /// Merge the memtables, L0 and levels of `snapshot` over a range of keys, with every version of each key.
+    /// With a prefix filter, i.e. an extractor and the hash of the prefix it extracts from all the keys of the
+    /// range, the memtables and SSTs whose filter rules the prefix out are skipped.
+    fn scan_inner(
+        snapshot: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix_filter: Option<(&PrefixExtractor, u32)>,
+        options: BlockReadOptions,
+    ) -> Result<LsmIteratorInner> {
+        // The versions of a key are ordered by descending ts, so an excluded key is skipped past its oldest
+        // version at the lower bound and its newest version at the upper bound.
+        let memtable_lower = match lower {
+            Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_END)),
+            _ => map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
+        };
+        let memtable_upper = match upper {
+            Bound::Excluded(key) => Bound::Excluded(KeySlice::from_slice(key, key::TS_RANGE_BEGIN)),
+            _ => map_key_bound_plus_ts(upper, key::TS_RANGE_END),
+        };
         let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
-        memtable_iters.push(Box::new(snapshot.memtable.scan(
-            map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-            map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-        )));
-        for memtable in snapshot.imm_memtables.iter() {
-            memtable_iters.push(Box::new(memtable.scan(
-                map_key_bound_plus_ts(lower, key::TS_RANGE_BEGIN),
-                map_key_bound_plus_ts(upper, key::TS_RANGE_END),
-            )));
+        for memtable in std::iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter()) {
+            if prefix_filter.is_none_or(|(prefix_extractor, prefix_hash)| {
+                memtable.may_contain_prefix(prefix_extractor, prefix_hash)
+            }) {
+                memtable_iters.push(Box::new(memtable.scan(memtable_lower, memtable_upper)));
+            }
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
-        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
-        for table_id in snapshot.l0_sstables.iter() {
-            let table = snapshot.sstables[table_id].clone();
-            if range_overlap(
+        let keep_table = |table: &SsTable| {
+            range_overlap(
                 lower,
                 upper,
                 table.first_key().as_key_slice(),
                 table.last_key().as_key_slice(),
-            ) {
+            ) && prefix_filter.is_none_or(|(prefix_extractor, prefix_hash)| {
+                table.may_contain_prefix(prefix_extractor, prefix_hash)
+            })
+        };
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table_id in snapshot.l0_sstables.iter() {
+            let table = snapshot.sstables[table_id].clone();
+            if keep_table(&table) {
                 let iter = match lower {
-                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key(
+                    Bound::Included(key) => SsTableIterator::create_and_seek_to_key_with_options(
                         table,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        options,
                     )?,
                     Bound::Excluded(key) => {
-                        let mut iter = SsTableIterator::create_and_seek_to_key(
+                        let mut iter = SsTableIterator::create_and_seek_to_key_with_options(
                             table,
                             KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                            options,
                         )?;
                         while iter.is_valid() && iter.key().key_ref() == key {
                             iter.next()?;
                         }
                         iter
                     }
-                    Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table)?,
+                    Bound::Unbounded => {
+                        SsTableIterator::create_and_seek_to_first_with_options(table, options)?
+                    }
                 };
 
                 table_iters.push(Box::new(iter));
@@ -829,43 +1527,36 @@ impl LsmStorageInner {
             let mut level_ssts = Vec::with_capacity(level_sst_ids.len());
             for table in level_sst_ids {
                 let table = snapshot.sstables[table].clone();
-                if range_overlap(
-                    lower,
-                    upper,
-                    table.first_key().as_key_slice(),
-                    table.last_key().as_key_slice(),
-                ) {
+                if keep_table(&table) {
                     level_ssts.push(table);
                 }
             }
 
             let level_iter = match lower {
-                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key(
+                Bound::Included(key) => SstConcatIterator::create_and_seek_to_key_with_options(
                     level_ssts,
                     KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                    options,
                 )?,
                 Bound::Excluded(key) => {
-                    let mut iter = SstConcatIterator::create_and_seek_to_key(
+                    let mut iter = SstConcatIterator::create_and_seek_to_key_with_options(
                         level_ssts,
                         KeySlice::from_slice(key, key::TS_RANGE_BEGIN),
+                        options,
                     )?;
                     while iter.is_valid() && iter.key().key_ref() == key {
                         iter.next()?;
                     }
                     iter
                 }
-                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level_ssts)?,
+                Bound::Unbounded => {
+                    SstConcatIterator::create_and_seek_to_first_with_options(level_ssts, options)?
+                }
             };
             level_iters.push(Box::new(level_iter));
         }
 
         let iter = TwoMergeIterator::create(memtable_iter, l0_iter)?;
-        let iter = TwoMergeIterator::create(iter, MergeIterator::create(level_iters))?;
-
-        Ok(FusedIterator::new(LsmIterator::new(
-            iter,
-            map_bound(upper),
-            read_ts,
-        )?))
+        TwoMergeIterator::create(iter, MergeIterator::create(level_iters))
     }
 }
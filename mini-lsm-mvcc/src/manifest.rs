@@ -8,7 +8,9 @@ use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
-use crate::compact::CompactionTask;
+use crate::compact::{CompactionTask, TrivialMove};
+use crate::mvcc::history::HistoryRetention;
+use crate::value_log::ValuePointer;
 
 pub struct Manifest {
     file: Arc<Mutex<File>>,
@@ -18,7 +20,19 @@ pub struct Manifest {
 pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
-    Compaction(CompactionTask, Vec<usize>),
+    /// A compaction task, its output, and the blob values it dropped, which no SST refers to anymore.
+    Compaction(
+        CompactionTask,
+        Vec<usize>,
+        #[serde(default)] Vec<ValuePointer>,
+    ),
+    /// SSTs relinked into a lower level as they are.
+    TrivialMove(TrivialMove),
+    /// The history retention set at runtime, replacing the previous one.
+    HistoryRetention(Option<HistoryRetention>),
+    /// A flush, with the latest commit ts at a time in seconds since the epoch. Written instead of `Flush` under
+    /// a retention duration, which needs the commit times to resolve after a restart.
+    FlushWithCommitTime(usize, u64, u64),
 }
 
 impl Manifest {
@@ -6,23 +6,31 @@ use std::sync::Arc;
 use anyhow::Result;
 use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
-use crossbeam_skiplist::SkipMap;
+use crossbeam_skiplist::{SkipMap, SkipSet};
 use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
 use crate::iterators::StorageIterator;
-use crate::key::{KeyBytes, KeySlice, TS_DEFAULT};
+use crate::key::{KeyBytes, KeySlice, ValueType, TS_DEFAULT};
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::value_log::BlobFileBuilder;
+use crate::wal::{Wal, WalRecoveryMode, WalRecoveryReport};
 
 /// A basic mem-table based on crossbeam-skiplist.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    pub(crate) map: Arc<SkipMap<KeyBytes, Bytes>>,
+    pub(crate) map: Arc<SkipMap<KeyBytes, (ValueType, Bytes)>>,
     wal: Option<Wal>,
     id: usize,
     approximate_size: Arc<AtomicUsize>,
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
+    prefix_extractor: Option<PrefixExtractor>,
+    /// Hashes of the prefixes of the keys written, to skip the mem-table in prefix scans.
+    prefix_hashes: SkipSet<u32>,
 }
 
 /// Create a bound of `Bytes` from a bound of `&[u8]`.
@@ -66,6 +74,9 @@ impl MemTable {
             map: Arc::new(SkipMap::new()),
             wal: None,
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            range_tombstones: RwLock::new(Vec::new()),
+            prefix_extractor: None,
+            prefix_hashes: SkipSet::new(),
         }
     }
 
@@ -76,27 +87,75 @@ impl MemTable {
             map: Arc::new(SkipMap::new()),
             wal: Some(Wal::create(path.as_ref())?),
             approximate_size: Arc::new(AtomicUsize::new(0)),
+            range_tombstones: RwLock::new(Vec::new()),
+            prefix_extractor: None,
+            prefix_hashes: SkipSet::new(),
         })
     }
 
     /// Create a memtable from WAL
     pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let (memtable, _) = Self::recover_from_wal_with_mode(id, path, WalRecoveryMode::default())?;
+        Ok(memtable)
+    }
+
+    /// Create a memtable from WAL, dealing with incomplete or corrupted records as `recovery_mode` says.
+    pub fn recover_from_wal_with_mode(
+        id: usize,
+        path: impl AsRef<Path>,
+        recovery_mode: WalRecoveryMode,
+    ) -> Result<(Self, WalRecoveryReport)> {
         let map = Arc::new(SkipMap::new());
-        Ok(Self {
-            id,
-            wal: Some(Wal::recover(path.as_ref(), &map)?),
-            map,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
-        })
+        let mut range_tombstones = Vec::new();
+        let (wal, report) =
+            Wal::recover(path.as_ref(), &map, &mut range_tombstones, recovery_mode)?;
+        Ok((
+            Self {
+                id,
+                wal: Some(wal),
+                map,
+                approximate_size: Arc::new(AtomicUsize::new(0)),
+                range_tombstones: RwLock::new(range_tombstones),
+                prefix_extractor: None,
+                prefix_hashes: SkipSet::new(),
+            },
+            report,
+        ))
     }
 
-    /// Get a value by key. Should not be used in week 3.
+    /// Track the prefixes extracted from the keys, including the ones already written, for prefix scans.
+    pub fn with_prefix_extractor(mut self, prefix_extractor: Option<PrefixExtractor>) -> Self {
+        self.prefix_extractor = prefix_extractor;
+        self.prefix_hashes = SkipSet::new();
+        for entry in self.map.iter() {
+            self.add_prefix_hash(entry.key().key_ref());
+        }
+        self
+    }
+
+    fn add_prefix_hash(&self, key: &[u8]) {
+        if let Some(prefix) = self
+            .prefix_extractor
+            .as_ref()
+            .and_then(|prefix_extractor| prefix_extractor.extract(key))
+        {
+            self.prefix_hashes.insert(farmhash::fingerprint32(prefix));
+        }
+    }
+
+    /// Whether the mem-table may have keys whose prefix extracted by `prefix_extractor` hashes to `prefix_hash`.
+    pub fn may_contain_prefix(&self, prefix_extractor: &PrefixExtractor, prefix_hash: u32) -> bool {
+        self.prefix_extractor.as_ref() != Some(prefix_extractor)
+            || self.prefix_hashes.contains(&prefix_hash)
+    }
+
+    /// Get a value by key. A deletion yields an empty value. Should not be used in week 3.
     pub fn get(&self, key: KeySlice) -> Option<Bytes> {
         let key_bytes = KeyBytes::from_bytes_with_ts(
             Bytes::from_static(unsafe { std::mem::transmute(key.key_ref()) }),
             key.ts(),
         );
-        self.map.get(&key_bytes).map(|e| e.value().clone())
+        self.map.get(&key_bytes).map(|e| e.value().1.clone())
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
@@ -123,19 +182,66 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
-        let estimated_size = key.raw_len() + value.len();
-        self.map.insert(
-            key.to_key_vec().into_key_bytes(),
-            Bytes::copy_from_slice(value),
-        );
+        self.put_batch(key.ts(), &[(key.key_ref(), ValueType::Put, value)])
+    }
+
+    /// Mark a key as deleted.
+    pub fn delete(&self, key: KeySlice) -> Result<()> {
+        self.put_batch(key.ts(), &[(key.key_ref(), ValueType::Delete, b"")])
+    }
+
+    /// Put a batch of entries sharing the timestamp `ts` into the mem-table. The batch is written to the WAL as
+    /// a single record, so that it is recovered either as a whole or not at all.
+    pub fn put_batch(&self, ts: u64, entries: &[(&[u8], ValueType, &[u8])]) -> Result<()> {
+        self.put_batch_inner(ts, entries, false)
+    }
+
+    /// Put a batch of entries into the mem-table, skipping the WAL if `disable_wal` is set.
+    pub(crate) fn put_batch_inner(
+        &self,
+        ts: u64,
+        entries: &[(&[u8], ValueType, &[u8])],
+        disable_wal: bool,
+    ) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        if let (Some(wal), false) = (&self.wal, disable_wal) {
+            wal.put_batch(ts, entries)?;
+        }
+        let mut estimated_size = 0;
+        for (key, value_type, value) in entries {
+            estimated_size += key.len() + std::mem::size_of::<u64>() + value.len() + 1;
+            // The prefix goes first, so that a prefix scan never skips a key already in the map.
+            self.add_prefix_hash(key);
+            self.map.insert(
+                KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key), ts),
+                (*value_type, Bytes::copy_from_slice(value)),
+            );
+        }
         self.approximate_size
             .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Delete the keys in `[begin, end)` written before `ts`.
+    pub fn delete_range(&self, begin: &[u8], end: &[u8], ts: u64) -> Result<()> {
+        let range_tombstone = RangeTombstone::new(begin, end, ts);
+        let estimated_size = begin.len() + end.len() + std::mem::size_of::<u64>();
         if let Some(ref wal) = self.wal {
-            wal.put(key, value)?;
+            wal.delete_range(&range_tombstone)?;
         }
+        self.range_tombstones.write().push(range_tombstone);
+        self.approximate_size
+            .fetch_add(estimated_size, std::sync::atomic::Ordering::Relaxed);
         Ok(())
     }
 
+    /// Get the range tombstones of the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
+    }
+
     pub fn sync_wal(&self) -> Result<()> {
         if let Some(ref wal) = self.wal {
             wal.sync()?;
@@ -143,13 +249,20 @@ impl MemTable {
         Ok(())
     }
 
+    #[cfg(test)]
+    pub(crate) fn redirect_wal_for_test(&self, file: std::fs::File) {
+        self.wal.as_ref().unwrap().redirect_for_test(file);
+    }
+
     /// Get an iterator over a range of keys.
     pub fn scan(&self, lower: Bound<KeySlice>, upper: Bound<KeySlice>) -> MemTableIterator {
         let (lower, upper) = (map_key_bound(lower), map_key_bound(upper));
+        let bounds = (lower.clone(), upper.clone());
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
             iter_builder: |map| map.range((lower, upper)),
-            item: (KeyBytes::new(), Bytes::new()),
+            item: (KeyBytes::new(), ValueType::Put, Bytes::new()),
+            bounds,
         }
         .build();
         iter.next().unwrap();
@@ -159,7 +272,35 @@ impl MemTable {
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(entry.key().as_key_slice(), &entry.value()[..]);
+            let (value_type, value) = entry.value();
+            builder.add_with_type(entry.key().as_key_slice(), *value_type, value);
+        }
+        for range_tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(range_tombstone);
+        }
+        Ok(())
+    }
+
+    /// Flush the mem-table to SSTable, moving values of at least `threshold` bytes to the blob file and storing
+    /// pointers to them in the SSTable.
+    pub fn flush_with_value_log(
+        &self,
+        builder: &mut SsTableBuilder,
+        blob_builder: &mut BlobFileBuilder,
+        threshold: usize,
+    ) -> Result<()> {
+        for entry in self.map.iter() {
+            let key = entry.key().as_key_slice();
+            let (value_type, value) = entry.value();
+            if *value_type == ValueType::Put && value.len() >= threshold {
+                let pointer = blob_builder.add(key, value);
+                builder.add_with_type(key, ValueType::BlobIndex, &pointer.encode());
+            } else {
+                builder.add_with_type(key, *value_type, value);
+            }
+        }
+        for range_tombstone in self.range_tombstones() {
+            builder.add_range_tombstone(range_tombstone);
         }
         Ok(())
     }
@@ -175,7 +316,7 @@ impl MemTable {
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
     }
 }
 
@@ -184,7 +325,7 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
     KeyBytes,
     (Bound<KeyBytes>, Bound<KeyBytes>),
     KeyBytes,
-    Bytes,
+    (ValueType, Bytes),
 >;
 
 /// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
@@ -194,20 +335,57 @@ type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
 #[self_referencing]
 pub struct MemTableIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<KeyBytes, Bytes>>,
+    map: Arc<SkipMap<KeyBytes, (ValueType, Bytes)>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (KeyBytes, Bytes),
+    /// Stores the current key, value type and value.
+    item: (KeyBytes, ValueType, Bytes),
+    /// Stores the range of the iterator, to move backward within it.
+    bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+}
+
+fn above_lower(key: &KeyBytes, lower: &Bound<KeyBytes>) -> bool {
+    match lower {
+        Bound::Included(lower) => key >= lower,
+        Bound::Excluded(lower) => key > lower,
+        Bound::Unbounded => true,
+    }
+}
+
+fn below_upper(key: &KeyBytes, upper: &Bound<KeyBytes>) -> bool {
+    match upper {
+        Bound::Included(upper) => key <= upper,
+        Bound::Excluded(upper) => key < upper,
+        Bound::Unbounded => true,
+    }
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, KeyBytes, Bytes>>) -> (KeyBytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, KeyBytes, (ValueType, Bytes)>>,
+    ) -> (KeyBytes, ValueType, Bytes) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (KeyBytes::new(), Bytes::new()))
+            .map(|x| (x.key().clone(), x.value().0, x.value().1.clone()))
+            .unwrap_or_else(|| (KeyBytes::new(), ValueType::Put, Bytes::new()))
+    }
+
+    /// Move to the last entry at or before `upper` within the range of the iterator. The skipmap iterator is
+    /// restarted right after it, so that `next` moves forward from there.
+    fn seek_backward_to(&mut self, upper: Bound<&KeyBytes>) {
+        self.with_mut(|x| {
+            let entry = x
+                .map
+                .upper_bound(upper)
+                .filter(|entry| above_lower(entry.key(), &x.bounds.0));
+            *x.item = MemTableIterator::entry_to_item(entry);
+            let lower = match x.item.0.is_empty() {
+                true => x.bounds.0.clone(),
+                false => Bound::Excluded(x.item.0.clone()),
+            };
+            *x.iter = x.map.range((lower, x.bounds.1.clone()));
+        });
     }
 }
 
@@ -215,7 +393,11 @@ impl StorageIterator for MemTableIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().2[..]
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.borrow_item().1
     }
 
     fn key(&self) -> KeySlice {
@@ -231,4 +413,43 @@ impl StorageIterator for MemTableIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        let key = self.borrow_item().0.clone();
+        self.seek_backward_to(Bound::Excluded(&key));
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        let key = KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key.key_ref()), key.ts());
+        self.with_mut(|x| {
+            let lower = match above_lower(&key, &x.bounds.0) {
+                true => Bound::Included(key),
+                false => x.bounds.0.clone(),
+            };
+            *x.iter = x.map.range((lower, x.bounds.1.clone()));
+        });
+        self.next()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.with_mut(|x| *x.iter = x.map.range(x.bounds.clone()));
+        self.next()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let upper = self.borrow_bounds().1.clone();
+        self.seek_backward_to(upper.as_ref());
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let key = KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(key.key_ref()), key.ts());
+        if below_upper(&key, &self.borrow_bounds().1) {
+            self.seek_backward_to(Bound::Included(&key));
+            Ok(())
+        } else {
+            self.seek_to_last()
+        }
+    }
 }
@@ -1,6 +1,8 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+pub mod history;
+pub mod snapshot;
 pub mod txn;
 pub mod watermark;
 
@@ -9,12 +11,18 @@ use std::{
     sync::{atomic::AtomicBool, Arc},
 };
 
+use anyhow::{bail, Result};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use crate::lsm_storage::LsmStorageInner;
 
-use self::{txn::Transaction, watermark::Watermark};
+use self::{
+    history::{now_secs, History},
+    snapshot::Snapshot,
+    txn::Transaction,
+    watermark::Watermark,
+};
 
 pub(crate) struct CommittedTxnData {
     pub(crate) key_hashes: HashSet<u32>,
@@ -29,6 +37,7 @@ pub(crate) struct LsmMvccInner {
     pub(crate) commit_lock: Mutex<()>,
     pub(crate) ts: Arc<Mutex<(u64, Watermark)>>,
     pub(crate) committed_txns: Arc<Mutex<BTreeMap<u64, CommittedTxnData>>>,
+    pub(crate) history: Mutex<History>,
 }
 
 impl LsmMvccInner {
@@ -38,6 +47,7 @@ impl LsmMvccInner {
             commit_lock: Mutex::new(()),
             ts: Arc::new(Mutex::new((initial_ts, Watermark::new()))),
             committed_txns: Arc::new(Mutex::new(BTreeMap::new())),
+            history: Mutex::new(History::default()),
         }
     }
 
@@ -47,18 +57,77 @@ impl LsmMvccInner {
 
     pub fn update_commit_ts(&self, ts: u64) {
         self.ts.lock().0 = ts;
+        self.history.lock().record_commit(now_secs(), ts);
     }
 
-    /// All ts (strictly) below this ts can be garbage collected.
+    /// All ts (strictly) below this ts are not used by any reader.
     pub fn watermark(&self) -> u64 {
         let ts = self.ts.lock();
         ts.1.watermark().unwrap_or(ts.0)
     }
 
+    /// All ts (strictly) below this ts can be garbage collected: the watermark, lowered to the history retention
+    /// floor if any.
+    pub fn gc_watermark(&self) -> u64 {
+        let watermark = self.watermark();
+        match self.history.lock().floor(now_secs()) {
+            Some(floor) => watermark.min(floor),
+            None => watermark,
+        }
+    }
+
     pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
         let mut ts = self.ts.lock();
         let read_ts = ts.0;
         ts.1.add_reader(read_ts);
+        Self::create_txn(inner, read_ts, serializable)
+    }
+
+    /// Create a transaction reading at `read_ts`, which must not be above the latest commit, nor below the
+    /// history that compaction keeps.
+    pub fn new_txn_at(
+        &self,
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+        serializable: bool,
+    ) -> Result<Arc<Transaction>> {
+        let mut ts = self.ts.lock();
+        if read_ts > ts.0 {
+            bail!(
+                "cannot read at ts {} above the latest commit ts {}",
+                read_ts,
+                ts.0
+            );
+        }
+        // Checked with the ts lock held, so that the watermark cannot pass `read_ts` before it is added.
+        let watermark = ts.1.watermark().unwrap_or(ts.0);
+        let gc_watermark = match self.history.lock().floor(now_secs()) {
+            Some(floor) => watermark.min(floor),
+            None => watermark,
+        };
+        if read_ts < gc_watermark {
+            bail!(
+                "history at ts {} is not retained, the oldest retained ts is {}",
+                read_ts,
+                gc_watermark
+            );
+        }
+        ts.1.add_reader(read_ts);
+        Ok(Self::create_txn(inner, read_ts, serializable))
+    }
+
+    pub fn new_snapshot(&self, inner: Arc<LsmStorageInner>) -> Arc<Snapshot> {
+        let mut ts = self.ts.lock();
+        let read_ts = ts.0;
+        ts.1.add_reader(read_ts);
+        Arc::new(Snapshot { read_ts, inner })
+    }
+
+    fn create_txn(
+        inner: Arc<LsmStorageInner>,
+        read_ts: u64,
+        serializable: bool,
+    ) -> Arc<Transaction> {
         Arc::new(Transaction {
             inner,
             read_ts,
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// How far back compaction keeps the versions of keys, on top of what the readers need.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryRetention {
+    /// Keep every version visible at this ts and after.
+    Timestamp(u64),
+    /// Keep every version visible within this duration before now.
+    Duration(Duration),
+}
+
+/// The history retention floor, and the commit times needed to resolve a retention duration to a ts.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    pub(crate) retention: Option<HistoryRetention>,
+    /// (seconds since the epoch, latest commit ts at that time), oldest first. Only the samples needed to
+    /// resolve the current retention are kept.
+    pub(crate) commit_times: VecDeque<(u64, u64)>,
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+impl History {
+    /// Record that `ts` is committed at `time`.
+    pub(crate) fn record_commit(&mut self, time: u64, ts: u64) {
+        match self.commit_times.back_mut() {
+            // Samples are kept one per second, and the clock may go backwards.
+            Some((last_time, last_ts)) if *last_time >= time => *last_ts = (*last_ts).max(ts),
+            _ => self.commit_times.push_back((time, ts)),
+        }
+        // Older samples are only needed to resolve a retention duration.
+        if !matches!(self.retention, Some(HistoryRetention::Duration(_))) {
+            self.prune(self.commit_times.len() - 1);
+        }
+    }
+
+    /// The latest commit time sample, to be persisted.
+    pub(crate) fn last_commit_time(&self) -> Option<(u64, u64)> {
+        self.commit_times.back().copied()
+    }
+
+    /// The ts that compaction must keep the history from at `now`, `None` if no history is retained.
+    pub(crate) fn floor(&mut self, now: u64) -> Option<u64> {
+        let floor = match self.retention? {
+            HistoryRetention::Timestamp(ts) => ts,
+            HistoryRetention::Duration(duration) => {
+                let cutoff = now.saturating_sub(duration.as_secs());
+                match self
+                    .commit_times
+                    .partition_point(|(time, _)| *time <= cutoff)
+                {
+                    // Nothing is known about the commits at the cutoff, keep the whole history.
+                    0 => 0,
+                    // The ts of the last sample at or before the cutoff was already committed at the cutoff.
+                    idx => {
+                        self.prune(idx - 1);
+                        self.commit_times[0].1
+                    }
+                }
+            }
+        };
+        Some(floor)
+    }
+
+    /// Drop the samples before `idx`, which are no longer needed for the current retention.
+    fn prune(&mut self, idx: usize) {
+        self.commit_times.drain(..idx);
+    }
+}
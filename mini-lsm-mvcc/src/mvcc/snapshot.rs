@@ -0,0 +1,116 @@
+use std::{ops::Bound, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::LsmStorageInner,
+    table::BlockReadOptions,
+};
+
+/// A read-only view of the storage at a fixed timestamp. Unlike a transaction, it has no local writes and no
+/// read set to track. Versions visible to it are kept by compaction until it is dropped.
+pub struct Snapshot {
+    pub(crate) read_ts: u64,
+    pub(crate) inner: Arc<LsmStorageInner>,
+}
+
+impl Snapshot {
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner
+            .get_with_ts(key, self.read_ts, BlockReadOptions::default())
+    }
+
+    pub fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Bytes>>> {
+        let keys = keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+        self.inner
+            .multi_get_with_ts(&keys, self.read_ts, BlockReadOptions::default())
+    }
+
+    pub fn scan(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<SnapshotIterator> {
+        Ok(SnapshotIterator {
+            _snapshot: self.clone(),
+            iter: self.inner.scan_with_ts(
+                lower,
+                upper,
+                None,
+                self.read_ts,
+                BlockReadOptions::default(),
+            )?,
+        })
+    }
+}
+
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("read_ts", &self.read_ts)
+            .finish()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts)
+    }
+}
+
+/// Iterates over a snapshot, keeping it alive while iterating.
+pub struct SnapshotIterator {
+    _snapshot: Arc<Snapshot>,
+    iter: FusedIterator<LsmIterator>,
+}
+
+impl StorageIterator for SnapshotIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.iter.prev()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_for_prev(key)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -14,17 +14,22 @@ use ouroboros::self_referencing;
 use parking_lot::Mutex;
 
 use crate::{
-    iterators::{two_merge_iterator::TwoMergeIterator, StorageIterator},
+    iterators::{two_merge_iterator::TwoMergeIterator, Direction, StorageIterator},
+    key::ValueType,
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::{LsmStorageInner, WriteBatchRecord},
+    lsm_storage::{
+        tighter_lower_bound, tighter_upper_bound, LsmStorageInner, ReadOptions, WriteBatchRecord,
+        WriteOptions,
+    },
     mem_table::map_bound,
     mvcc::CommittedTxnData,
+    prefix_extractor::prefix_upper_bound,
 };
 
 pub struct Transaction {
     pub(crate) read_ts: u64,
     pub(crate) inner: Arc<LsmStorageInner>,
-    pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
+    pub(crate) local_storage: Arc<SkipMap<Bytes, (ValueType, Bytes)>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
     pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
@@ -32,32 +37,124 @@ pub struct Transaction {
 
 impl Transaction {
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_with_options(key, &ReadOptions::default())
+    }
+
+    pub fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        self.check_read_ts(options)?;
         if let Some(guard) = &self.key_hashes {
             let mut guard = guard.lock();
             let (_, read_set) = &mut *guard;
             read_set.insert(farmhash::hash32(key));
         }
         if let Some(entry) = self.local_storage.get(key) {
-            if entry.value().is_empty() {
+            let (value_type, value) = entry.value();
+            if *value_type == ValueType::Delete {
                 return Ok(None);
             } else {
-                return Ok(Some(entry.value().clone()));
+                return Ok(Some(value.clone()));
             }
         }
-        self.inner.get_with_ts(key, self.read_ts)
+        self.inner
+            .get_with_ts(key, self.read_ts, options.block_read_options())
+    }
+
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.multi_get_with_options(keys, &ReadOptions::default())
+    }
+
+    /// Get several keys at once. Keys without a local write are read from the storage together.
+    pub fn multi_get_with_options(
+        &self,
+        keys: &[&[u8]],
+        options: &ReadOptions,
+    ) -> Result<Vec<Option<Bytes>>> {
+        if self.committed.load(Ordering::SeqCst) {
+            panic!("cannot operate on committed txn!");
+        }
+        self.check_read_ts(options)?;
+        if let Some(guard) = &self.key_hashes {
+            let mut guard = guard.lock();
+            let (_, read_set) = &mut *guard;
+            read_set.extend(keys.iter().map(|key| farmhash::hash32(key)));
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        let mut storage_keys = Vec::new();
+        for key in keys {
+            values.push(self.local_storage.get(*key).map(|entry| {
+                let (value_type, value) = entry.value();
+                (*value_type != ValueType::Delete).then(|| value.clone())
+            }));
+            if values.last().unwrap().is_none() {
+                storage_keys.push(*key);
+            }
+        }
+        let mut storage_values = self
+            .inner
+            .multi_get_with_ts(&storage_keys, self.read_ts, options.block_read_options())?
+            .into_iter();
+        Ok(values
+            .into_iter()
+            .map(|value| value.unwrap_or_else(|| storage_values.next().unwrap()))
+            .collect())
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub fn scan_with_options(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        self.scan_inner(lower, upper, None, options)
+    }
+
+    /// Create an iterator over every key starting with `prefix`.
+    pub fn scan_prefix(self: &Arc<Self>, prefix: &[u8]) -> Result<TxnIterator> {
+        self.scan_prefix_with_options(prefix, &ReadOptions::default())
+    }
+
+    pub fn scan_prefix_with_options(
+        self: &Arc<Self>,
+        prefix: &[u8],
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = match &upper {
+            Some(upper) => Bound::Excluded(&upper[..]),
+            None => Bound::Unbounded,
+        };
+        self.scan_inner(Bound::Included(prefix), upper, Some(prefix), options)
+    }
+
+    /// Create an iterator over a range of keys, all starting with `prefix` if it is set.
+    fn scan_inner(
+        self: &Arc<Self>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<TxnIterator> {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
+        self.check_read_ts(options)?;
+        let lower =
+            tighter_lower_bound(lower, options.iterate_lower_bound.as_ref().map(|x| &x[..]));
+        let upper =
+            tighter_upper_bound(upper, options.iterate_upper_bound.as_ref().map(|x| &x[..]));
+        let bounds = (map_bound(lower), map_bound(upper));
         let mut local_iter = TxnLocalIteratorBuilder {
             map: self.local_storage.clone(),
             iter_builder: |map| map.range((map_bound(lower), map_bound(upper))),
-            item: (Bytes::new(), Bytes::new()),
+            item: (Bytes::new(), ValueType::Put, Bytes::new()),
+            bounds,
         }
         .build();
         let entry = local_iter.with_iter_mut(|iter| TxnLocalIterator::entry_to_item(iter.next()));
@@ -67,17 +164,36 @@ impl Transaction {
             self.clone(),
             TwoMergeIterator::create(
                 local_iter,
-                self.inner.scan_with_ts(lower, upper, self.read_ts)?,
+                self.inner.scan_with_ts(
+                    lower,
+                    upper,
+                    prefix,
+                    self.read_ts,
+                    options.block_read_options(),
+                )?,
             )?,
         )
     }
 
+    fn check_read_ts(&self, options: &ReadOptions) -> Result<()> {
+        match options.pinned_read_ts()? {
+            Some(read_ts) if read_ts != self.read_ts => bail!(
+                "transaction reads at ts {}, cannot read at ts {}",
+                self.read_ts,
+                read_ts
+            ),
+            _ => Ok(()),
+        }
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (ValueType::Put, Bytes::copy_from_slice(value)),
+        );
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
@@ -89,8 +205,10 @@ impl Transaction {
         if self.committed.load(Ordering::SeqCst) {
             panic!("cannot operate on committed txn!");
         }
-        self.local_storage
-            .insert(Bytes::copy_from_slice(key), Bytes::new());
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (ValueType::Delete, Bytes::new()),
+        );
         if let Some(key_hashes) = &self.key_hashes {
             let mut key_hashes = key_hashes.lock();
             let (write_hashes, _) = &mut *key_hashes;
@@ -99,10 +217,18 @@ impl Transaction {
     }
 
     pub fn commit(&self) -> Result<()> {
+        self.commit_with_options(&WriteOptions::default())
+    }
+
+    pub fn commit_with_options(&self, options: &WriteOptions) -> Result<()> {
         self.committed
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .expect("cannot operate on committed txn!");
-        let _commit_lock = self.inner.mvcc().commit_lock.lock();
+        // Only the serializable check needs commits to go one at a time, others may share a write group.
+        let _commit_lock = self
+            .key_hashes
+            .is_some()
+            .then(|| self.inner.mvcc().commit_lock.lock());
         let serializability_check;
         if let Some(guard) = &self.key_hashes {
             let guard = guard.lock();
@@ -129,14 +255,15 @@ impl Transaction {
             .local_storage
             .iter()
             .map(|entry| {
-                if entry.value().is_empty() {
+                let (value_type, value) = entry.value();
+                if *value_type == ValueType::Delete {
                     WriteBatchRecord::Del(entry.key().clone())
                 } else {
-                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+                    WriteBatchRecord::Put(entry.key().clone(), value.clone())
                 }
             })
             .collect::<Vec<_>>();
-        let ts = self.inner.write_batch_inner(&batch)?;
+        let ts = self.inner.write_batch_inner(&batch, options)?;
         if serializability_check {
             let mut committed_txns = self.inner.mvcc().committed_txns.lock();
             let mut key_hashes = self.key_hashes.as_ref().unwrap().lock();
@@ -172,26 +299,53 @@ impl Drop for Transaction {
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    Bytes,
+    (Bound<Bytes>, Bound<Bytes>),
+    Bytes,
+    (ValueType, Bytes),
+>;
 
 #[self_referencing]
 pub struct TxnLocalIterator {
     /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<Bytes, (ValueType, Bytes)>>,
     /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (Bytes, Bytes),
+    /// Stores the current key, value type and value.
+    item: (Bytes, ValueType, Bytes),
+    /// Stores the range of the iterator, to move backward within it.
+    bounds: (Bound<Bytes>, Bound<Bytes>),
 }
 
 impl TxnLocalIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, Bytes, (ValueType, Bytes)>>,
+    ) -> (Bytes, ValueType, Bytes) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::new(), Bytes::new()))
+            .map(|x| (x.key().clone(), x.value().0, x.value().1.clone()))
+            .unwrap_or_else(|| (Bytes::new(), ValueType::Put, Bytes::new()))
+    }
+
+    /// Move to the last entry at or before `upper` within the range of the iterator. The skipmap iterator is
+    /// restarted right after it, so that `next` moves forward from there.
+    fn seek_backward_to(&mut self, upper: Bound<&[u8]>) {
+        self.with_mut(|x| {
+            let entry = x.map.upper_bound(upper).filter(|entry| match &x.bounds.0 {
+                Bound::Included(lower) => entry.key() >= lower,
+                Bound::Excluded(lower) => entry.key() > lower,
+                Bound::Unbounded => true,
+            });
+            *x.item = TxnLocalIterator::entry_to_item(entry);
+            let lower = match x.item.0.is_empty() {
+                true => x.bounds.0.clone(),
+                false => Bound::Excluded(x.item.0.clone()),
+            };
+            *x.iter = x.map.range((lower, x.bounds.1.clone()));
+        });
     }
 }
 
@@ -199,7 +353,11 @@ impl StorageIterator for TxnLocalIterator {
     type KeyType<'a> = &'a [u8];
 
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().2[..]
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.borrow_item().1
     }
 
     fn key(&self) -> &[u8] {
@@ -215,6 +373,50 @@ impl StorageIterator for TxnLocalIterator {
         self.with_mut(|x| *x.item = entry);
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        let key = self.borrow_item().0.clone();
+        self.seek_backward_to(Bound::Excluded(&key));
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.with_mut(|x| {
+            let lower = match &x.bounds.0 {
+                Bound::Included(lower) | Bound::Excluded(lower) if key <= lower => {
+                    x.bounds.0.clone()
+                }
+                _ => Bound::Included(Bytes::copy_from_slice(key)),
+            };
+            *x.iter = x.map.range((lower, x.bounds.1.clone()));
+        });
+        self.next()
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.with_mut(|x| *x.iter = x.map.range(x.bounds.clone()));
+        self.next()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let upper = self.borrow_bounds().1.clone();
+        self.seek_backward_to(upper.as_ref().map(|x| &x[..]));
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let below_upper = match &self.borrow_bounds().1 {
+            Bound::Included(upper) => key <= upper,
+            Bound::Excluded(upper) => key < upper,
+            Bound::Unbounded => true,
+        };
+        if below_upper {
+            self.seek_backward_to(Bound::Included(key));
+            Ok(())
+        } else {
+            self.seek_to_last()
+        }
+    }
 }
 
 pub struct TxnIterator {
@@ -228,16 +430,20 @@ impl TxnIterator {
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
         let mut iter = Self { txn, iter };
-        iter.skip_deletes()?;
-        if iter.is_valid() {
-            iter.add_to_read_set(iter.key());
-        }
+        iter.skip_deletes(Direction::Forward)?;
         Ok(iter)
     }
 
-    fn skip_deletes(&mut self) -> Result<()> {
-        while self.iter.is_valid() && self.iter.value().is_empty() {
-            self.iter.next()?;
+    /// Skip the keys deleted by the transaction in `direction`, and add the key landed on to the read set.
+    fn skip_deletes(&mut self, direction: Direction) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value_type() == ValueType::Delete {
+            match direction {
+                Direction::Forward => self.iter.next()?,
+                Direction::Backward => self.iter.prev()?,
+            }
+        }
+        if self.is_valid() {
+            self.add_to_read_set(self.key());
         }
         Ok(())
     }
@@ -268,11 +474,32 @@ impl StorageIterator for TxnIterator {
 
     fn next(&mut self) -> Result<()> {
         self.iter.next()?;
-        self.skip_deletes()?;
-        if self.is_valid() {
-            self.add_to_read_set(self.key());
-        }
-        Ok(())
+        self.skip_deletes(Direction::Forward)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.iter.prev()?;
+        self.skip_deletes(Direction::Backward)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)?;
+        self.skip_deletes(Direction::Forward)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()?;
+        self.skip_deletes(Direction::Forward)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()?;
+        self.skip_deletes(Direction::Backward)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_for_prev(key)?;
+        self.skip_deletes(Direction::Backward)
     }
 
     fn num_active_iterators(&self) -> usize {
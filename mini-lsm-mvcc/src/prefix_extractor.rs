@@ -0,0 +1,84 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+/// Extracts a prefix from keys, to filter SSTs and memtables by prefix in prefix scans.
+///
+/// The prefix of every key is hashed into the bloom filter of an SST next to the key itself, and the extractor
+/// is recorded in the SST so that the filter is only used for prefixes extracted the same way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrefixExtractor {
+    /// The first `n` bytes of the key. Shorter keys have no prefix.
+    FixedLength(usize),
+    /// The key up to and including the `count`-th `delimiter`, e.g. `tenant/` of `tenant/entity/id` with one
+    /// `/`. Keys with fewer delimiters have no prefix.
+    Delimited { delimiter: u8, count: usize },
+}
+
+impl PrefixExtractor {
+    /// Get the prefix of `key`, or `None` if it has none.
+    pub fn extract<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        match *self {
+            PrefixExtractor::FixedLength(len) => key.get(..len),
+            PrefixExtractor::Delimited { delimiter, count } => {
+                if count == 0 {
+                    return None;
+                }
+                let (idx, _) = key
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &byte)| byte == delimiter)
+                    .nth(count - 1)?;
+                Some(&key[..=idx])
+            }
+        }
+    }
+
+    /// Encode an optional prefix extractor to a buffer: a tag (u8) of 0 for none, 1 for fixed length followed
+    /// by the length (u32), or 2 for delimited followed by the delimiter (u8) and count (u32), then the
+    /// checksum of the section (u32).
+    pub fn encode(prefix_extractor: Option<&Self>, buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        match prefix_extractor {
+            None => buf.put_u8(0),
+            Some(PrefixExtractor::FixedLength(len)) => {
+                buf.put_u8(1);
+                buf.put_u32(*len as u32);
+            }
+            Some(PrefixExtractor::Delimited { delimiter, count }) => {
+                buf.put_u8(2);
+                buf.put_u8(*delimiter);
+                buf.put_u32(*count as u32);
+            }
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    /// Decode an optional prefix extractor from a buffer.
+    pub fn decode(mut buf: &[u8]) -> Result<Option<Self>> {
+        if buf.remaining() < 5 {
+            bail!("prefix extractor section too short");
+        }
+        let checksum = (&buf[buf.remaining() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.remaining() - 4]) {
+            bail!("prefix extractor checksum mismatched");
+        }
+        Ok(match buf.get_u8() {
+            0 => None,
+            1 => Some(PrefixExtractor::FixedLength(buf.get_u32() as usize)),
+            2 => Some(PrefixExtractor::Delimited {
+                delimiter: buf.get_u8(),
+                count: buf.get_u32() as usize,
+            }),
+            tag => bail!("unknown prefix extractor {}", tag),
+        })
+    }
+}
+
+/// The smallest key greater than every key starting with `prefix`, or `None` if there is none, i.e. the prefix
+/// is empty or only made of `0xff`.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let idx = prefix.iter().rposition(|&byte| byte != u8::MAX)?;
+    let mut upper = prefix[..=idx].to_vec();
+    upper[idx] += 1;
+    Some(upper)
+}
@@ -0,0 +1,126 @@
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::key::{KeyBytes, TS_RANGE_BEGIN};
+
+/// Deletes every version of the keys in `[begin, end)` written before `ts`.
+///
+/// A range tombstone lives next to the point entries: in the memtable (and its WAL), and in a dedicated
+/// section of the SST. Readers at or above `ts` treat every covered version older than `ts` as deleted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub begin: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(begin: &[u8], end: &[u8], ts: u64) -> Self {
+        Self {
+            begin: Bytes::copy_from_slice(begin),
+            end: Bytes::copy_from_slice(end),
+            ts,
+        }
+    }
+
+    /// Whether `key` falls into the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.begin.as_ref() <= key && key < self.end.as_ref()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by this tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.contains(key)
+    }
+
+    /// Whether the range intersects with the user key range `[lower, upper]`.
+    pub fn overlaps(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+        let below_upper = match upper {
+            Bound::Included(key) => self.begin.as_ref() <= key,
+            Bound::Excluded(key) => self.begin.as_ref() < key,
+            Bound::Unbounded => true,
+        };
+        let above_lower = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => key < self.end.as_ref(),
+            Bound::Unbounded => true,
+        };
+        below_upper && above_lower
+    }
+
+    /// Restrict the range to `[lower, upper)`, returns `None` if nothing is left.
+    pub fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<Self> {
+        let begin = match lower {
+            Some(lower) if lower > self.begin.as_ref() => Bytes::copy_from_slice(lower),
+            _ => self.begin.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < self.end.as_ref() => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        if begin >= end {
+            return None;
+        }
+        Some(Self {
+            begin,
+            end,
+            ts: self.ts,
+        })
+    }
+
+    /// The smallest key of the range.
+    pub fn first_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.begin.clone(), TS_RANGE_BEGIN)
+    }
+
+    /// A key that is greater than every key of the range. As the range excludes `end`, this is the smallest key
+    /// of `end`.
+    pub fn last_key(&self) -> KeyBytes {
+        KeyBytes::from_bytes_with_ts(self.end.clone(), TS_RANGE_BEGIN)
+    }
+
+    /// Encode range tombstones to a buffer: number of tombstones (u32), then for each tombstone begin_len
+    /// (u32) | begin | end_len (u32) | end | ts (u64), then the checksum of the section (u32).
+    pub fn encode_range_tombstones(range_tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(range_tombstones.len() as u32);
+        for tombstone in range_tombstones {
+            buf.put_u32(tombstone.begin.len() as u32);
+            buf.put_slice(&tombstone.begin);
+            buf.put_u32(tombstone.end.len() as u32);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.ts);
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    /// Decode range tombstones from a buffer.
+    pub fn decode_range_tombstones(mut buf: &[u8]) -> Result<Vec<RangeTombstone>> {
+        let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
+        let num = buf.get_u32() as usize;
+        let mut range_tombstones = Vec::with_capacity(num);
+        for _ in 0..num {
+            let begin_len = buf.get_u32() as usize;
+            let begin = buf.copy_to_bytes(begin_len);
+            let end_len = buf.get_u32() as usize;
+            let end = buf.copy_to_bytes(end_len);
+            let ts = buf.get_u64();
+            range_tombstones.push(RangeTombstone { begin, end, ts });
+        }
+        if buf.get_u32() != checksum {
+            bail!("range tombstones checksum mismatched");
+        }
+        Ok(range_tombstones)
+    }
+}
+
+/// Returns the newest timestamp among the tombstones that contain `key`, or `None` if no tombstone does. A
+/// version of `key` is deleted if its timestamp is below the returned one.
+pub fn max_covering_ts(range_tombstones: &[RangeTombstone], key: &[u8]) -> Option<u64> {
+    range_tombstones
+        .iter()
+        .filter(|tombstone| tombstone.contains(key))
+        .map(|tombstone| tombstone.ts)
+        .max()
+}
@@ -1,5 +1,7 @@
 pub(crate) mod bloom;
 mod builder;
+mod compression;
+pub mod filter;
 mod iterator;
 
 use std::fs::File;
@@ -9,13 +11,49 @@ use std::sync::Arc;
 use anyhow::{anyhow, bail, Result};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut};
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
 
 use crate::block::Block;
 use crate::key::{KeyBytes, KeySlice};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
 
 use self::bloom::Bloom;
+use self::filter::Filter;
+
+/// The SST format written by this version.
+///
+/// Version 1 is the original format, without a footer: its blocks hold u16 lengths and offsets with no restart
+/// points, codec or value type, an empty value being a deletion, and the block meta holds u16 key lengths. It has
+/// no range tombstone section, and always has a bloom filter. Version 2 adds the footer and the current block
+/// layout, tags deletions with `ValueType::Delete`, and has u32 key lengths in the block meta, a range tombstone
+/// section, the kind of the filter, which may be absent, and the prefix extractor whose prefixes are in the
+/// filter, in a section after it.
+pub(crate) const SST_FORMAT_VERSION: u32 = 2;
+
+/// Written at the very end of SSTs of version 2 and above, after the format version.
+const SST_MAGIC: u32 = 0x4d4c_534d;
+
+/// How blocks are read from an SST.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockReadOptions {
+    /// Insert the blocks read from disk into the block cache. Blocks read without verifying their checksum
+    /// are never inserted.
+    pub fill_cache: bool,
+    /// Check the checksum of the blocks read from disk.
+    pub verify_checksums: bool,
+}
+
+impl Default for BlockReadOptions {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            verify_checksums: true,
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -35,11 +73,11 @@ impl BlockMeta {
             // The size of offset
             estimated_size += std::mem::size_of::<u32>();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += std::mem::size_of::<u32>();
             // The size of actual key
             estimated_size += meta.first_key.raw_len();
             // The size of key length
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += std::mem::size_of::<u32>();
             // The size of actual key
             estimated_size += meta.last_key.raw_len();
         }
@@ -53,10 +91,10 @@ impl BlockMeta {
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put_u32(meta.first_key.key_len() as u32);
             buf.put_slice(meta.first_key.key_ref());
             buf.put_u64(meta.first_key.ts());
-            buf.put_u16(meta.last_key.key_len() as u16);
+            buf.put_u32(meta.last_key.key_len() as u32);
             buf.put_slice(meta.last_key.key_ref());
             buf.put_u64(meta.last_key.ts());
         }
@@ -66,16 +104,31 @@ impl BlockMeta {
     }
 
     /// Decode block meta from a buffer.
-    pub fn decode_block_meta(mut buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+    pub fn decode_block_meta(buf: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+        Self::decode_block_meta_of_version(buf, SST_FORMAT_VERSION)
+    }
+
+    /// Decode block meta written in `format_version`. Key lengths are u16 in version 1.
+    fn decode_block_meta_of_version(
+        mut buf: &[u8],
+        format_version: u32,
+    ) -> Result<(Vec<BlockMeta>, u64)> {
+        let get_key_len = |buf: &mut &[u8]| {
+            if format_version < 2 {
+                buf.get_u16() as usize
+            } else {
+                buf.get_u32() as usize
+            }
+        };
         let mut block_meta = Vec::new();
         let num = buf.get_u32() as usize;
         let checksum = crc32fast::hash(&buf[..buf.remaining() - 4]);
         for _ in 0..num {
             let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let first_key_len = get_key_len(&mut buf);
             let first_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(first_key_len), buf.get_u64());
-            let last_key_len: usize = buf.get_u16() as usize;
+            let last_key_len = get_key_len(&mut buf);
             let last_key =
                 KeyBytes::from_bytes_with_ts(buf.copy_to_bytes(last_key_len), buf.get_u64());
             block_meta.push(BlockMeta {
@@ -140,8 +193,14 @@ pub struct SsTable {
     block_cache: Option<Arc<BlockCache>>,
     first_key: KeyBytes,
     last_key: KeyBytes,
-    pub(crate) bloom: Option<Bloom>,
+    pub(crate) filter: Option<Filter>,
+    /// The extractor of the prefixes in the filter, if any.
+    pub(crate) prefix_extractor: Option<PrefixExtractor>,
     max_ts: u64,
+    /// Range tombstones, stored in their own section between the block meta and the filter.
+    pub(crate) range_tombstones: Vec<RangeTombstone>,
+    /// The on-disk format version the SST was written in.
+    pub(crate) format_version: u32,
 }
 impl SsTable {
     #[cfg(test)]
@@ -151,28 +210,93 @@ impl SsTable {
 
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let len = file.size();
-        let raw_bloom_offset = file.read(len - 4, 4)?;
-        let bloom_offset = (&raw_bloom_offset[..]).get_u32() as u64;
-        let raw_bloom = file.read(bloom_offset, len - 4 - bloom_offset)?;
-        let bloom_filter = Bloom::decode(&raw_bloom)?;
-        let raw_meta_offset = file.read(bloom_offset - 4, 4)?;
+        let raw_footer = file.read(file.size() - 8, 8)?;
+        let (format_version, len) = if (&raw_footer[4..]).get_u32() == SST_MAGIC {
+            ((&raw_footer[..4]).get_u32(), file.size() - 8)
+        } else {
+            (1, file.size())
+        };
+        if format_version > SST_FORMAT_VERSION {
+            bail!("unsupported SST format version {}", format_version);
+        }
+        let (prefix_extractor, len) = if format_version >= 2 {
+            let raw_prefix_extractor_offset = file.read(len - 4, 4)?;
+            let prefix_extractor_offset = (&raw_prefix_extractor_offset[..]).get_u32() as u64;
+            let raw_prefix_extractor =
+                file.read(prefix_extractor_offset, len - 4 - prefix_extractor_offset)?;
+            (
+                PrefixExtractor::decode(&raw_prefix_extractor)?,
+                prefix_extractor_offset,
+            )
+        } else {
+            (None, len)
+        };
+        let raw_filter_offset = file.read(len - 4, 4)?;
+        let filter_offset = (&raw_filter_offset[..]).get_u32() as u64;
+        let raw_filter = file.read(filter_offset, len - 4 - filter_offset)?;
+        let filter = if format_version >= 2 {
+            Filter::decode(&raw_filter)?
+        } else {
+            Some(Filter::Bloom(Bloom::decode(&raw_filter)?))
+        };
+        let (range_tombstones, len) = if format_version >= 2 {
+            let raw_range_tombstones_offset = file.read(filter_offset - 4, 4)?;
+            let range_tombstones_offset = (&raw_range_tombstones_offset[..]).get_u32() as u64;
+            let raw_range_tombstones = file.read(
+                range_tombstones_offset,
+                filter_offset - 4 - range_tombstones_offset,
+            )?;
+            (
+                RangeTombstone::decode_range_tombstones(&raw_range_tombstones)?,
+                range_tombstones_offset,
+            )
+        } else {
+            (Vec::new(), filter_offset)
+        };
+        let raw_meta_offset = file.read(len - 4, 4)?;
         let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, bloom_offset - 4 - block_meta_offset)?;
-        let (block_meta, max_ts) = BlockMeta::decode_block_meta(&raw_meta[..])?;
+        let raw_meta = file.read(block_meta_offset, len - 4 - block_meta_offset)?;
+        let (block_meta, max_ts) =
+            BlockMeta::decode_block_meta_of_version(&raw_meta[..], format_version)?;
+        let (first_key, last_key) = Self::key_range(&block_meta, &range_tombstones);
         Ok(Self {
             file,
-            first_key: block_meta.first().unwrap().first_key.clone(),
-            last_key: block_meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta,
             block_meta_offset: block_meta_offset as usize,
             id,
             block_cache,
-            bloom: Some(bloom_filter),
+            filter,
+            prefix_extractor,
             max_ts,
+            range_tombstones,
+            format_version,
         })
     }
 
+    /// The key range of an SST spans both its entries and its range tombstones.
+    fn key_range(
+        block_meta: &[BlockMeta],
+        range_tombstones: &[RangeTombstone],
+    ) -> (KeyBytes, KeyBytes) {
+        let first_key = block_meta
+            .first()
+            .map(|meta| meta.first_key.clone())
+            .into_iter()
+            .chain(range_tombstones.iter().map(RangeTombstone::first_key))
+            .min()
+            .expect("SST should not be empty");
+        let last_key = block_meta
+            .last()
+            .map(|meta| meta.last_key.clone())
+            .into_iter()
+            .chain(range_tombstones.iter().map(RangeTombstone::last_key))
+            .max()
+            .expect("SST should not be empty");
+        (first_key, last_key)
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -188,13 +312,20 @@ impl SsTable {
             block_cache: None,
             first_key,
             last_key,
-            bloom: None,
+            filter: None,
+            prefix_extractor: None,
             max_ts: 0,
+            range_tombstones: Vec::new(),
+            format_version: SST_FORMAT_VERSION,
         }
     }
 
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        self.read_block_inner(block_idx, true)
+    }
+
+    fn read_block_inner(&self, block_idx: usize, verify_checksum: bool) -> Result<Arc<Block>> {
         let offset = self.block_meta[block_idx].offset;
         let offset_end = self
             .block_meta
@@ -206,21 +337,43 @@ impl SsTable {
             .read(offset as u64, (offset_end - offset) as u64)?;
         let block_data = &block_data_with_chksum[..block_len];
         let checksum = (&block_data_with_chksum[block_len..]).get_u32();
-        if checksum != crc32fast::hash(block_data) {
+        if verify_checksum && checksum != crc32fast::hash(block_data) {
             bail!("block checksum mismatched");
         }
-        Ok(Arc::new(Block::decode(block_data)))
+        if self.format_version < 2 {
+            return Ok(Arc::new(Block::decode_v1(block_data)));
+        }
+        // The last byte before the checksum records the codec of this block.
+        let compression = CompressionType::from_u8(block_data[block_len - 1])?;
+        let block_data = compression.decompress(&block_data[..block_len - 1])?;
+        Ok(Arc::new(Block::decode(&block_data)))
     }
 
     /// Read a block from disk, with block cache.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
-            Ok(blk)
-        } else {
-            self.read_block(block_idx)
+        self.read_block_with_options(block_idx, BlockReadOptions::default())
+    }
+
+    /// Read a block from the block cache, or from disk on a miss.
+    pub fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        options: BlockReadOptions,
+    ) -> Result<Arc<Block>> {
+        match self.block_cache {
+            Some(ref block_cache) if options.fill_cache && options.verify_checksums => {
+                let blk = block_cache
+                    .try_get_with((self.id, block_idx), || {
+                        self.read_block_inner(block_idx, options.verify_checksums)
+                    })
+                    .map_err(|e| anyhow!("{}", e))?;
+                Ok(blk)
+            }
+            Some(ref block_cache) => match block_cache.get(&(self.id, block_idx)) {
+                Some(blk) => Ok(blk),
+                None => self.read_block_inner(block_idx, options.verify_checksums),
+            },
+            None => self.read_block_inner(block_idx, options.verify_checksums),
         }
     }
 
@@ -231,6 +384,17 @@ impl SsTable {
             .saturating_sub(1)
     }
 
+    /// Whether the SST may have keys whose prefix extracted by `prefix_extractor` hashes to `prefix_hash`. The
+    /// filter only answers for the extractor the SST was written with.
+    pub fn may_contain_prefix(&self, prefix_extractor: &PrefixExtractor, prefix_hash: u32) -> bool {
+        match &self.filter {
+            Some(filter) if self.prefix_extractor.as_ref() == Some(prefix_extractor) => {
+                filter.may_contain(prefix_hash)
+            }
+            _ => true,
+        }
+    }
+
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_meta.len()
@@ -255,4 +419,12 @@ impl SsTable {
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// The filter of the SST if it is a bloom filter.
+    pub fn bloom(&self) -> Option<&Bloom> {
+        match &self.filter {
+            Some(Filter::Bloom(bloom)) => Some(bloom),
+            _ => None,
+        }
+    }
 }
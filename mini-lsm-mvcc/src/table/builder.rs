@@ -4,11 +4,13 @@ use std::sync::Arc;
 use anyhow::Result;
 use bytes::BufMut;
 
-use super::bloom::Bloom;
-use super::{BlockMeta, FileObject, SsTable};
-use crate::block::BlockBuilder;
-use crate::key::{KeySlice, KeyVec};
+use super::filter::{BloomFilterPolicy, Filter, FilterPolicy};
+use super::{BlockMeta, CompressionType, FileObject, SsTable, SST_FORMAT_VERSION, SST_MAGIC};
+use crate::block::{BlockBuilder, DEFAULT_RESTART_INTERVAL};
+use crate::key::{KeySlice, KeyVec, ValueType};
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
@@ -19,7 +21,15 @@ pub struct SsTableBuilder {
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
     key_hashes: Vec<u32>,
+    prefix_extractor: Option<PrefixExtractor>,
+    /// Hashes of the prefixes of the keys, each prefix hashed once.
+    prefix_hashes: Vec<u32>,
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
     max_ts: u64,
+    compression: CompressionType,
+    restart_interval: usize,
+    hash_index: bool,
+    range_tombstones: Vec<RangeTombstone>,
 }
 
 impl SsTableBuilder {
@@ -33,12 +43,63 @@ impl SsTableBuilder {
             block_size,
             builder: BlockBuilder::new(block_size),
             key_hashes: Vec::new(),
+            prefix_extractor: None,
+            prefix_hashes: Vec::new(),
+            filter_policy: Some(Arc::new(BloomFilterPolicy::default())),
             max_ts: 0,
+            compression: CompressionType::None,
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            hash_index: false,
+            range_tombstones: Vec::new(),
         }
     }
 
-    /// Adds a key-value pair to SSTable
+    /// Set the codec used to compress data blocks.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the number of entries between two restart points in data blocks.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        self.restart_interval = restart_interval;
+        self.builder = self.new_block_builder();
+        self
+    }
+
+    /// Build a hash index in each data block for point lookups.
+    pub fn with_hash_index(mut self, hash_index: bool) -> Self {
+        self.hash_index = hash_index;
+        self.builder = self.new_block_builder();
+        self
+    }
+
+    /// Add the prefixes extracted from the keys to the filter, for prefix scans.
+    pub fn with_prefix_extractor(mut self, prefix_extractor: Option<PrefixExtractor>) -> Self {
+        self.prefix_extractor = prefix_extractor;
+        self
+    }
+
+    /// Set the policy of the filter built over the keys, or build no filter.
+    pub fn with_filter_policy(mut self, filter_policy: Option<Arc<dyn FilterPolicy>>) -> Self {
+        self.filter_policy = filter_policy;
+        self
+    }
+
+    fn new_block_builder(&self) -> BlockBuilder {
+        BlockBuilder::new(self.block_size)
+            .with_restart_interval(self.restart_interval)
+            .with_hash_index(self.hash_index)
+    }
+
+    /// Adds a key-value pair to SSTable, an empty value being a deletion. Use `add_with_type` to add an empty
+    /// value.
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.add_with_type(key, ValueType::of_untyped_value(value), value)
+    }
+
+    /// Adds a key-value pair of the given value type to SSTable
+    pub fn add_with_type(&mut self, key: KeySlice, value_type: ValueType, value: &[u8]) {
         if self.first_key.is_empty() {
             self.first_key.set_from_slice(key);
         }
@@ -47,8 +108,17 @@ impl SsTableBuilder {
             self.max_ts = key.ts();
         }
         self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        if let Some(prefix_extractor) = &self.prefix_extractor {
+            if let Some(prefix) = prefix_extractor.extract(key.key_ref()) {
+                // Keys come in order, so the keys sharing a prefix are next to each other.
+                let prefix_hash = farmhash::fingerprint32(prefix);
+                if self.prefix_hashes.last() != Some(&prefix_hash) {
+                    self.prefix_hashes.push(prefix_hash);
+                }
+            }
+        }
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_type(key, value_type, value) {
             self.last_key.set_from_slice(key);
             return;
         }
@@ -57,26 +127,42 @@ impl SsTableBuilder {
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_type(key, value_type, value));
         self.first_key.set_from_slice(key);
         self.last_key.set_from_slice(key);
     }
 
+    /// Adds a range tombstone to SSTable
+    pub fn add_range_tombstone(&mut self, range_tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(range_tombstone.ts);
+        self.range_tombstones.push(range_tombstone);
+    }
+
+    /// Whether nothing has been added to the builder.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
+    }
+
     /// Get the estimated size of the SSTable.
     pub fn estimated_size(&self) -> usize {
         self.data.len()
     }
 
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+        let new_builder = self.new_block_builder();
+        let builder = std::mem::replace(&mut self.builder, new_builder);
         let encoded_block = builder.build().encode();
+        let (compression, payload) = self.compression.compress(&encoded_block);
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into_key_bytes(),
             last_key: std::mem::take(&mut self.last_key).into_key_bytes(),
         });
-        let checksum = crc32fast::hash(&encoded_block);
-        self.data.extend(encoded_block);
+        let block_offset = self.data.len();
+        self.data.extend(payload);
+        // Record the codec after each block so that blocks are self-describing.
+        self.data.put_u8(compression.to_u8());
+        let checksum = crc32fast::hash(&self.data[block_offset..]);
         self.data.put_u32(checksum);
     }
 
@@ -87,29 +173,45 @@ impl SsTableBuilder {
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        // An SST may hold nothing but range tombstones.
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
         let mut buf = self.data;
         let meta_offset = buf.len();
         BlockMeta::encode_block_meta(&self.meta, self.max_ts, &mut buf);
         buf.put_u32(meta_offset as u32);
-        let bloom = Bloom::build_from_key_hashes(
-            &self.key_hashes,
-            Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01),
-        );
-        let bloom_offset = buf.len();
-        bloom.encode(&mut buf);
-        buf.put_u32(bloom_offset as u32);
+        let range_tombstones_offset = buf.len();
+        RangeTombstone::encode_range_tombstones(&self.range_tombstones, &mut buf);
+        buf.put_u32(range_tombstones_offset as u32);
+        let mut key_hashes = self.key_hashes;
+        key_hashes.extend(&self.prefix_hashes);
+        let filter_offset = buf.len();
+        let filter = self
+            .filter_policy
+            .map(|filter_policy| filter_policy.build(&key_hashes));
+        Filter::encode(filter.as_ref(), &mut buf);
+        buf.put_u32(filter_offset as u32);
+        let prefix_extractor_offset = buf.len();
+        PrefixExtractor::encode(self.prefix_extractor.as_ref(), &mut buf);
+        buf.put_u32(prefix_extractor_offset as u32);
+        buf.put_u32(SST_FORMAT_VERSION);
+        buf.put_u32(SST_MAGIC);
         let file = FileObject::create(path.as_ref(), buf)?;
+        let (first_key, last_key) = SsTable::key_range(&self.meta, &self.range_tombstones);
         Ok(SsTable {
             id,
             file,
-            first_key: self.meta.first().unwrap().first_key.clone(),
-            last_key: self.meta.last().unwrap().last_key.clone(),
+            first_key,
+            last_key,
             block_meta: self.meta,
             block_meta_offset: meta_offset,
             block_cache,
-            bloom: Some(bloom),
+            filter,
+            prefix_extractor: self.prefix_extractor,
             max_ts: self.max_ts,
+            range_tombstones: self.range_tombstones,
+            format_version: SST_FORMAT_VERSION,
         })
     }
 
@@ -0,0 +1,59 @@
+use anyhow::{bail, Result};
+
+/// The codec used to compress a data block. The codec is recorded next to each block on disk, so SSTs
+/// written with different codecs (or files mixing codecs) can be read regardless of the current option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Store blocks as-is.
+    #[default]
+    None,
+    /// LZ4, fast compression and decompression with a moderate ratio.
+    Lz4,
+    /// Zstandard, slower than LZ4 but with a much higher compression ratio.
+    Zstd,
+}
+
+/// The zstd level used for data blocks.
+const ZSTD_LEVEL: i32 = 3;
+
+impl CompressionType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+        }
+    }
+
+    pub(crate) fn from_u8(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Zstd,
+            _ => bail!("unknown compression type {}", tag),
+        })
+    }
+
+    /// Compress an encoded block. Returns the codec actually used together with the payload: if the codec
+    /// fails or does not make the block smaller, the block is stored uncompressed.
+    pub(crate) fn compress(self, data: &[u8]) -> (CompressionType, Vec<u8>) {
+        let compressed = match self {
+            CompressionType::None => None,
+            CompressionType::Lz4 => Some(lz4_flex::block::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+        };
+        match compressed {
+            Some(compressed) if compressed.len() < data.len() => (self, compressed),
+            _ => (CompressionType::None, data.to_vec()),
+        }
+    }
+
+    /// Decompress a block payload written with this codec.
+    pub(crate) fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            CompressionType::None => data.to_vec(),
+            CompressionType::Lz4 => lz4_flex::block::decompress_size_prepended(data)?,
+            CompressionType::Zstd => zstd::decode_all(data)?,
+        })
+    }
+}
@@ -0,0 +1,336 @@
+use std::fmt::Debug;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::bloom::{BitSlice, BitSliceMut, Bloom};
+
+/// Decides the filter built over the key hashes of an SST.
+///
+/// Every filter records its kind in the SST, so SSTs built with different policies can be read regardless of
+/// the current one.
+pub trait FilterPolicy: Debug + Send + Sync {
+    /// Build a filter from key hashes. A hash may appear several times.
+    fn build(&self, key_hashes: &[u32]) -> Filter;
+}
+
+/// The Bloom filter, with the bits of a key spread over the whole filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BloomFilterPolicy {
+    pub bits_per_key: usize,
+}
+
+impl BloomFilterPolicy {
+    /// Use as many bits per key as needed for a false positive rate.
+    pub fn with_false_positive_rate(false_positive_rate: f64) -> Self {
+        Self {
+            bits_per_key: Bloom::bloom_bits_per_key(1, false_positive_rate),
+        }
+    }
+}
+
+impl Default for BloomFilterPolicy {
+    fn default() -> Self {
+        Self::with_false_positive_rate(0.01)
+    }
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        Filter::Bloom(Bloom::build_from_key_hashes(key_hashes, self.bits_per_key))
+    }
+}
+
+/// A Bloom filter with the bits of a key all in one cache line, so that a probe touches a single cache line at
+/// the cost of a slightly higher false positive rate than the Bloom filter with as many bits per key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockedBloomFilterPolicy {
+    pub bits_per_key: usize,
+}
+
+impl FilterPolicy for BlockedBloomFilterPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        Filter::BlockedBloom(BlockedBloom::build_from_key_hashes(
+            key_hashes,
+            self.bits_per_key,
+        ))
+    }
+}
+
+/// An XOR filter with 8-bit fingerprints: about 9.8 bits per key for a false positive rate of 0.4%, less than a
+/// Bloom filter needs for the same rate, but it is built in several passes over the keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XorFilterPolicy;
+
+impl FilterPolicy for XorFilterPolicy {
+    fn build(&self, key_hashes: &[u32]) -> Filter {
+        Filter::Xor(XorFilter::build_from_key_hashes(key_hashes))
+    }
+}
+
+/// The filter of an SST, answering whether a key hash may have been added to it.
+pub enum Filter {
+    Bloom(Bloom),
+    BlockedBloom(BlockedBloom),
+    Xor(XorFilter),
+}
+
+impl Filter {
+    /// Check if the filter may contain a key hash.
+    pub fn may_contain(&self, h: u32) -> bool {
+        match self {
+            Filter::Bloom(bloom) => bloom.may_contain(h),
+            Filter::BlockedBloom(bloom) => bloom.may_contain(h),
+            Filter::Xor(xor) => xor.may_contain(h),
+        }
+    }
+
+    /// Encode an optional filter: its kind (u8), 0 for no filter, then the filter.
+    pub fn encode(filter: Option<&Self>, buf: &mut Vec<u8>) {
+        match filter {
+            None => buf.put_u8(0),
+            Some(Filter::Bloom(bloom)) => {
+                buf.put_u8(1);
+                bloom.encode(buf);
+            }
+            Some(Filter::BlockedBloom(bloom)) => {
+                buf.put_u8(2);
+                bloom.encode(buf);
+            }
+            Some(Filter::Xor(xor)) => {
+                buf.put_u8(3);
+                xor.encode(buf);
+            }
+        }
+    }
+
+    /// Decode an optional filter, of the kind recorded in the buffer.
+    pub fn decode(buf: &[u8]) -> Result<Option<Self>> {
+        if buf.is_empty() {
+            bail!("filter section too short");
+        }
+        Ok(match buf[0] {
+            0 => None,
+            1 => Some(Filter::Bloom(Bloom::decode(&buf[1..])?)),
+            2 => Some(Filter::BlockedBloom(BlockedBloom::decode(&buf[1..])?)),
+            3 => Some(Filter::Xor(XorFilter::decode(&buf[1..])?)),
+            tag => bail!("unknown filter type {}", tag),
+        })
+    }
+}
+
+/// Number of bits in a block of a blocked Bloom filter, a cache line.
+const BLOCK_BITS: usize = 512;
+
+/// Map a hash to `[0, n)`, using its high bits.
+fn reduce(h: u32, n: usize) -> usize {
+    ((h as u64 * n as u64) >> 32) as usize
+}
+
+/// Implements a cache-line-blocked Bloom filter: the first hash picks a block, and the probes of a key are
+/// within that block.
+pub struct BlockedBloom {
+    /// data of filter in bits, a multiple of the block size
+    pub(crate) filter: Bytes,
+    /// number of hash functions
+    pub(crate) k: u8,
+}
+
+impl BlockedBloom {
+    /// Build the filter from key hashes.
+    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
+        let k = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        let num_blocks = (keys.len() * bits_per_key).div_ceil(BLOCK_BITS).max(1);
+        let mut filter = BytesMut::zeroed(num_blocks * BLOCK_BITS / 8);
+        for &h in keys {
+            let block = reduce(h, num_blocks) * BLOCK_BITS;
+            let (mut h, delta) = Self::probe_hashes(h);
+            for _ in 0..k {
+                filter.set_bit(block + h as usize % BLOCK_BITS, true);
+                h = h.wrapping_add(delta);
+            }
+        }
+        Self {
+            filter: filter.freeze(),
+            k: k as u8,
+        }
+    }
+
+    /// The hashes of the probes within a block, independent of the high bits picking the block.
+    fn probe_hashes(h: u32) -> (u32, u32) {
+        let h = h.wrapping_mul(0x9e37_79b9).rotate_left(16);
+        (h, h.rotate_right(17))
+    }
+
+    /// Check if the filter may contain a key hash.
+    pub fn may_contain(&self, h: u32) -> bool {
+        let num_blocks = self.filter.bit_len() / BLOCK_BITS;
+        let block = reduce(h, num_blocks) * BLOCK_BITS;
+        let (mut h, delta) = Self::probe_hashes(h);
+        for _ in 0..self.k {
+            if !self.filter.get_bit(block + h as usize % BLOCK_BITS) {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Encode the filter: the bits, k (u8), then the checksum (u32).
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.extend(&self.filter);
+        buf.put_u8(self.k);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    /// Decode the filter.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() <= 5 || !(buf.len() - 5).is_multiple_of(BLOCK_BITS / 8) {
+            bail!("invalid blocked bloom filter size");
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for blocked bloom filters");
+        }
+        Ok(Self {
+            filter: Bytes::copy_from_slice(&buf[..buf.len() - 5]),
+            k: buf[buf.len() - 5],
+        })
+    }
+}
+
+/// Implements an XOR filter with 8-bit fingerprints.
+///
+/// Each key maps to one slot in each third of the table, and the fingerprints are assigned so that the XOR of
+/// the three slots of a key is its fingerprint. A key not in the filter matches with a probability of 1/256.
+pub struct XorFilter {
+    seed: u64,
+    /// number of slots in each third of the table
+    block_length: usize,
+    fingerprints: Bytes,
+}
+
+impl XorFilter {
+    /// Mix a key hash with the seed into 64 bits.
+    fn mix(h: u32, seed: u64) -> u64 {
+        // splitmix64 finalizer
+        let mut h = (h as u64).wrapping_add(seed);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^ (h >> 31)
+    }
+
+    fn fingerprint(h: u64) -> u8 {
+        (h ^ (h >> 32)) as u8
+    }
+
+    fn slots(h: u64, block_length: usize) -> [usize; 3] {
+        [
+            reduce(h as u32, block_length),
+            reduce(h.rotate_left(21) as u32, block_length) + block_length,
+            reduce(h.rotate_left(42) as u32, block_length) + 2 * block_length,
+        ]
+    }
+
+    /// Build the filter from key hashes.
+    pub fn build_from_key_hashes(keys: &[u32]) -> Self {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+        let block_length = (32 + keys.len() * 123 / 100).div_ceil(3);
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        loop {
+            if let Some(fingerprints) = Self::try_build(&keys, seed, block_length) {
+                return Self {
+                    seed,
+                    block_length,
+                    fingerprints: fingerprints.into(),
+                };
+            }
+            // Peeling fails with a small probability; another seed maps the keys to other slots.
+            seed = Self::mix(seed as u32, seed);
+        }
+    }
+
+    /// Assign the fingerprints by peeling the slots used by one key at a time, or `None` if the keys cannot
+    /// all be peeled with this seed.
+    fn try_build(keys: &[u32], seed: u64, block_length: usize) -> Option<Vec<u8>> {
+        let size = 3 * block_length;
+        // The XOR of the hashes of the keys mapped to each slot, and their number.
+        let mut slot_hashes = vec![0u64; size];
+        let mut slot_counts = vec![0u32; size];
+        for &key in keys {
+            let h = Self::mix(key, seed);
+            for slot in Self::slots(h, block_length) {
+                slot_hashes[slot] ^= h;
+                slot_counts[slot] += 1;
+            }
+        }
+        let mut queue = (0..size)
+            .filter(|&slot| slot_counts[slot] == 1)
+            .collect::<Vec<_>>();
+        // The keys in peeling order, with the slot each one is assigned to.
+        let mut stack = Vec::with_capacity(keys.len());
+        while let Some(slot) = queue.pop() {
+            if slot_counts[slot] != 1 {
+                continue;
+            }
+            let h = slot_hashes[slot];
+            stack.push((h, slot));
+            for other in Self::slots(h, block_length) {
+                slot_hashes[other] ^= h;
+                slot_counts[other] -= 1;
+                if slot_counts[other] == 1 {
+                    queue.push(other);
+                }
+            }
+        }
+        if stack.len() != keys.len() {
+            return None;
+        }
+        let mut fingerprints = vec![0u8; size];
+        for &(h, slot) in stack.iter().rev() {
+            let [a, b, c] = Self::slots(h, block_length);
+            // The slot assigned is still 0, so it does not change the XOR.
+            fingerprints[slot] =
+                Self::fingerprint(h) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+        }
+        Some(fingerprints)
+    }
+
+    /// Check if the filter may contain a key hash.
+    pub fn may_contain(&self, h: u32) -> bool {
+        let h = Self::mix(h, self.seed);
+        let [a, b, c] = Self::slots(h, self.block_length);
+        Self::fingerprint(h) == self.fingerprints[a] ^ self.fingerprints[b] ^ self.fingerprints[c]
+    }
+
+    /// Encode the filter: the seed (u64), the fingerprints, then the checksum (u32).
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let offset = buf.len();
+        buf.put_u64(self.seed);
+        buf.extend(&self.fingerprints);
+        let checksum = crc32fast::hash(&buf[offset..]);
+        buf.put_u32(checksum);
+    }
+
+    /// Decode the filter.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        // Even a filter of no key has slots.
+        if buf.len() <= 12 || !(buf.len() - 12).is_multiple_of(3) {
+            bail!("invalid xor filter size");
+        }
+        let checksum = (&buf[buf.len() - 4..]).get_u32();
+        if checksum != crc32fast::hash(&buf[..buf.len() - 4]) {
+            bail!("checksum mismatched for xor filters");
+        }
+        let fingerprints = &buf[8..buf.len() - 4];
+        Ok(Self {
+            seed: (&buf[..8]).get_u64(),
+            block_length: fingerprints.len() / 3,
+            fingerprints: Bytes::copy_from_slice(fingerprints),
+        })
+    }
+}
@@ -2,54 +2,184 @@ use std::sync::Arc;
 
 use anyhow::Result;
 
-use super::SsTable;
-use crate::block::BlockIterator;
+use super::{BlockReadOptions, SsTable};
+use crate::block::{Block, BlockIterator};
 use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::key::{KeySlice, ValueType};
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    options: BlockReadOptions,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+    /// An SST that only holds range tombstones has no data block to iterate over.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block {
+            data: Vec::new(),
+            offsets: Vec::new(),
+            hash_buckets: Vec::new(),
+        }))
+    }
+
+    fn seek_to_first_inner(
+        table: &Arc<SsTable>,
+        options: BlockReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         Ok((
             0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
+            BlockIterator::create_and_seek_to_first(table.read_block_with_options(0, options)?),
         ))
     }
 
     /// Create a new iterator and seek to the first key-value pair.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
+        Self::create_and_seek_to_first_with_options(table, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the first key-value pair.
+    pub fn create_and_seek_to_first_with_options(
+        table: Arc<SsTable>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table, options)?;
         let iter = Self {
             blk_iter,
             table,
             blk_idx,
+            options,
         };
         Ok(iter)
     }
 
     /// Seek to the first key-value pair.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
+        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table, self.options)?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        Ok(())
+    }
+
+    fn seek_to_last_inner(
+        table: &Arc<SsTable>,
+        options: BlockReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
+        let blk_idx = table.num_of_blocks() - 1;
+        Ok((
+            blk_idx,
+            BlockIterator::create_and_seek_to_last(
+                table.read_block_with_options(blk_idx, options)?,
+            ),
+        ))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        Self::create_and_seek_to_last_with_options(table, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the last key-value pair.
+    pub fn create_and_seek_to_last_with_options(
+        table: Arc<SsTable>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&table, options)?;
+        let iter = Self {
+            blk_iter,
+            table,
+            blk_idx,
+            options,
+        };
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair.
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&self.table, self.options)?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        Ok(())
+    }
+
+    fn seek_for_prev_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        options: BlockReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
+        // Only the first block may start after `key`, in which case there is no such pair.
+        let blk_idx = table.find_block_idx(key);
+        Ok((
+            blk_idx,
+            BlockIterator::create_and_seek_for_prev(
+                table.read_block_with_options(blk_idx, options)?,
+                key,
+            ),
+        ))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair which <= `key`.
+    pub fn create_and_seek_for_prev(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_for_prev_with_options(table, key, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the last key-value pair which <= `key`.
+    pub fn create_and_seek_for_prev_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice<'_>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_for_prev_inner(&table, key, options)?;
+        let iter = Self {
+            blk_iter,
+            table,
+            blk_idx,
+            options,
+        };
+        Ok(iter)
+    }
+
+    /// Seek to the last key-value pair which <= `key`.
+    pub fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_for_prev_inner(&self.table, key, self.options)?;
         self.blk_idx = blk_idx;
         self.blk_iter = blk_iter;
         Ok(())
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: KeySlice) -> Result<(usize, BlockIterator)> {
+    fn seek_to_key_inner(
+        table: &Arc<SsTable>,
+        key: KeySlice,
+        by_hash: bool,
+        options: BlockReadOptions,
+    ) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
+        let block = table.read_block_with_options(blk_idx, options)?;
+        let mut blk_iter = if by_hash {
+            BlockIterator::create_and_seek_to_key_by_hash(block, key)
+        } else {
+            BlockIterator::create_and_seek_to_key(block, key)
+        };
         if !blk_iter.is_valid() {
             blk_idx += 1;
             if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
+                blk_iter = BlockIterator::create_and_seek_to_first(
+                    table.read_block_with_options(blk_idx, options)?,
+                );
             }
         }
         Ok((blk_idx, blk_iter))
@@ -57,18 +187,50 @@ impl SsTableIterator {
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
+        Self::create_and_seek_to_key_with_options(table, key, BlockReadOptions::default())
+    }
+
+    /// Create a new iterator reading blocks with `options`, and seek to the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice<'_>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key, false, options)?;
+        let iter = Self {
+            blk_iter,
+            table,
+            blk_idx,
+            options,
+        };
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the first key-value pair which >= `key`, using the hash index of
+    /// the data block when available. Intended for point lookups.
+    pub fn create_and_seek_to_key_by_hash(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
+        Self::create_and_seek_to_key_by_hash_with_options(table, key, BlockReadOptions::default())
+    }
+
+    /// Same as `create_and_seek_to_key_by_hash`, reading blocks with `options`.
+    pub fn create_and_seek_to_key_by_hash_with_options(
+        table: Arc<SsTable>,
+        key: KeySlice<'_>,
+        options: BlockReadOptions,
+    ) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key, true, options)?;
         let iter = Self {
             blk_iter,
             table,
             blk_idx,
+            options,
         };
         Ok(iter)
     }
 
     /// Seek to the first key-value pair which >= `key`.
     pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
+        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key, false, self.options)?;
         self.blk_iter = blk_iter;
         self.blk_idx = blk_idx;
         Ok(())
@@ -82,6 +244,10 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.value()
     }
 
+    fn value_type(&self) -> ValueType {
+        self.blk_iter.value_type()
+    }
+
     fn key(&self) -> KeySlice {
         self.blk_iter.key()
     }
@@ -96,10 +262,39 @@ impl StorageIterator for SsTableIterator {
             self.blk_idx += 1;
             if self.blk_idx < self.table.num_of_blocks() {
                 self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
+                    self.table
+                        .read_block_with_options(self.blk_idx, self.options)?,
                 );
             }
         }
         Ok(())
     }
+
+    fn prev(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter = BlockIterator::create_and_seek_to_last(
+                self.table
+                    .read_block_with_options(self.blk_idx, self.options)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_to_key(key)
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        SsTableIterator::seek_to_last(self)
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        SsTableIterator::seek_for_prev(self, key)
+    }
 }
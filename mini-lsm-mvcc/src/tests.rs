@@ -1,4 +1,29 @@
+mod block_compression;
+mod block_hash_index;
+mod block_restart;
+mod compact_range;
+mod compaction_sizing;
+mod concurrent_compaction;
+mod filter_policy;
 mod harness;
+mod helpers;
+mod history_retention;
+mod iterator_seek;
+mod large_entries;
+mod multi_get;
+mod point_lookup;
+mod prefix_scan;
+mod range_delete;
+mod read_options;
+mod reverse_iteration;
+mod snapshot;
+mod subcompaction;
+mod trivial_move;
+mod value_log;
+mod value_types;
+mod version_history;
+mod wal_batch;
+mod wal_recovery;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -19,3 +44,4 @@ mod week3_day4;
 mod week3_day5;
 mod week3_day6;
 mod week3_day7;
+mod write_options;
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{BlockCache, LsmStorageOptions, MiniLsm},
+    table::{CompressionType, SsTable, SsTableBuilder, SsTableIterator},
+    tests::harness::{check_iter_result_by_key, check_lsm_iter_result_by_key, sync},
+};
+
+use super::helpers::key_of;
+
+fn value_of(idx: usize) -> Vec<u8> {
+    // Repetitive values so that every codec is able to shrink the blocks.
+    format!("value_{:05}_", idx).repeat(16).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    500
+}
+
+fn build_sst(
+    compression: CompressionType,
+    path: impl AsRef<std::path::Path>,
+    block_cache: Option<Arc<BlockCache>>,
+) -> SsTable {
+    let mut builder = SsTableBuilder::new(4096).with_compression(compression);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    builder.build(0, block_cache, path).unwrap()
+}
+
+fn expected_entries() -> Vec<(Bytes, Bytes)> {
+    (0..num_of_keys())
+        .map(|idx| (Bytes::from(key_of(idx)), Bytes::from(value_of(idx))))
+        .collect()
+}
+
+#[test]
+fn test_sst_compression_roundtrip() {
+    let dir = tempdir().unwrap();
+    let uncompressed = build_sst(CompressionType::None, dir.path().join("none.sst"), None);
+    for compression in [CompressionType::Lz4, CompressionType::Zstd] {
+        let sst = build_sst(
+            compression,
+            dir.path().join(format!("{:?}.sst", compression)),
+            None,
+        );
+        assert!(
+            sst.table_size() < uncompressed.table_size(),
+            "{:?} did not shrink the table: {} >= {}",
+            compression,
+            sst.table_size(),
+            uncompressed.table_size()
+        );
+        let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        check_iter_result_by_key(&mut iter, expected_entries());
+        let mut iter = SsTableIterator::create_and_seek_to_key(
+            sst,
+            KeySlice::for_testing_from_slice_no_ts(&key_of(num_of_keys() / 2)),
+        )
+        .unwrap();
+        check_iter_result_by_key(&mut iter, expected_entries().split_off(num_of_keys() / 2));
+    }
+}
+
+#[test]
+fn test_sst_compression_block_cache() {
+    let dir = tempdir().unwrap();
+    let block_cache = Arc::new(BlockCache::new(1024));
+    let sst = Arc::new(build_sst(
+        CompressionType::Zstd,
+        dir.path().join("1.sst"),
+        Some(block_cache.clone()),
+    ));
+    // Blocks are decompressed before they are inserted into the cache, so reading a cached block twice
+    // yields the same decoded contents.
+    for _ in 0..2 {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        check_iter_result_by_key(&mut iter, expected_entries());
+    }
+    let block = sst.read_block_cached(0).unwrap();
+    assert_eq!(block.data, sst.read_block(0).unwrap().data);
+}
+
+#[test]
+fn test_integration_compression() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.compression = CompressionType::Lz4;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..num_of_keys() / 2 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    sync(&storage.inner);
+    storage.close().unwrap();
+    drop(storage);
+
+    // Files written with another codec remain readable after the option changes.
+    options.compression = CompressionType::Zstd;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in num_of_keys() / 2..num_of_keys() {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    sync(&storage.inner);
+    for idx in 0..num_of_keys() {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+    storage.force_full_compaction().unwrap();
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    check_lsm_iter_result_by_key(&mut iter, expected_entries());
+    assert!(!iter.is_valid());
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockBuilder, BlockIterator},
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::{KeySlice, KeyVec},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{SsTableBuilder, SsTableIterator},
+    tests::harness::sync,
+};
+
+fn key_of(idx: usize) -> KeyVec {
+    // Several versions of each key.
+    KeyVec::from_vec_with_ts(
+        format!("key_{:05}", idx / 3 * 2).into_bytes(),
+        (3 - idx % 3) as u64,
+    )
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+fn generate_block(num_keys: usize, restart_interval: usize) -> Block {
+    let mut builder = BlockBuilder::new(65536)
+        .with_restart_interval(restart_interval)
+        .with_hash_index(true);
+    for idx in 0..num_keys {
+        assert!(builder.add(key_of(idx).as_key_slice(), &value_of(idx)));
+    }
+    builder.build()
+}
+
+fn check_seek_by_hash(block: Arc<Block>, num_keys: usize) {
+    let mut probes = Vec::new();
+    for idx in 0..num_keys {
+        probes.push(key_of(idx));
+        let key = key_of(idx);
+        // Newest possible version, and a key that is not in the block.
+        probes.push(KeyVec::from_vec_with_ts(key.key_ref().to_vec(), u64::MAX));
+        let mut absent = key.key_ref().to_vec();
+        absent.push(b'_');
+        probes.push(KeyVec::from_vec_with_ts(absent, u64::MAX));
+    }
+    for probe in probes {
+        let by_hash =
+            BlockIterator::create_and_seek_to_key_by_hash(block.clone(), probe.as_key_slice());
+        let by_seek = BlockIterator::create_and_seek_to_key(block.clone(), probe.as_key_slice());
+        assert_eq!(by_hash.is_valid(), by_seek.is_valid(), "{:?}", probe);
+        if by_seek.is_valid() {
+            assert_eq!(by_hash.key(), by_seek.key(), "{:?}", probe);
+            assert_eq!(by_hash.value(), by_seek.value(), "{:?}", probe);
+        }
+    }
+}
+
+#[test]
+fn test_block_hash_index() {
+    for restart_interval in [1, 4, 16] {
+        let block = generate_block(240, restart_interval);
+        assert!(!block.hash_buckets.is_empty());
+        let block = Arc::new(Block::decode(&block.encode()));
+        assert!(!block.hash_buckets.is_empty());
+        check_seek_by_hash(block, 240);
+    }
+}
+
+#[test]
+fn test_block_hash_index_too_many_restarts() {
+    // Restart indexes no longer fit into a bucket, so the block is built without a hash index and
+    // lookups fall back to binary search.
+    let block = generate_block(1000, 1);
+    assert!(block.hash_buckets.is_empty());
+    let block = Arc::new(Block::decode(&block.encode()));
+    assert!(block.hash_buckets.is_empty());
+    check_seek_by_hash(block, 1000);
+}
+
+#[test]
+fn test_sst_hash_index() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(256).with_hash_index(true);
+    for idx in 0..300 {
+        builder.add(key_of(idx).as_key_slice(), &value_of(idx));
+    }
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    for idx in 0..300 {
+        let key = key_of(idx);
+        let iter = SsTableIterator::create_and_seek_to_key_by_hash(
+            sst.clone(),
+            KeySlice::from_slice(key.key_ref(), u64::MAX),
+        )
+        .unwrap();
+        // Lands on the newest version of the key.
+        assert_eq!(iter.key(), key_of(idx / 3 * 3).as_key_slice());
+        let mut absent = key.key_ref().to_vec();
+        absent.push(b'_');
+        let iter = SsTableIterator::create_and_seek_to_key_by_hash(
+            sst.clone(),
+            KeySlice::from_slice(&absent, u64::MAX),
+        )
+        .unwrap();
+        if idx / 3 + 1 < 100 {
+            assert_eq!(iter.key(), key_of(idx / 3 * 3 + 3).as_key_slice());
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+}
+
+#[test]
+fn test_integration_hash_index() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.block_hash_index = true;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for round in 0..3 {
+        for idx in 0..200 {
+            let key = format!("key_{:05}", idx);
+            if round == 2 && idx % 5 == 0 {
+                storage.delete(key.as_bytes()).unwrap();
+            } else {
+                storage
+                    .put(
+                        key.as_bytes(),
+                        format!("value_{}_{}", idx, round).as_bytes(),
+                    )
+                    .unwrap();
+            }
+        }
+        sync(&storage.inner);
+    }
+    storage.force_full_compaction().unwrap();
+    for idx in 0..200 {
+        let key = format!("key_{:05}", idx);
+        let expected = if idx % 5 == 0 {
+            None
+        } else {
+            Some(Bytes::from(format!("value_{}_2", idx)))
+        };
+        assert_eq!(storage.get(key.as_bytes()).unwrap(), expected);
+        assert_eq!(storage.get(format!("{}_", key).as_bytes()).unwrap(), None);
+    }
+}
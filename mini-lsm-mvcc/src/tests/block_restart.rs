@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::key::{KeySlice, KeyVec};
+
+fn key_of(idx: usize) -> KeyVec {
+    // Long shared prefix, different suffixes, and several versions of each key.
+    KeyVec::from_vec_with_ts(
+        format!("a_very_long_shared_key_prefix_{:03}", idx / 3).into_bytes(),
+        (3 - idx % 3) as u64,
+    )
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    100
+}
+
+fn generate_block(restart_interval: usize) -> Block {
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(restart_interval);
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        assert!(builder.add(key.as_key_slice(), &value_of(idx)));
+    }
+    builder.build()
+}
+
+#[test]
+fn test_block_restart_points() {
+    for restart_interval in [1, 2, 7, 16, 1000] {
+        let block = generate_block(restart_interval);
+        assert_eq!(
+            block.offsets.len(),
+            num_of_keys().div_ceil(restart_interval)
+        );
+        let block = Arc::new(Block::decode(&block.encode()));
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        for idx in 0..num_of_keys() {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_block_delta_encoding() {
+    // Encoding keys against their predecessor shrinks blocks compared to storing every key in full.
+    let full_keys = generate_block(1).encode();
+    let delta_keys = generate_block(16).encode();
+    assert!(delta_keys.len() < full_keys.len());
+}
+
+#[test]
+fn test_block_seek_with_restarts() {
+    for restart_interval in [1, 3, 16, 1000] {
+        let block = Arc::new(generate_block(restart_interval));
+        for idx in 0..num_of_keys() {
+            // Seek to an existing key.
+            let iter =
+                BlockIterator::create_and_seek_to_key(block.clone(), key_of(idx).as_key_slice());
+            assert_eq!(iter.key(), key_of(idx).as_key_slice());
+            assert_eq!(iter.value(), value_of(idx));
+        }
+        for user_key in 0..num_of_keys().div_ceil(3) {
+            let key = format!("a_very_long_shared_key_prefix_{:03}", user_key).into_bytes();
+            // A newer ts than any stored version lands on the newest version of the key.
+            let iter = BlockIterator::create_and_seek_to_key(
+                block.clone(),
+                KeySlice::from_slice(&key, 10),
+            );
+            assert_eq!(iter.key(), key_of(user_key * 3).as_key_slice());
+            // An older ts than any stored version lands on the next key.
+            let iter =
+                BlockIterator::create_and_seek_to_key(block.clone(), KeySlice::from_slice(&key, 0));
+            let next = user_key * 3 + 3;
+            if next < num_of_keys() {
+                assert_eq!(iter.key(), key_of(next).as_key_slice());
+            } else {
+                assert!(!iter.is_valid());
+            }
+        }
+        let iter = BlockIterator::create_and_seek_to_key(
+            block.clone(),
+            KeySlice::from_slice(b"a_very_long_shared_key_prefix_999", 0),
+        );
+        assert!(!iter.is_valid());
+        let iter = BlockIterator::create_and_seek_to_key(block, KeySlice::from_slice(b"a", 0));
+        assert_eq!(iter.key(), key_of(0).as_key_slice());
+    }
+}
@@ -0,0 +1,183 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+        TieredCompactionOptions,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+};
+
+use super::helpers::{key_of, value_of, write_sst};
+
+fn flush(storage: &Arc<LsmStorageInner>) {
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+}
+
+#[test]
+fn test_compact_range_to_level() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    options.num_memtable_limit = 100;
+    // No compaction thread, so that only the manual compactions run.
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let low = write_sst(&storage, 0..100, 0);
+    write_sst(&storage, 200..300, 0);
+    write_sst(&storage, 250..400, 1);
+    let high = write_sst(&storage, 500..600, 0);
+
+    // The SSTs in the range are compacted, with the ones overlapping them.
+    storage
+        .compact_range(
+            Bound::Included(&key_of(210)),
+            Bound::Included(&key_of(220)),
+            Some(1),
+        )
+        .unwrap();
+    {
+        let state = storage.state.read();
+        assert_eq!(state.l0_sstables, vec![high, low]);
+        assert!(!state.levels[0].1.is_empty());
+        assert!(state.levels[1].1.is_empty() && state.levels[2].1.is_empty());
+    }
+    assert_eq!(storage.compaction_stats().compactions, 1);
+    let expected = (0..100)
+        .chain(200..250)
+        .chain(500..600)
+        .map(|idx| (idx, 0))
+        .chain((250..400).map(|idx| (idx, 1)))
+        .collect::<Vec<_>>();
+    for &(idx, version) in &expected {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, version)))
+        );
+    }
+
+    // Down to the last level, where the deleted keys are dropped.
+    for idx in 0..50 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    flush(&storage);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels[0].1.is_empty() && state.levels[1].1.is_empty());
+        let first_sst = &state.sstables[&state.levels[2].1[0]];
+        assert_eq!(first_sst.first_key().key_ref(), key_of(50));
+    }
+    for &(idx, version) in &expected {
+        let expected = (idx >= 50).then(|| Bytes::from(value_of(idx, version)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+
+    // Nothing left to compact in the range.
+    storage
+        .compact_range(Bound::Included(&key_of(700)), Bound::Unbounded, None)
+        .unwrap();
+    assert_eq!(storage.compaction_stats().compactions, 2);
+    for target_level in [0, 4] {
+        assert!(storage
+            .compact_range(Bound::Unbounded, Bound::Unbounded, Some(target_level))
+            .is_err());
+    }
+}
+
+#[test]
+fn test_compact_range_with_compaction_threads() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.num_compaction_threads = 2;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for version in 0..2 {
+        for idx in 0..2000 {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+    }
+    // A bulk delete, whose space is reclaimed right away.
+    for idx in 0..1500 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, None)
+        .unwrap();
+    {
+        let state = storage.inner.state.read();
+        assert!(state.l0_sstables.is_empty());
+        for (_, level) in &state.levels[..3] {
+            assert!(level.is_empty());
+        }
+        let first_sst = &state.sstables[&state.levels[3].1[0]];
+        assert_eq!(first_sst.first_key().key_ref(), key_of(1500));
+    }
+    for idx in (0..2000).step_by(7) {
+        let expected = (idx >= 1500).then(|| Bytes::from(value_of(idx, 1)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_compact_range_tiered() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 100,
+            max_size_amplification_percent: 10000,
+            size_ratio: 10000,
+            min_merge_width: 100,
+        },
+    ));
+    options.num_memtable_limit = 100;
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    for version in 0..3 {
+        for idx in (version * 100..300).step_by(2) {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+        flush(&storage);
+    }
+    storage.put(&key_of(1000), &value_of(1000, 3)).unwrap();
+    flush(&storage);
+    assert_eq!(storage.state.read().levels.len(), 4);
+
+    // The tiers from the newest one in the range are merged into one.
+    storage
+        .compact_range(
+            Bound::Included(&key_of(150)),
+            Bound::Included(&key_of(160)),
+            None,
+        )
+        .unwrap();
+    assert_eq!(storage.state.read().levels.len(), 3);
+    for idx in (0..300).step_by(2) {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, idx / 100)))
+        );
+    }
+    assert!(storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded, Some(1))
+        .is_err());
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+        TieredCompactionController, TieredCompactionOptions,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::helpers::write_sst;
+
+/// A storage without flush and compaction threads, holding SSTs of different sizes in L0.
+fn open(dir: &tempfile::TempDir) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+        },
+    ));
+    options.num_memtable_limit = 100;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+#[test]
+fn test_simple_leveled_sizing() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let large = write_sst(&storage, 0..1000, 0);
+    let older = write_sst(&storage, 0..10, 0);
+    let newer = write_sst(&storage, 10..20, 0);
+    let mut snapshot = storage.state.read().as_ref().clone();
+    snapshot.l0_sstables = vec![newer, older];
+    snapshot.levels[0].1 = vec![large];
+    assert!(snapshot.table_size_of(&[large]) > 2 * snapshot.table_size_of(&[newer, older]));
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 2,
+    });
+
+    // One file in L1 against two in L0, but L1 is more than twice as large, so L1 goes down first.
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(
+        (task.upper_level, task.upper_level_sst_ids, task.lower_level),
+        (Some(1), vec![large], 2)
+    );
+
+    // Four files in L1 against two in L0, but L0 is the larger one, so it is compacted.
+    let small = (0..4)
+        .map(|idx| write_sst(&storage, idx * 2 + 100..idx * 2 + 102, 0))
+        .collect::<Vec<_>>();
+    snapshot = storage.state.read().as_ref().clone();
+    snapshot.l0_sstables = vec![newer, large];
+    snapshot.levels[0].1 = small;
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(
+        (task.upper_level, task.upper_level_sst_ids, task.lower_level),
+        (None, vec![newer, large], 1)
+    );
+}
+
+#[test]
+fn test_tiered_sizing() {
+    let dir = tempdir().unwrap();
+    let storage = open(&dir);
+    let bottom = write_sst(&storage, 0..1000, 0);
+    let middle = write_sst(&storage, 0..10, 0);
+    let top = write_sst(&storage, 0..12, 0);
+    let mut snapshot = storage.state.read().as_ref().clone();
+    snapshot.l0_sstables.clear();
+    snapshot.levels = vec![
+        (top, vec![top]),
+        (middle, vec![middle]),
+        (bottom, vec![bottom]),
+    ];
+    let controller = TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+    });
+
+    // Two tiers above one would be a space amplification of 200% by file counts. By size, the small tiers are
+    // merged together.
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.tiers, snapshot.levels[..2].to_vec());
+    assert!(!task.bottom_tier_included);
+
+    // A large tier above the bottom one is merged into it.
+    snapshot.levels = vec![
+        (top, vec![top]),
+        (bottom, vec![bottom]),
+        (middle, vec![middle]),
+    ];
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.tiers, snapshot.levels);
+    assert!(task.bottom_tier_included);
+}
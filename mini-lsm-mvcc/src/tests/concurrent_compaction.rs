@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionTask, LeveledCompactionOptions, LeveledCompactionTask,
+        RunningCompaction, SimpleLeveledCompactionController, SimpleLeveledCompactionOptions,
+        TieredCompactionTask,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm},
+};
+
+use super::helpers::{key_of, value_of, write_sst};
+
+#[test]
+fn test_disjoint_compaction_tasks() {
+    let dir = tempdir().unwrap();
+    let options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    };
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(options.clone())),
+        )
+        .unwrap(),
+    );
+    let bottom = write_sst(&storage, 0..100, 0);
+    let older = write_sst(&storage, 0..100, 0);
+    let newer = write_sst(&storage, 0..100, 0);
+    let mut snapshot = storage.state.read().as_ref().clone();
+    snapshot.l0_sstables = vec![newer, older];
+    snapshot.levels[1].1 = vec![bottom];
+    let controller = SimpleLeveledCompactionController::new(options);
+
+    let task = controller.generate_compaction_task(&snapshot).unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![newer, older]);
+
+    // With L0 being compacted, the next task goes down to the last level.
+    let compacting = HashSet::from([newer, older]);
+    let task = controller
+        .generate_compaction_task_excluding(&snapshot, &compacting)
+        .unwrap();
+    assert_eq!(
+        (task.upper_level, task.upper_level_sst_ids, task.lower_level),
+        (Some(2), vec![bottom], 3)
+    );
+    let compacting = HashSet::from([newer, older, bottom]);
+    assert!(controller
+        .generate_compaction_task_excluding(&snapshot, &compacting)
+        .is_none());
+}
+
+#[test]
+fn test_running_compaction_conflicts() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    let storage = Arc::new(LsmStorageInner::open(&dir, options).unwrap());
+    let low = write_sst(&storage, 0..100, 0);
+    let middle = write_sst(&storage, 90..200, 0);
+    let high = write_sst(&storage, 300..400, 0);
+    let snapshot = storage.state.read().as_ref().clone();
+    let running = |upper_level: Option<usize>, upper: Vec<usize>, lower_level, lower| {
+        let task = CompactionTask::Leveled(LeveledCompactionTask {
+            upper_level,
+            upper_level_sst_ids: upper,
+            lower_level,
+            lower_level_sst_ids: lower,
+            is_lower_level_bottom_level: false,
+        });
+        RunningCompaction::new(&task, &snapshot)
+    };
+
+    let l0 = running(None, vec![low], 1, vec![]);
+    // Tasks sharing an input conflict.
+    assert!(l0.conflicts_with(&running(None, vec![low], 2, vec![])));
+    // Tasks writing overlapping keys into a common level conflict.
+    assert!(l0.conflicts_with(&running(Some(1), vec![middle], 2, vec![])));
+    assert!(l0.conflicts_with(&running(None, vec![middle], 1, vec![])));
+    // Tasks on other key ranges or other levels do not.
+    assert!(!l0.conflicts_with(&running(Some(1), vec![high], 2, vec![])));
+    assert!(!l0.conflicts_with(&running(Some(2), vec![middle], 3, vec![])));
+
+    // Tiered compaction renumbers the tiers and runs alone.
+    let tiered = RunningCompaction::new(
+        &CompactionTask::Tiered(TieredCompactionTask {
+            tiers: vec![(high, vec![high])],
+            bottom_tier_included: false,
+        }),
+        &snapshot,
+    );
+    assert!(tiered.conflicts_with(&running(Some(2), vec![middle], 3, vec![])));
+}
+
+#[test]
+fn test_compaction_conflict_retry() {
+    let dir = tempdir().unwrap();
+    let options = SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    };
+    let storage = Arc::new(
+        LsmStorageInner::open(
+            &dir,
+            LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(options)),
+        )
+        .unwrap(),
+    );
+    let bottom = write_sst(&storage, 0..100, 0);
+    let older = write_sst(&storage, 0..100, 0);
+    let newer = write_sst(&storage, 0..100, 0);
+    let mut snapshot = storage.state.read().as_ref().clone();
+    snapshot.l0_sstables = vec![newer, older];
+    snapshot.levels[1].1 = vec![bottom];
+    *storage.state.write() = Arc::new(snapshot.clone());
+    // A running task over L0 and L1 conflicts with the L0 compaction, without sharing its SSTs.
+    let task = CompactionTask::Leveled(LeveledCompactionTask {
+        upper_level: None,
+        upper_level_sst_ids: vec![],
+        lower_level: 1,
+        lower_level_sst_ids: vec![],
+        is_lower_level_bottom_level: false,
+    });
+    storage
+        .running_compactions
+        .lock()
+        .push(RunningCompaction::new(&task, &snapshot));
+
+    // The next candidate is run instead: moving L2 down to the last level.
+    storage.trigger_compaction().unwrap();
+    let state = storage.state.read();
+    assert_eq!(state.l0_sstables, vec![newer, older]);
+    assert!(state.levels[1].1.is_empty());
+    assert_eq!(state.levels[2].1, vec![bottom]);
+    drop(state);
+    assert_eq!(storage.compaction_stats().trivial_moves, 1);
+    assert_eq!(storage.running_compactions.lock().len(), 1);
+}
+
+#[test]
+fn test_concurrent_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.num_compaction_threads = 4;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for version in 0..4 {
+        for idx in 0..2000 {
+            let idx = (idx * 7919 + version * 13) % 2000;
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+    }
+    std::thread::sleep(Duration::from_secs(1));
+    {
+        let state = storage.inner.state.read();
+        for (_, level) in &state.levels {
+            let ssts = level
+                .iter()
+                .map(|id| &state.sstables[id])
+                .collect::<Vec<_>>();
+            for pair in ssts.windows(2) {
+                assert!(pair[0].last_key() < pair[1].first_key());
+            }
+        }
+    }
+    // Every version writes every key.
+    let check = |storage: &MiniLsm| {
+        for idx in 0..2000 {
+            assert_eq!(
+                storage.get(&key_of(idx)).unwrap(),
+                Some(Bytes::from(value_of(idx, 3)))
+            );
+        }
+    };
+    check(&storage);
+    storage.close().unwrap();
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage);
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    lsm_storage::MiniLsm,
+    table::filter::{
+        BlockedBloomFilterPolicy, BloomFilterPolicy, Filter, FilterPolicy, XorFilterPolicy,
+    },
+};
+
+use super::helpers::{flush, key_of, options, value_of};
+
+fn filter_kind(filter: Option<&Filter>) -> &'static str {
+    match filter {
+        None => "none",
+        Some(Filter::Bloom(_)) => "bloom",
+        Some(Filter::BlockedBloom(_)) => "blocked bloom",
+        Some(Filter::Xor(_)) => "xor",
+    }
+}
+
+/// The kinds of the filters of the SSTs in L0, newest first, and in the first level.
+fn filter_kinds(storage: &MiniLsm) -> (Vec<&'static str>, Vec<&'static str>) {
+    let state = storage.inner.state.read();
+    let kinds = |ids: &[usize]| {
+        ids.iter()
+            .map(|id| filter_kind(state.sstables[id].filter.as_ref()))
+            .collect::<Vec<_>>()
+    };
+    (kinds(&state.l0_sstables), kinds(&state.levels[0].1))
+}
+
+#[test]
+fn test_filter_policies() {
+    let key_hashes = (0..10000)
+        .map(|idx| farmhash::fingerprint32(&key_of(idx)))
+        .collect::<Vec<_>>();
+    let policies: [(Arc<dyn FilterPolicy>, f64); 4] = [
+        (Arc::new(BloomFilterPolicy::default()), 0.02),
+        (
+            Arc::new(BloomFilterPolicy::with_false_positive_rate(0.001)),
+            0.002,
+        ),
+        (
+            Arc::new(BlockedBloomFilterPolicy { bits_per_key: 10 }),
+            0.03,
+        ),
+        (Arc::new(XorFilterPolicy), 0.01),
+    ];
+    for (policy, max_false_positive_rate) in policies {
+        // Repeated hashes, as versions of a key, are added once.
+        let mut hashes = key_hashes.clone();
+        hashes.extend(&key_hashes[..100]);
+        let filter = policy.build(&hashes);
+        let mut buf = Vec::new();
+        Filter::encode(Some(&filter), &mut buf);
+        let decoded = Filter::decode(&buf).unwrap().unwrap();
+        assert_eq!(filter_kind(Some(&filter)), filter_kind(Some(&decoded)));
+        for filter in [filter, decoded] {
+            for &h in &key_hashes {
+                assert!(filter.may_contain(h), "{:?} misses a key", policy);
+            }
+            let false_positives = (10000..20000)
+                .filter(|&idx| filter.may_contain(farmhash::fingerprint32(&key_of(idx))))
+                .count();
+            let false_positive_rate = false_positives as f64 / 10000.0;
+            assert!(
+                false_positive_rate < max_false_positive_rate,
+                "{:?} has a false positive rate of {}",
+                policy,
+                false_positive_rate
+            );
+        }
+        // A filter of no key answers.
+        policy.build(&[]).may_contain(key_hashes[0]);
+    }
+
+    let mut buf = Vec::new();
+    Filter::encode(None, &mut buf);
+    assert!(Filter::decode(&buf).unwrap().is_none());
+    buf = Vec::new();
+    Filter::encode(Some(&XorFilterPolicy.build(&key_hashes)), &mut buf);
+    buf[10] ^= 1;
+    assert!(Filter::decode(&buf).is_err());
+    // Filters without any slot or block, even with a valid checksum, are corrupted.
+    for (tag, body) in [(2u8, vec![7u8]), (3, vec![0u8; 8])] {
+        let mut buf = vec![tag];
+        buf.extend(&body);
+        buf.put_u32(crc32fast::hash(&body));
+        assert!(Filter::decode(&buf).is_err());
+    }
+    assert!(Filter::decode(&[3]).is_err());
+}
+
+#[test]
+fn test_filter_policy_per_level() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.filter_policies = vec![
+        Some(Arc::new(BloomFilterPolicy::with_false_positive_rate(0.001))),
+        Some(Arc::new(BlockedBloomFilterPolicy { bits_per_key: 10 })),
+        None,
+    ];
+    assert!(options.filter_policy(2).is_none());
+    assert!(options.filter_policy(6).is_none());
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    assert_eq!(filter_kinds(&storage), (vec!["bloom"], vec![]));
+    storage.force_full_compaction().unwrap();
+    assert_eq!(filter_kinds(&storage), (vec![], vec!["blocked bloom"]));
+    for idx in (0..200).step_by(2) {
+        let expected = (idx < 100).then(|| Bytes::from(value_of(idx, 0)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_no_filter() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.filter_policies = vec![None];
+    // SSTs without a filter are read for every key in their range.
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in (0..100).step_by(2) {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    assert_eq!(filter_kinds(&storage), (vec!["none"], vec![]));
+    for idx in 0..100 {
+        let expected = (idx % 2 == 0).then(|| Bytes::from(value_of(idx, 0)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+    let keys = (0..100).map(key_of).collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| &key[..]).collect::<Vec<_>>();
+    let values = storage.multi_get(&keys).unwrap();
+    assert_eq!(values.iter().filter(|value| value.is_some()).count(), 50);
+}
+
+#[test]
+fn test_mixed_filters() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.enable_wal = true;
+    let policies: [Option<Arc<dyn FilterPolicy>>; 4] = [
+        Some(Arc::new(XorFilterPolicy)),
+        Some(Arc::new(BlockedBloomFilterPolicy { bits_per_key: 8 })),
+        None,
+        Some(Arc::new(BloomFilterPolicy::default())),
+    ];
+    // Each SST is written with another policy, and read back with the last one.
+    for (version, policy) in policies.into_iter().enumerate() {
+        options.filter_policies = vec![policy];
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for idx in (version..100).step_by(4) {
+            storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        }
+        flush(&storage);
+        storage.close().unwrap();
+    }
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        filter_kinds(&storage).0,
+        vec!["bloom", "none", "blocked bloom", "xor"]
+    );
+    for idx in 0..110 {
+        let expected = (idx < 100).then(|| Bytes::from(value_of(idx, 0)));
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+    }
+}
@@ -0,0 +1,44 @@
+//! Fixtures shared by the tests of the features only the MVCC engine has.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::compact::CompactionOptions;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+pub fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+pub fn value_of(idx: usize, version: usize) -> Vec<u8> {
+    format!("value_{}_{}", idx, version).into_bytes()
+}
+
+/// Options without compaction and without flushes other than the ones of the test.
+pub fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    // Flushes are driven by the test.
+    options.num_memtable_limit = 100;
+    options
+}
+
+/// Flush the memtable and all immutable memtables.
+pub fn flush(storage: &MiniLsm) {
+    while !storage.inner.state.read().memtable.is_empty()
+        || !storage.inner.state.read().imm_memtables.is_empty()
+    {
+        storage.force_flush().unwrap();
+    }
+}
+
+/// Write the keys of `range` at `version` to a new L0 SST, and return its id.
+pub fn write_sst(storage: &Arc<LsmStorageInner>, range: Range<usize>, version: usize) -> usize {
+    for idx in range {
+        storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.state.read().l0_sstables[0]
+}
@@ -0,0 +1,156 @@
+use std::ops::Bound;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mvcc::history::{History, HistoryRetention},
+};
+
+use super::helpers::{self, flush, key_of, value_of};
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.enable_wal = true;
+    options
+}
+
+/// Write 3 versions of 10 keys, each committed by 10 writes, and delete a key after them.
+fn write_versions(storage: &MiniLsm) {
+    for version in 0..3 {
+        for idx in 0..10 {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+        flush(storage);
+    }
+    storage.delete(&key_of(0)).unwrap();
+    flush(storage);
+}
+
+fn check_version_at(storage: &MiniLsm, version: usize) {
+    let ts = 10 * (version as u64 + 1);
+    for idx in 0..10 {
+        assert_eq!(
+            storage.get_at(&key_of(idx), ts).unwrap(),
+            Some(Bytes::from(value_of(idx, version)))
+        );
+    }
+    let mut iter = storage
+        .scan_at(Bound::Included(&key_of(5)), Bound::Unbounded, ts)
+        .unwrap();
+    for idx in 5..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, version));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_history_retention_timestamp() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage
+        .set_history_retention(Some(HistoryRetention::Timestamp(20)))
+        .unwrap();
+    write_versions(&storage);
+    storage.force_full_compaction().unwrap();
+    check_version_at(&storage, 1);
+    check_version_at(&storage, 2);
+    assert_eq!(storage.get_at(&key_of(0), 31).unwrap(), None);
+    // The versions below the floor are compacted away.
+    assert!(storage.get_at(&key_of(1), 10).is_err());
+
+    // The retention is persisted.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(
+        storage.history_retention(),
+        Some(HistoryRetention::Timestamp(20))
+    );
+    storage.put(&key_of(1), b"new").unwrap();
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    check_version_at(&storage, 1);
+
+    // Without retention, compaction keeps only what the readers need.
+    storage.set_history_retention(None).unwrap();
+    storage.force_full_compaction().unwrap();
+    assert!(storage.get_at(&key_of(1), 30).is_err());
+    assert_eq!(
+        storage.get_at(&key_of(1), 32).unwrap(),
+        Some(Bytes::from_static(b"new"))
+    );
+    let state = storage.inner.state.read().clone();
+    assert_eq!(state.levels[0].1.len(), 1);
+    assert_eq!(state.sstables[&state.levels[0].1[0]].max_ts(), 32);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.history_retention(), None);
+    // The commit times are only persisted under a retention duration.
+    assert_eq!(storage.inner.mvcc().history.lock().last_commit_time(), None);
+}
+
+#[test]
+fn test_history_retention_duration() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage
+        .set_history_retention(Some(HistoryRetention::Duration(Duration::from_secs(3600))))
+        .unwrap();
+    write_versions(&storage);
+    // Everything is committed within the last hour.
+    storage.force_full_compaction().unwrap();
+    for version in 0..3 {
+        check_version_at(&storage, version);
+    }
+
+    // Pretend the first two versions were committed more than an hour ago.
+    {
+        let mut history = storage.inner.mvcc().history.lock();
+        history.commit_times.clear();
+        history.record_commit(1, 10);
+        history.record_commit(2, 20);
+        history.record_commit(u64::MAX, 31);
+    }
+    storage.force_full_compaction().unwrap();
+    check_version_at(&storage, 1);
+    assert!(storage.get_at(&key_of(1), 10).is_err());
+
+    // The commit times are persisted on flush.
+    storage.put(&key_of(1), b"new").unwrap();
+    flush(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let last_commit_time = storage.inner.mvcc().history.lock().last_commit_time();
+    assert_eq!(last_commit_time.map(|(_, ts)| ts), Some(32));
+}
+
+#[test]
+fn test_history_floor() {
+    let mut history = History::default();
+    history.record_commit(100, 1);
+    assert_eq!(history.floor(1000), None);
+    // Only the latest sample is kept without a retention duration.
+    history.record_commit(200, 2);
+    assert_eq!(history.commit_times.len(), 1);
+    history.retention = Some(HistoryRetention::Timestamp(5));
+    assert_eq!(history.floor(1000), Some(5));
+
+    history.retention = Some(HistoryRetention::Duration(Duration::from_secs(100)));
+    // Nothing is known about the time before the first sample.
+    assert_eq!(history.floor(250), Some(0));
+    history.record_commit(300, 3);
+    history.record_commit(300, 4);
+    history.record_commit(400, 5);
+    assert_eq!(history.floor(350), Some(2));
+    assert_eq!(history.floor(400), Some(4));
+    assert_eq!(history.commit_times.len(), 2);
+    assert_eq!(history.floor(10000), Some(5));
+}
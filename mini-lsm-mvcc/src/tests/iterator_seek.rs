@@ -0,0 +1,178 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::{concat_iterator::SstConcatIterator, StorageIterator},
+    key::{KeySlice, ValueType, TS_RANGE_BEGIN},
+    lsm_storage::MiniLsm,
+};
+
+use super::harness::generate_sst_with_ts;
+use super::helpers::{flush, key_of, options, value_of};
+
+/// 40 keys in a level, the even ones updated in L0, and every fifth one deleted in the memtable.
+fn write_keys(storage: &MiniLsm) {
+    for idx in 0..40 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(storage);
+    storage.force_full_compaction().unwrap();
+    for idx in (0..40).step_by(2) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    flush(storage);
+    for idx in (0..40).step_by(5) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+}
+
+fn check_at<I>(iter: &I, idx: usize)
+where
+    I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    assert!(iter.is_valid());
+    assert_eq!(iter.key(), key_of(idx));
+    assert_eq!(iter.value(), value_of(idx, 1 - idx % 2));
+}
+
+#[test]
+fn test_sst_concat_seek() {
+    let dir = tempdir().unwrap();
+    let tables = (0..3)
+        .map(|table_idx| {
+            let data = (table_idx * 10..table_idx * 10 + 10)
+                .map(|idx| ((Bytes::from(key_of(idx)), 1), Bytes::from(value_of(idx, 0))))
+                .collect();
+            Arc::new(generate_sst_with_ts(
+                table_idx,
+                dir.path().join(format!("{}.sst", table_idx)),
+                data,
+                None,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut iter = SstConcatIterator::create_and_seek_to_first(tables).unwrap();
+    iter.seek(KeySlice::from_slice(&key_of(15), TS_RANGE_BEGIN))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(15));
+    // Between two tables.
+    iter.seek(KeySlice::from_slice(b"key_00009_", TS_RANGE_BEGIN))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(10));
+    iter.seek(KeySlice::from_slice(b"z", TS_RANGE_BEGIN))
+        .unwrap();
+    assert!(!iter.is_valid());
+    // An exhausted iterator can seek back.
+    iter.seek(KeySlice::from_slice(b"a", TS_RANGE_BEGIN))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(0));
+    for idx in 1..30 {
+        iter.next().unwrap();
+        assert_eq!(iter.key().key_ref(), key_of(idx));
+    }
+}
+
+#[test]
+fn test_scan_seek() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_keys(&storage);
+
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(10)), Bound::Excluded(&key_of(30)))
+        .unwrap();
+    check_at(&iter, 11);
+    iter.seek(&key_of(22)).unwrap();
+    check_at(&iter, 22);
+    iter.next().unwrap();
+    check_at(&iter, 23);
+    // A deleted key is skipped.
+    iter.seek(&key_of(25)).unwrap();
+    check_at(&iter, 26);
+    iter.seek(b"key_00017_").unwrap();
+    check_at(&iter, 18);
+    // Seeks stay within the range of the scan.
+    iter.seek(&key_of(30)).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek(&key_of(2)).unwrap();
+    check_at(&iter, 11);
+    iter.seek(&key_of(28)).unwrap();
+    iter.next().unwrap();
+    check_at(&iter, 29);
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek(&key_of(13)).unwrap();
+    check_at(&iter, 13);
+    iter.prev().unwrap();
+    check_at(&iter, 12);
+    iter.seek(&key_of(19)).unwrap();
+    check_at(&iter, 19);
+    iter.prev().unwrap();
+    check_at(&iter, 18);
+
+    // The versions the snapshot sees are seeked to.
+    let snapshot = storage.get_snapshot();
+    storage.put(&key_of(33), b"new").unwrap();
+    let mut iter = snapshot
+        .scan(Bound::Excluded(&key_of(31)), Bound::Unbounded)
+        .unwrap();
+    iter.seek(&key_of(31)).unwrap();
+    check_at(&iter, 32);
+    iter.seek(&key_of(33)).unwrap();
+    check_at(&iter, 33);
+
+    // Local writes of a transaction are seeked to as well.
+    let txn = Arc::new(storage.new_txn().unwrap());
+    txn.put(&key_of(35), b"local");
+    txn.delete(&key_of(36));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek(&key_of(34)).unwrap();
+    check_at(&iter, 34);
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"local");
+    iter.seek(&key_of(36)).unwrap();
+    check_at(&iter, 37);
+    iter.seek(&key_of(0)).unwrap();
+    check_at(&iter, 1);
+}
+
+#[test]
+fn test_version_iterator_seek() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_keys(&storage);
+    storage.delete_range(&key_of(0), &key_of(2)).unwrap();
+
+    let mut iter = storage
+        .scan_versions(Bound::Unbounded, Bound::Unbounded, 0..=u64::MAX)
+        .unwrap();
+    // Key 0 is written at ts 1, 41, deleted at 61 and range deleted at 69.
+    let mut versions = Vec::new();
+    while iter.is_valid() && iter.key().key_ref() == key_of(0) {
+        versions.push((iter.key().ts(), iter.value_type()));
+        iter.next().unwrap();
+    }
+    assert_eq!(
+        versions,
+        vec![
+            (69, ValueType::Delete),
+            (61, ValueType::Delete),
+            (41, ValueType::Put),
+            (1, ValueType::Put)
+        ]
+    );
+    // Only the versions at or before the ts sought are yielded for the key.
+    iter.seek(KeySlice::from_slice(&key_of(0), 60)).unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(0), 41));
+    iter.seek(KeySlice::from_slice(&key_of(1), 100)).unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(1), 69));
+    assert_eq!(iter.value_type(), ValueType::Delete);
+    iter.next().unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(1), 2));
+    assert_eq!(iter.value(), value_of(1, 0));
+    iter.seek(KeySlice::from_slice(&key_of(1), 1)).unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(2), 42));
+}
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block::{Block, BlockBuilder, BlockIterator},
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    table::{SsTableBuilder, SsTableIterator},
+};
+
+fn large_value(seed: usize, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| ((i * 31 + seed * 7) % 251) as u8)
+        .collect()
+}
+
+#[test]
+fn test_block_large_entries() {
+    let large_key = vec![b'k'; 100 << 10];
+    let value = large_value(0, 200 << 10);
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(b"a"), b"small"));
+    // Does not fit into a partially-filled block...
+    assert!(!builder.add(KeySlice::for_testing_from_slice_no_ts(&large_key), &value));
+    // ...but goes into an empty one.
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(&large_key), &value));
+    assert!(!builder.add(KeySlice::for_testing_from_slice_no_ts(b"l"), b"small"));
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    assert_eq!(iter.key().for_testing_key_ref(), &large_key[..]);
+    assert_eq!(iter.value(), &value[..]);
+    iter.next();
+    assert!(!iter.is_valid());
+    let iter = BlockIterator::create_and_seek_to_key(
+        block,
+        KeySlice::for_testing_from_slice_no_ts(&large_key),
+    );
+    assert_eq!(iter.value(), &value[..]);
+}
+
+#[test]
+fn test_sst_oversized_entry_own_block() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    let key_of = |idx: usize| format!("key_{:03}", idx).into_bytes();
+    let value_of = |idx: usize| {
+        if idx == 50 {
+            large_value(idx, 3 << 20)
+        } else {
+            format!("value_{:03}", idx).into_bytes()
+        }
+    };
+    for idx in 0..100 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    let large_block = sst
+        .block_meta
+        .iter()
+        .find(|meta| meta.first_key.key_ref() == key_of(50))
+        .expect("oversized entry should start a block");
+    assert_eq!(large_block.last_key.key_ref(), key_of(50));
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..100 {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_to_key(
+        sst,
+        KeySlice::for_testing_from_slice_no_ts(&key_of(50)),
+    )
+    .unwrap();
+    assert_eq!(iter.value(), value_of(50));
+}
+
+#[test]
+fn test_integration_large_entries() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    // Flushes are driven by the test.
+    options.num_memtable_limit = 100;
+    let large_key = vec![b'k'; 100 << 10];
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        (b"doc1".to_vec(), large_value(1, 2 << 20)),
+        (b"doc2".to_vec(), large_value(2, 300 << 10)),
+        (large_key.clone(), large_value(3, 70 << 10)),
+        (b"doc3".to_vec(), b"small".to_vec()),
+    ];
+    let flush = |storage: &MiniLsm| {
+        while !storage.inner.state.read().memtable.is_empty()
+            || !storage.inner.state.read().imm_memtables.is_empty()
+        {
+            storage.force_flush().unwrap();
+        }
+    };
+    let check = |storage: &MiniLsm, entries: &[(Vec<u8>, Vec<u8>)]| {
+        for (key, value) in entries {
+            assert_eq!(
+                storage.get(key).unwrap(),
+                Some(Bytes::copy_from_slice(value))
+            );
+        }
+    };
+
+    // Memtable, then recovery from the WAL.
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for (key, value) in &entries {
+        storage.put(key, value).unwrap();
+    }
+    check(&storage, &entries);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage, &entries);
+
+    // Flush to L0.
+    flush(&storage);
+    check(&storage, &entries);
+
+    // Overwrite some of the entries and compact.
+    let entries = vec![
+        (b"doc1".to_vec(), large_value(4, 3 << 20)),
+        entries[1].clone(),
+        (large_key, large_value(5, 1 << 20)),
+        entries[3].clone(),
+    ];
+    storage.put(&entries[0].0, &entries[0].1).unwrap();
+    storage.put(&entries[2].0, &entries[2].1).unwrap();
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage, &entries);
+
+    // Recovery from the SSTs.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage, &entries);
+    let mut iter = storage
+        .scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded)
+        .unwrap();
+    let mut expected = entries.clone();
+    expected.sort();
+    for (key, value) in expected {
+        assert_eq!(iter.key(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
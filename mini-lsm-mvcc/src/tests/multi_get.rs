@@ -0,0 +1,155 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::MiniLsm;
+
+use super::helpers::{flush, key_of, options, value_of};
+
+/// Keys spread over a level, L0, an immutable memtable and the active memtable, with deletes in each of them.
+fn write_keys(storage: &MiniLsm) {
+    for idx in 0..200 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(storage);
+    storage.force_full_compaction().unwrap();
+    for idx in (0..200).step_by(3) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.delete(&key_of(7)).unwrap();
+    flush(storage);
+    for idx in (0..200).step_by(5) {
+        storage.put(&key_of(idx), &value_of(idx, 2)).unwrap();
+    }
+    storage.delete(&key_of(11)).unwrap();
+    storage
+        .inner
+        .force_freeze_memtable(&storage.inner.state_lock.lock())
+        .unwrap();
+    for idx in (0..200).step_by(7) {
+        storage.put(&key_of(idx), &value_of(idx, 3)).unwrap();
+    }
+    storage.delete(&key_of(15)).unwrap();
+    storage.delete_range(&key_of(100), &key_of(110)).unwrap();
+}
+
+fn check_multi_get(storage: &MiniLsm, keys: &[Vec<u8>]) {
+    let keys = keys.iter().map(|key| &key[..]).collect::<Vec<_>>();
+    let expected = keys
+        .iter()
+        .map(|key| storage.get(key).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(storage.multi_get(&keys).unwrap(), expected);
+}
+
+#[test]
+fn test_multi_get() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_keys(&storage);
+    {
+        let state = storage.inner.state.read();
+        assert_eq!(state.imm_memtables.len(), 1);
+        assert_eq!(state.l0_sstables.len(), 1);
+        assert!(!state.levels[0].1.is_empty());
+    }
+
+    let keys = (0..210).map(key_of).collect::<Vec<_>>();
+    check_multi_get(&storage, &keys);
+    let values = storage
+        .multi_get(&[
+            &key_of(11),
+            &key_of(21),
+            &key_of(3),
+            &key_of(5),
+            &key_of(105),
+            &key_of(1),
+        ])
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            None,
+            Some(Bytes::from(value_of(21, 3))),
+            Some(Bytes::from(value_of(3, 1))),
+            Some(Bytes::from(value_of(5, 2))),
+            None,
+            Some(Bytes::from(value_of(1, 0)))
+        ]
+    );
+    // Keys may come in any order, repeated, or be missing from the storage.
+    let mut keys = (0..220).rev().step_by(4).map(key_of).collect::<Vec<_>>();
+    keys.extend((0..220).step_by(6).map(key_of));
+    keys.push(b"a".to_vec());
+    keys.push(b"z".to_vec());
+    check_multi_get(&storage, &keys);
+    assert_eq!(storage.multi_get(&[]).unwrap(), Vec::<Option<Bytes>>::new());
+
+    flush(&storage);
+    check_multi_get(&storage, &(0..210).map(key_of).collect::<Vec<_>>());
+    storage.force_full_compaction().unwrap();
+    check_multi_get(&storage, &(0..210).map(key_of).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_multi_get_blob_values() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.value_log_threshold = Some(1024);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..50 {
+        let value = if idx % 2 == 0 {
+            value_of(idx, 0).repeat(200)
+        } else {
+            value_of(idx, 0)
+        };
+        storage.put(&key_of(idx), &value).unwrap();
+    }
+    flush(&storage);
+    assert!(!storage.inner.value_log.file_ids().is_empty());
+    check_multi_get(&storage, &(0..50).rev().map(key_of).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_multi_get_snapshot_and_txn() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_keys(&storage);
+
+    let snapshot = storage.get_snapshot();
+    let txn = storage.new_txn().unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), b"new").unwrap();
+    }
+    flush(&storage);
+    let keys = (0..10).map(key_of).collect::<Vec<_>>();
+    let keys = keys.iter().map(|key| &key[..]).collect::<Vec<_>>();
+    // Both read at the ts they were created at.
+    let expected = keys
+        .iter()
+        .map(|key| snapshot.get(key).unwrap())
+        .collect::<Vec<_>>();
+    assert!(expected
+        .iter()
+        .all(|value| value.as_deref() != Some(b"new")));
+    assert_eq!(snapshot.multi_get(&keys).unwrap(), expected);
+    assert_eq!(txn.multi_get(&keys).unwrap(), expected);
+
+    // Local writes of a transaction take precedence over the storage.
+    txn.put(&key_of(7), b"local");
+    txn.delete(&key_of(8));
+    let values = txn
+        .multi_get(&[&key_of(8), &key_of(7), &key_of(9)])
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            None,
+            Some(Bytes::from_static(b"local")),
+            expected[9].clone()
+        ]
+    );
+    assert_eq!(
+        storage.multi_get(&[&key_of(7), &key_of(8)]).unwrap(),
+        vec![Some(Bytes::from_static(b"new")); 2]
+    );
+}
@@ -0,0 +1,115 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{iterators::StorageIterator, lsm_storage::MiniLsm};
+
+use super::helpers::{flush, key_of, options, value_of};
+
+/// The SSTs with a block in the cache.
+fn ssts_read(storage: &MiniLsm) -> Vec<usize> {
+    let state = storage.inner.state.read().clone();
+    let mut ssts = state
+        .sstables
+        .values()
+        .filter(|sst| {
+            (0..sst.num_of_blocks()).any(|block_idx| {
+                storage
+                    .inner
+                    .block_cache
+                    .contains_key(&(sst.sst_id(), block_idx))
+            })
+        })
+        .map(|sst| sst.sst_id())
+        .collect::<Vec<_>>();
+    ssts.sort();
+    ssts
+}
+
+#[test]
+fn test_point_lookup_stops_at_newest_version() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    for idx in 0..50 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    flush(&storage);
+    let (l0_sst, level_ssts) = {
+        let state = storage.inner.state.read();
+        (state.l0_sstables[0], state.levels[0].1.clone())
+    };
+
+    // A version in the memtable reads no block.
+    storage.put(&key_of(20), &value_of(20, 2)).unwrap();
+    assert_eq!(
+        storage.get(&key_of(20)).unwrap(),
+        Some(Bytes::from(value_of(20, 2)))
+    );
+    assert!(ssts_read(&storage).is_empty());
+    // A version in L0 is read without reading the level below.
+    assert_eq!(
+        storage.get(&key_of(10)).unwrap(),
+        Some(Bytes::from(value_of(10, 1)))
+    );
+    assert_eq!(ssts_read(&storage), vec![l0_sst]);
+    // A key out of the range of L0 is read from the level only.
+    assert_eq!(
+        storage.get(&key_of(80)).unwrap(),
+        Some(Bytes::from(value_of(80, 0)))
+    );
+    let mut expected = level_ssts.clone();
+    expected.push(l0_sst);
+    expected.sort();
+    assert_eq!(ssts_read(&storage), expected);
+}
+
+#[test]
+fn test_point_lookup_deep_tree() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.block_size = 128;
+    options.target_sst_size = 1024;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut snapshots = Vec::new();
+    for version in 0..4 {
+        for idx in (0..300).step_by(version + 1) {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+        for idx in (version..300).step_by(11) {
+            storage.delete(&key_of(idx)).unwrap();
+        }
+        storage
+            .delete_range(&key_of(version * 60), &key_of(version * 60 + 20))
+            .unwrap();
+        flush(&storage);
+        if version < 2 {
+            storage.force_full_compaction().unwrap();
+        }
+        snapshots.push(storage.get_snapshot());
+    }
+    assert!(storage.inner.state.read().levels[0].1.len() > 1);
+    assert!(storage.inner.state.read().l0_sstables.len() > 1);
+
+    // Every snapshot gets what it scans.
+    for snapshot in snapshots {
+        let mut expected = vec![None; 300];
+        let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        while iter.is_valid() {
+            let idx = std::str::from_utf8(&iter.key()[4..])
+                .unwrap()
+                .parse::<usize>()
+                .unwrap();
+            expected[idx] = Some(Bytes::copy_from_slice(iter.value()));
+            iter.next().unwrap();
+        }
+        for (idx, value) in expected.into_iter().enumerate() {
+            assert_eq!(snapshot.get(&key_of(idx)).unwrap(), value);
+        }
+    }
+}
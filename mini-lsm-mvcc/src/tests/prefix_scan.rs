@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    prefix_extractor::{prefix_upper_bound, PrefixExtractor},
+    table::{SsTable, SsTableBuilder},
+};
+
+use super::helpers::{self, flush};
+
+fn key_of(tenant: usize, idx: usize) -> Vec<u8> {
+    format!("tenant_{}/entity/{:03}", tenant, idx).into_bytes()
+}
+
+fn value_of(tenant: usize, idx: usize) -> Vec<u8> {
+    format!("value_{}_{}", tenant, idx).into_bytes()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.prefix_extractor = Some(PrefixExtractor::Delimited {
+        delimiter: b'/',
+        count: 1,
+    });
+    options
+}
+
+/// The SSTs with a block in the cache.
+fn ssts_read(storage: &MiniLsm) -> Vec<usize> {
+    let state = storage.inner.state.read().clone();
+    let mut ssts = state
+        .sstables
+        .values()
+        .filter(|sst| {
+            (0..sst.num_of_blocks()).any(|block_idx| {
+                storage
+                    .inner
+                    .block_cache
+                    .contains_key(&(sst.sst_id(), block_idx))
+            })
+        })
+        .map(|sst| sst.sst_id())
+        .collect::<Vec<_>>();
+    ssts.sort();
+    ssts
+}
+
+fn check_prefix(
+    storage: &MiniLsm,
+    prefix: &[u8],
+    tenant: usize,
+    idxs: impl Iterator<Item = usize>,
+) {
+    let mut iter = storage.scan_prefix(prefix).unwrap();
+    for idx in idxs {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(tenant, idx));
+        assert_eq!(iter.value(), value_of(tenant, idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_prefix_extractor() {
+    let fixed = PrefixExtractor::FixedLength(3);
+    assert_eq!(fixed.extract(b"abcd"), Some(&b"abc"[..]));
+    assert_eq!(fixed.extract(b"abc"), Some(&b"abc"[..]));
+    assert_eq!(fixed.extract(b"ab"), None);
+    let delimited = PrefixExtractor::Delimited {
+        delimiter: b'/',
+        count: 2,
+    };
+    assert_eq!(delimited.extract(b"a/b/c/d"), Some(&b"a/b/"[..]));
+    assert_eq!(delimited.extract(b"a/b/"), Some(&b"a/b/"[..]));
+    assert_eq!(delimited.extract(b"a/b"), None);
+
+    assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+    assert_eq!(prefix_upper_bound(b"a\xff\xff"), Some(b"b".to_vec()));
+    assert_eq!(prefix_upper_bound(b"\xff"), None);
+    assert_eq!(prefix_upper_bound(b""), None);
+
+    // The extractor is recorded in the SST.
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128).with_prefix_extractor(Some(delimited.clone()));
+    builder.add(KeySlice::from_slice(b"a/b/c", 1), b"value");
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.prefix_extractor, Some(delimited.clone()));
+    assert!(sst.may_contain_prefix(&delimited, farmhash::fingerprint32(b"a/b/")));
+    assert!(!sst.may_contain_prefix(&delimited, farmhash::fingerprint32(b"a/c/")));
+    // The filter says nothing about the prefixes of another extractor.
+    assert!(sst.may_contain_prefix(&fixed, farmhash::fingerprint32(b"a/c")));
+}
+
+#[test]
+fn test_scan_prefix() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    // Tenants 0 and 2 in one SST, 1 and 3 in another, with overlapping key ranges.
+    for tenants in [[0, 2], [1, 3]] {
+        for tenant in tenants {
+            for idx in 0..50 {
+                storage
+                    .put(&key_of(tenant, idx), &value_of(tenant, idx))
+                    .unwrap();
+            }
+        }
+        flush(&storage);
+    }
+    let (sst_02, sst_13) = {
+        let state = storage.inner.state.read();
+        (state.l0_sstables[1], state.l0_sstables[0])
+    };
+    for idx in 0..10 {
+        storage.put(&key_of(4, idx), &value_of(4, idx)).unwrap();
+    }
+
+    check_prefix(&storage, b"tenant_1/", 1, 0..50);
+    assert_eq!(ssts_read(&storage), vec![sst_13]);
+    check_prefix(&storage, b"tenant_2/entity/02", 2, 20..30);
+    assert_eq!(ssts_read(&storage), vec![sst_02, sst_13]);
+    // The memtable has no key of tenant 3, but the prefix of tenant 4.
+    let prefix_extractor = options().prefix_extractor.unwrap();
+    let memtable = storage.inner.state.read().memtable.clone();
+    assert!(!memtable.may_contain_prefix(&prefix_extractor, farmhash::fingerprint32(b"tenant_3/")));
+    assert!(memtable.may_contain_prefix(&prefix_extractor, farmhash::fingerprint32(b"tenant_4/")));
+    check_prefix(&storage, b"tenant_4/", 4, 0..10);
+    check_prefix(&storage, b"tenant_3/", 3, 0..50);
+    // A prefix without an extracted prefix is scanned without filtering.
+    check_prefix(&storage, b"tenant_1", 1, 0..50);
+    check_prefix(&storage, b"tenant_5/", 5, 0..0);
+
+    // Writes and deletes of a transaction are merged in.
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(1, 50), &value_of(1, 50));
+    txn.delete(&key_of(1, 0));
+    txn.put(&key_of(2, 0), &value_of(2, 0));
+    let mut iter = txn.scan_prefix(b"tenant_1/").unwrap();
+    for idx in 1..51 {
+        assert_eq!(iter.key(), key_of(1, idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_scan_prefix_change_extractor() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.enable_wal = true;
+    options.prefix_extractor = None;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for tenant in 0..3 {
+        for idx in 0..20 {
+            storage
+                .put(&key_of(tenant, idx), &value_of(tenant, idx))
+                .unwrap();
+        }
+        if tenant < 2 {
+            flush(&storage);
+        }
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // SSTs written without the extractor, or with another one, are not filtered by prefix.
+    options.prefix_extractor = Some(PrefixExtractor::FixedLength(9));
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for tenant in 0..3 {
+        check_prefix(&storage, &key_of(tenant, 0)[..9], tenant, 0..20);
+    }
+    storage.put(&key_of(3, 0), &value_of(3, 0)).unwrap();
+    flush(&storage);
+    storage.close().unwrap();
+    drop(storage);
+
+    options.prefix_extractor = Some(PrefixExtractor::Delimited {
+        delimiter: b'/',
+        count: 1,
+    });
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for tenant in 0..3 {
+        check_prefix(
+            &storage,
+            format!("tenant_{}/", tenant).as_bytes(),
+            tenant,
+            0..20,
+        );
+    }
+    check_prefix(&storage, b"tenant_3/", 3, 0..1);
+}
@@ -0,0 +1,212 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::KeySlice,
+    lsm_storage::MiniLsm,
+    range_tombstone::RangeTombstone,
+    table::{SsTable, SsTableBuilder, SsTableIterator},
+};
+
+use super::helpers::{flush, key_of, options, value_of};
+
+/// `expected[idx]` is the value of `key_of(idx)`, if any.
+fn check(storage: &MiniLsm, expected: &[Option<Vec<u8>>]) {
+    for (idx, value) in expected.iter().enumerate() {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            value.as_ref().map(|value| Bytes::copy_from_slice(value)),
+            "key_{:05}",
+            idx
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (idx, value) in expected.iter().enumerate() {
+        if let Some(value) = value {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value);
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+    // A scan starting in the middle of a deleted range.
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(25)), Bound::Excluded(&key_of(60)))
+        .unwrap();
+    for (idx, value) in expected.iter().enumerate().take(60).skip(25) {
+        if let Some(value) = value {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value);
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+}
+
+fn num_range_tombstones(storage: &MiniLsm) -> usize {
+    let state = storage.inner.state.read();
+    state
+        .sstables
+        .values()
+        .map(|table| table.range_tombstones.len())
+        .sum()
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 10..20 {
+        builder.add(KeySlice::from_slice(&key_of(idx), 1), &value_of(idx, 0));
+    }
+    builder.add_range_tombstone(RangeTombstone::new(&key_of(5), &key_of(15), 2));
+    builder.add_range_tombstone(RangeTombstone::new(&key_of(18), &key_of(30), 3));
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.first_key().key_ref(), key_of(5));
+    assert_eq!(sst.last_key().key_ref(), key_of(30));
+    assert_eq!(sst.max_ts(), 3);
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    assert_eq!(
+        sst.range_tombstones,
+        vec![
+            RangeTombstone::new(&key_of(5), &key_of(15), 2),
+            RangeTombstone::new(&key_of(18), &key_of(30), 3)
+        ]
+    );
+    assert_eq!(sst.first_key().key_ref(), key_of(5));
+    assert_eq!(sst.last_key().key_ref(), key_of(30));
+
+    // An SST of range tombstones only.
+    let mut builder = SsTableBuilder::new(128);
+    builder.add_range_tombstone(RangeTombstone::new(&key_of(5), &key_of(15), 2));
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert!(!SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .is_valid());
+    assert!(
+        !SsTableIterator::create_and_seek_to_key(sst, KeySlice::from_slice(&key_of(6), 2))
+            .unwrap()
+            .is_valid()
+    );
+}
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    let mut expected = Vec::new();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        expected.push(Some(value_of(idx, 0)));
+    }
+    let txn = storage.new_txn().unwrap();
+    assert!(storage.delete_range(&key_of(50), &key_of(20)).is_err());
+    assert!(storage.delete_range(&key_of(50), &key_of(50)).is_err());
+    storage.delete_range(&key_of(20), &key_of(50)).unwrap();
+    expected[20..50].fill(None);
+    // Writes after the tombstone are not affected by it.
+    storage.put(&key_of(30), &value_of(30, 1)).unwrap();
+    expected[30] = Some(value_of(30, 1));
+    check(&storage, &expected);
+    // A transaction started before the deletion still sees the keys.
+    assert_eq!(
+        txn.get(&key_of(40)).unwrap(),
+        Some(Bytes::from(value_of(40, 0)))
+    );
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..100 {
+        assert_eq!(iter.key(), key_of(idx));
+        iter.next().unwrap();
+    }
+    drop(txn);
+
+    // Recover the tombstone from the WAL.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    check(&storage, &expected);
+
+    // The tombstone in an SST covers the keys in older SSTs, and one with only a tombstone works as well.
+    flush(&storage);
+    storage.delete_range(&key_of(45), &key_of(70)).unwrap();
+    expected[45..70].fill(None);
+    flush(&storage);
+    check(&storage, &expected);
+    assert_eq!(num_range_tombstones(&storage), 2);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    check(&storage, &expected);
+}
+
+#[test]
+fn test_delete_range_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.block_size = 128;
+    options.target_sst_size = 1024;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let mut expected = Vec::new();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        expected.push(Some(value_of(idx, 0)));
+    }
+    flush(&storage);
+    let txn = storage.new_txn().unwrap();
+    storage.delete_range(&key_of(20), &key_of(80)).unwrap();
+    expected[20..80].fill(None);
+    storage.put(&key_of(50), &value_of(50, 1)).unwrap();
+    expected[50] = Some(value_of(50, 1));
+    flush(&storage);
+
+    // The tombstone is above the watermark, so it is kept, split among the output SSTs.
+    storage.force_full_compaction().unwrap();
+    assert!(storage.inner.state.read().levels[0].1.len() > 1);
+    assert!(num_range_tombstones(&storage) > 1);
+    check(&storage, &expected);
+    for idx in 0..100 {
+        assert_eq!(
+            txn.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+    }
+    drop(txn);
+
+    // Once no reader needs the deleted versions, they are dropped together with the tombstone.
+    storage.force_full_compaction().unwrap();
+    assert_eq!(num_range_tombstones(&storage), 0);
+    check(&storage, &expected);
+    let state = storage.inner.state.read().clone();
+    let num_entries: usize = state.levels[0]
+        .1
+        .iter()
+        .map(|id| {
+            let mut iter =
+                SsTableIterator::create_and_seek_to_first(state.sstables[id].clone()).unwrap();
+            let mut num_entries = 0;
+            while iter.is_valid() {
+                num_entries += 1;
+                iter.next().unwrap();
+            }
+            num_entries
+        })
+        .sum();
+    assert_eq!(num_entries, 41);
+
+    // Deleting everything leaves no SST behind.
+    storage.delete_range(&key_of(0), &key_of(100)).unwrap();
+    expected.fill(None);
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage, &expected);
+    assert!(storage.inner.state.read().sstables.is_empty());
+}
@@ -0,0 +1,273 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{MiniLsm, ReadOptions},
+    mvcc::history::HistoryRetention,
+};
+
+use super::helpers::{flush, key_of, options, value_of};
+
+fn num_cached_blocks(storage: &MiniLsm) -> usize {
+    let state = storage.inner.state.read().clone();
+    state
+        .sstables
+        .values()
+        .flat_map(|sst| (0..sst.num_of_blocks()).map(|block_idx| (sst.sst_id(), block_idx)))
+        .filter(|block| storage.inner.block_cache.contains_key(block))
+        .count()
+}
+
+#[test]
+fn test_read_options_fill_cache() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    let no_fill = ReadOptions {
+        fill_cache: false,
+        ..Default::default()
+    };
+    let mut iter = storage
+        .scan_with_options(Bound::Unbounded, Bound::Unbounded, &no_fill)
+        .unwrap();
+    for idx in 0..100 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 0));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(
+        storage.get_with_options(&key_of(1), &no_fill).unwrap(),
+        Some(Bytes::from(value_of(1, 0)))
+    );
+    assert_eq!(num_cached_blocks(&storage), 0);
+
+    // Reads with the default options fill the cache, and reads without filling still use it.
+    assert_eq!(
+        storage.get(&key_of(1)).unwrap(),
+        Some(Bytes::from(value_of(1, 0)))
+    );
+    assert_eq!(num_cached_blocks(&storage), 1);
+    assert_eq!(
+        storage.get_with_options(&key_of(1), &no_fill).unwrap(),
+        Some(Bytes::from(value_of(1, 0)))
+    );
+    assert_eq!(num_cached_blocks(&storage), 1);
+}
+
+#[test]
+fn test_read_options_verify_checksums() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    // Damage the checksum of the first block, leaving its data intact.
+    let sst_id = storage.inner.state.read().l0_sstables[0];
+    let sst = storage.inner.state.read().sstables[&sst_id].clone();
+    let block_end = sst
+        .block_meta
+        .get(1)
+        .map_or(sst.block_meta_offset, |meta| meta.offset);
+    let path = storage.inner.path_of_sst(sst_id);
+    let mut data = std::fs::read(&path).unwrap();
+    data[block_end - 1] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+
+    let verify = ReadOptions {
+        fill_cache: false,
+        ..Default::default()
+    };
+    assert!(storage.get_with_options(&key_of(0), &verify).is_err());
+    let no_verify = ReadOptions {
+        fill_cache: false,
+        verify_checksums: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(0), &no_verify).unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+    let mut iter = storage
+        .scan_with_options(Bound::Unbounded, Bound::Unbounded, &no_verify)
+        .unwrap();
+    for idx in 0..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    drop(iter);
+
+    // The unverified block is not cached for the reads that verify checksums.
+    let no_verify = ReadOptions {
+        verify_checksums: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(0), &no_verify).unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+    assert_eq!(num_cached_blocks(&storage), 0);
+    assert!(storage.get(&key_of(0)).is_err());
+}
+
+#[test]
+fn test_read_options_bounds() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        if idx == 10 {
+            flush(&storage);
+        }
+    }
+    let check = |lower: Bound<&[u8]>, upper: Bound<&[u8]>, options: &ReadOptions, expected| {
+        let mut iter = storage.scan_with_options(lower, upper, options).unwrap();
+        for idx in expected {
+            assert_eq!(iter.key(), key_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+    let options = ReadOptions {
+        iterate_lower_bound: Bound::Included(Bytes::from(key_of(5))),
+        iterate_upper_bound: Bound::Excluded(Bytes::from(key_of(15))),
+        ..Default::default()
+    };
+    check(Bound::Unbounded, Bound::Unbounded, &options, 5..15);
+    // The tighter of the scan bounds and the option bounds applies.
+    check(
+        Bound::Excluded(&key_of(7)),
+        Bound::Included(&key_of(17)),
+        &options,
+        8..15,
+    );
+    check(
+        Bound::Included(&key_of(2)),
+        Bound::Included(&key_of(9)),
+        &options,
+        5..10,
+    );
+    check(
+        Bound::Excluded(&key_of(5)),
+        Bound::Unbounded,
+        &options,
+        6..15,
+    );
+    check(
+        Bound::Unbounded,
+        Bound::Included(&key_of(3)),
+        &options,
+        0..0,
+    );
+
+    // Transactions take the bounds as well.
+    let txn = storage.new_txn().unwrap();
+    txn.put(&key_of(4), b"");
+    txn.put(&key_of(12), b"");
+    let mut iter = txn
+        .scan_with_options(Bound::Unbounded, Bound::Unbounded, &options)
+        .unwrap();
+    for idx in 5..15 {
+        assert_eq!(iter.key(), key_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let iter = txn
+        .scan_with_options(Bound::Unbounded, Bound::Included(&key_of(4)), &options)
+        .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_read_options_read_ts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage
+        .set_history_retention(Some(HistoryRetention::Timestamp(0)))
+        .unwrap();
+    for version in 0..3 {
+        for idx in 0..10 {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+        }
+        flush(&storage);
+    }
+    storage.delete(&key_of(0)).unwrap();
+    // Each version is committed by 10 writes.
+    for version in 0..3 {
+        let options = ReadOptions {
+            read_ts: Some(10 * (version as u64 + 1)),
+            ..Default::default()
+        };
+        for idx in 0..10 {
+            assert_eq!(
+                storage.get_with_options(&key_of(idx), &options).unwrap(),
+                Some(Bytes::from(value_of(idx, version)))
+            );
+        }
+        let mut iter = storage
+            .scan_with_options(Bound::Unbounded, Bound::Unbounded, &options)
+            .unwrap();
+        for idx in 0..10 {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value_of(idx, version));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    let options = ReadOptions {
+        read_ts: Some(5),
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(4), &options).unwrap(),
+        Some(Bytes::from(value_of(4, 0)))
+    );
+    assert_eq!(
+        storage.get_with_options(&key_of(5), &options).unwrap(),
+        None
+    );
+
+    // Reading ahead of the latest commit, or at another ts in a transaction, is an error.
+    let options = ReadOptions {
+        read_ts: Some(32),
+        ..Default::default()
+    };
+    assert!(storage.get_with_options(&key_of(0), &options).is_err());
+    let txn = storage.new_txn().unwrap();
+    let options = ReadOptions {
+        read_ts: Some(10),
+        ..Default::default()
+    };
+    assert!(txn.get_with_options(&key_of(0), &options).is_err());
+    let options = ReadOptions {
+        read_ts: Some(31),
+        ..Default::default()
+    };
+    assert_eq!(txn.get_with_options(&key_of(0), &options).unwrap(), None);
+    drop(txn);
+
+    // So is reading below the history kept by compaction.
+    storage
+        .set_history_retention(Some(HistoryRetention::Timestamp(20)))
+        .unwrap();
+    let options = ReadOptions {
+        read_ts: Some(10),
+        ..Default::default()
+    };
+    assert!(storage.get_with_options(&key_of(0), &options).is_err());
+    storage.set_history_retention(None).unwrap();
+    let options = ReadOptions {
+        read_ts: Some(30),
+        ..Default::default()
+    };
+    assert!(storage.get_with_options(&key_of(0), &options).is_err());
+}
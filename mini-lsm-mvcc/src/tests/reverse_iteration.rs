@@ -0,0 +1,309 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    block::{BlockBuilder, BlockIterator},
+    iterators::{merge_iterator::MergeIterator, StorageIterator},
+    key::{KeySlice, KeyVec, TS_RANGE_BEGIN, TS_RANGE_END},
+    lsm_storage::MiniLsm,
+    mem_table::MemTable,
+    table::SsTableIterator,
+};
+
+use super::harness::generate_sst_with_ts;
+use super::helpers::{flush, key_of, options, value_of};
+
+/// Collect the (key, ts) pairs of an iterator walking backward.
+fn collect_backward<I>(iter: &mut I) -> Vec<(Vec<u8>, u64)>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+{
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+        iter.prev().unwrap();
+    }
+    keys
+}
+
+/// Collect the keys and values of a storage iterator walking backward.
+fn collect_keys_backward<I>(iter: &mut I) -> Vec<(Vec<u8>, Vec<u8>)>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.prev().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_block_reverse_iteration() {
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(7);
+    let keys = (0..100)
+        .map(|idx| KeyVec::from_vec_with_ts(key_of(idx / 3), (3 - idx % 3) as u64))
+        .collect::<Vec<_>>();
+    for (idx, key) in keys.iter().enumerate() {
+        assert!(builder.add(key.as_key_slice(), &value_of(idx, 0)));
+    }
+    let block = Arc::new(builder.build());
+    let mut iter = BlockIterator::create_and_seek_to_last(block.clone());
+    for idx in (0..100).rev() {
+        assert_eq!(iter.key(), keys[idx].as_key_slice());
+        assert_eq!(iter.value(), value_of(idx, 0));
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+
+    // The oldest version of a key is the last entry at or before it.
+    let iter = BlockIterator::create_and_seek_for_prev(
+        block.clone(),
+        KeySlice::from_slice(&key_of(10), TS_RANGE_END),
+    );
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(10), 1));
+    let iter = BlockIterator::create_and_seek_for_prev(
+        block.clone(),
+        KeySlice::from_slice(&key_of(10), TS_RANGE_BEGIN),
+    );
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(9), 1));
+    let iter =
+        BlockIterator::create_and_seek_for_prev(block, KeySlice::from_slice(b"key", TS_RANGE_END));
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_reverse_iteration() {
+    let dir = tempdir().unwrap();
+    let data = (0..100)
+        .flat_map(|idx| {
+            (1..=3).rev().map(move |ts| {
+                (
+                    (Bytes::from(key_of(idx)), ts),
+                    Bytes::from(value_of(idx, ts as usize)),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let sst = Arc::new(generate_sst_with_ts(
+        1,
+        dir.path().join("1.sst"),
+        data.clone(),
+        None,
+    ));
+    assert!(sst.num_of_blocks() > 1);
+    let expected = data
+        .iter()
+        .rev()
+        .map(|((key, ts), _)| (key.to_vec(), *ts))
+        .collect::<Vec<_>>();
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst.clone()).unwrap();
+    assert_eq!(collect_backward(&mut iter), expected);
+
+    // Turn around in the middle of the table, across block boundaries.
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for _ in 0..150 {
+        iter.next().unwrap();
+    }
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(49), 1));
+    assert_eq!(collect_backward(&mut iter), expected[150..]);
+
+    let mut iter = SsTableIterator::create_and_seek_for_prev(
+        sst.clone(),
+        KeySlice::from_slice(b"key_00050_", TS_RANGE_BEGIN),
+    )
+    .unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(50), 1));
+    iter.seek_for_prev(KeySlice::from_slice(b"key", TS_RANGE_BEGIN))
+        .unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(KeySlice::from_slice(b"z", TS_RANGE_BEGIN))
+        .unwrap();
+    assert_eq!(iter.key(), KeySlice::from_slice(&key_of(99), 1));
+}
+
+#[test]
+fn test_merge_iterator_turns_around() {
+    let memtables = (0..3).map(MemTable::create).collect::<Vec<_>>();
+    // Keys are spread over the memtables, and key 0 and 9 are in every one of them with the same ts.
+    for idx in 0..10 {
+        for (table_idx, memtable) in memtables.iter().enumerate() {
+            if idx % 3 == table_idx || idx == 0 || idx == 9 {
+                memtable
+                    .put(
+                        KeySlice::from_slice(&key_of(idx), 1),
+                        &value_of(idx, table_idx),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+    let create = || {
+        MergeIterator::create(
+            memtables
+                .iter()
+                .map(|memtable| Box::new(memtable.scan(Bound::Unbounded, Bound::Unbounded)))
+                .collect(),
+        )
+    };
+    let mut iter = create();
+    iter.seek_to_last().unwrap();
+    // The first iterator wins ties in both directions.
+    assert_eq!(iter.value(), value_of(9, 0));
+    let keys = collect_backward(&mut iter);
+    assert_eq!(
+        keys,
+        (0..10)
+            .rev()
+            .map(|idx| (key_of(idx), 1))
+            .collect::<Vec<_>>()
+    );
+
+    let mut iter = create();
+    for idx in 0..5 {
+        assert_eq!(iter.key().key_ref(), key_of(idx));
+        iter.next().unwrap();
+    }
+    for idx in (1..5).rev() {
+        iter.prev().unwrap();
+        assert_eq!(iter.key().key_ref(), key_of(idx));
+    }
+    iter.prev().unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(0));
+    assert_eq!(iter.value(), value_of(0, 0));
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(1));
+    iter.seek_for_prev(KeySlice::from_slice(&key_of(9), TS_RANGE_END))
+        .unwrap();
+    assert_eq!(iter.value(), value_of(9, 0));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_scan_reverse() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..30 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    for idx in (0..30).step_by(2) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    flush(&storage);
+    let snapshot = storage.get_snapshot();
+    for idx in (0..30).step_by(3) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(20), &key_of(25)).unwrap();
+    storage.put(&key_of(22), &value_of(22, 2)).unwrap();
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut expected = Vec::new();
+    while iter.is_valid() {
+        expected.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    assert!(expected.contains(&(key_of(22), value_of(22, 2))));
+    assert!(!expected.iter().any(|(key, _)| *key == key_of(21)));
+    expected.reverse();
+    let mut iter = storage
+        .scan_reverse(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(collect_keys_backward(&mut iter), expected);
+
+    // Walking backward reads the versions visible at the read ts.
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek_to_last().unwrap();
+    let keys = collect_keys_backward(&mut iter);
+    assert_eq!(keys.len(), 30);
+    for (idx, (key, value)) in keys.into_iter().rev().enumerate() {
+        assert_eq!(key, key_of(idx));
+        assert_eq!(value, value_of(idx, 1 - idx % 2));
+    }
+
+    // Bounds are respected, and the iterator can turn around.
+    let mut iter = storage
+        .scan_reverse(Bound::Excluded(&key_of(10)), Bound::Excluded(&key_of(20)))
+        .unwrap();
+    assert_eq!(iter.key(), key_of(19));
+    let keys = collect_keys_backward(&mut iter)
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        [19, 17, 16, 14, 13, 11]
+            .map(key_of)
+            .into_iter()
+            .collect::<Vec<_>>()
+    );
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(10)), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), key_of(10));
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(13));
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(11));
+    assert_eq!(iter.value(), value_of(11, 0));
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(10));
+    assert_eq!(iter.value(), value_of(10, 1));
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(&key_of(22)).unwrap();
+    assert_eq!(iter.value(), value_of(22, 2));
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(19));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(22));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(25));
+}
+
+#[test]
+fn test_txn_reverse_iteration() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    let txn = Arc::new(storage.new_txn().unwrap());
+    txn.put(&key_of(3), &value_of(3, 1));
+    txn.put(&key_of(10), &value_of(10, 1));
+    txn.delete(&key_of(5));
+    txn.delete(&key_of(9));
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek_to_last().unwrap();
+    let keys = collect_keys_backward(&mut iter);
+    let expected = [10, 8, 7, 6, 4, 3, 2, 1, 0]
+        .into_iter()
+        .map(|idx| {
+            let version = if idx == 3 || idx == 10 { 1 } else { 0 };
+            (key_of(idx), value_of(idx, version))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+    iter.seek_for_prev(&key_of(5)).unwrap();
+    assert_eq!(iter.key(), key_of(4));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(6));
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(3));
+    assert_eq!(iter.value(), value_of(3, 1));
+    assert_eq!(
+        storage.get(&key_of(3)).unwrap(),
+        Some(Bytes::from(value_of(3, 0)))
+    );
+}
@@ -0,0 +1,129 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{MiniLsm, ReadOptions},
+};
+
+use super::helpers::{flush, key_of, options, value_of};
+
+fn write_version(storage: &MiniLsm, version: usize) {
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_version(&storage, 0);
+    let snapshot = storage.get_snapshot();
+    assert_eq!(snapshot.read_ts(), 10);
+    write_version(&storage, 1);
+    storage.delete(&key_of(0)).unwrap();
+    storage.put(&key_of(10), b"new").unwrap();
+
+    assert_eq!(
+        snapshot.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+    assert_eq!(snapshot.get(&key_of(10)).unwrap(), None);
+    assert_eq!(
+        snapshot
+            .multi_get(&[key_of(1), key_of(10), key_of(2)])
+            .unwrap(),
+        vec![
+            Some(Bytes::from(value_of(1, 0))),
+            None,
+            Some(Bytes::from(value_of(2, 0)))
+        ]
+    );
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in 0..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 0));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // The snapshot can be passed to the read APIs.
+    let options = ReadOptions {
+        snapshot: Some(snapshot.clone()),
+        ..Default::default()
+    };
+    assert_eq!(
+        storage.get_with_options(&key_of(0), &options).unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+    let mut iter = storage
+        .scan_with_options(Bound::Included(&key_of(5)), Bound::Unbounded, &options)
+        .unwrap();
+    for idx in 5..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 0));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let txn = storage.new_txn().unwrap();
+    assert!(txn.get_with_options(&key_of(0), &options).is_err());
+    let options = ReadOptions {
+        snapshot: Some(snapshot.clone()),
+        read_ts: Some(11),
+        ..Default::default()
+    };
+    assert!(storage.get_with_options(&key_of(0), &options).is_err());
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_watermark() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    write_version(&storage, 0);
+    flush(&storage);
+    let snapshot = storage.get_snapshot();
+    write_version(&storage, 1);
+    assert_eq!(storage.inner.mvcc().watermark(), 10);
+
+    // The versions the snapshot sees survive compaction, even while it is only held by an iterator.
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().watermark(), 10);
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    for idx in 0..10 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 0));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let snapshot = storage.get_snapshot();
+    let old_snapshot_read = ReadOptions {
+        read_ts: Some(10),
+        ..Default::default()
+    };
+    assert_eq!(
+        storage
+            .get_with_options(&key_of(0), &old_snapshot_read)
+            .unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+
+    // Once released, the old versions are dropped by the next compaction, and can no longer be read.
+    drop(iter);
+    assert_eq!(storage.inner.mvcc().watermark(), 20);
+    storage.force_full_compaction().unwrap();
+    assert!(storage
+        .get_with_options(&key_of(0), &old_snapshot_read)
+        .is_err());
+    assert_eq!(
+        snapshot.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 1)))
+    );
+    drop(snapshot);
+    assert_eq!(storage.inner.mvcc().ts.lock().1.num_retained_snapshots(), 0);
+}
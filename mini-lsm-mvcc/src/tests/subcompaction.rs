@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::helpers::{self, flush, key_of, value_of};
+
+fn options(max_subcompactions: usize) -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.max_subcompactions = max_subcompactions;
+    options
+}
+
+fn check_scan(storage: &MiniLsm, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key.as_slice());
+        assert_eq!(iter.value(), value.as_slice());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// The SSTs of L1, which must be sorted and not overlap.
+fn check_l1(storage: &MiniLsm) -> usize {
+    let state = storage.inner.state.read();
+    let ssts = state.levels[0]
+        .1
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .collect::<Vec<_>>();
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key() < pair[1].first_key());
+    }
+    ssts.len()
+}
+
+#[test]
+fn test_subcompactions() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options(4)).unwrap();
+    let mut expected = BTreeMap::new();
+    for version in 0..6 {
+        for idx in (version..1000).step_by(version + 1) {
+            storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+            expected.insert(key_of(idx), value_of(idx, version));
+        }
+        for idx in (version * 7..1000).step_by(13) {
+            storage.delete(&key_of(idx)).unwrap();
+            expected.remove(&key_of(idx));
+        }
+        flush(&storage);
+        if version == 2 {
+            storage.force_full_compaction().unwrap();
+        }
+    }
+    // A range tombstone over every subcompaction.
+    storage.delete_range(&key_of(100), &key_of(900)).unwrap();
+    expected.retain(|key, _| key < &key_of(100) || key >= &key_of(900));
+    flush(&storage);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 4);
+
+    // Each subcompaction writes its own SSTs, while the whole output would fit in one.
+    let stats = storage.inner.compaction_stats();
+    storage.force_full_compaction().unwrap();
+    assert!(check_l1(&storage) >= 4);
+    assert_eq!(
+        storage.inner.compaction_stats().compactions,
+        stats.compactions + 1
+    );
+    check_scan(&storage, &expected);
+    for idx in 0..1000 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            expected.get(&key_of(idx)).cloned().map(Bytes::from)
+        );
+    }
+
+    // More writes over the output of the subcompactions.
+    for idx in (500..1000).step_by(3) {
+        storage.put(&key_of(idx), &value_of(idx, 6)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 6));
+    }
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    check_l1(&storage);
+    check_scan(&storage, &expected);
+}
+
+#[test]
+fn test_subcompactions_same_output() {
+    // The same writes compacted with and without subcompactions read the same.
+    let mut ssts = Vec::new();
+    for max_subcompactions in [1, 3, 16] {
+        let dir = tempdir().unwrap();
+        let storage = MiniLsm::open(&dir, options(max_subcompactions)).unwrap();
+        for version in 0..3 {
+            for idx in (version * 20..300).step_by(version + 2) {
+                storage.put(&key_of(idx), &value_of(idx, version)).unwrap();
+            }
+            storage
+                .delete_range(&key_of(version * 100 + 10), &key_of(version * 100 + 50))
+                .unwrap();
+            flush(&storage);
+        }
+        storage.force_full_compaction().unwrap();
+        ssts.push(check_l1(&storage));
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let mut entries = Vec::new();
+        while iter.is_valid() {
+            entries.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        let expected = (0..300)
+            .filter_map(|idx| {
+                let version = (0..3).rev().find(|&version| {
+                    idx >= version * 20
+                        && (idx - version * 20) % (version + 2) == 0
+                        && !(0..3).any(|tombstone: usize| {
+                            tombstone >= version
+                                && (tombstone * 100 + 10..tombstone * 100 + 50).contains(&idx)
+                        })
+                })?;
+                Some((key_of(idx), value_of(idx, version)))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+    }
+    assert_eq!(ssts[0], 1);
+    assert_eq!(ssts[1], 3);
+    // There are not as many input SSTs as subcompactions.
+    assert_eq!(ssts[2], 3);
+}
+
+#[test]
+fn test_subcompaction_failure() {
+    let dir = tempdir().unwrap();
+    let mut options = options(4);
+    options.block_size = 256;
+    options.target_sst_size = 1024;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for part in 0..4 {
+        for idx in part * 250..(part + 1) * 250 {
+            storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        }
+        flush(&storage);
+    }
+    // Damage the checksum of the last block of the latest SST, so that the subcompaction of the last range
+    // fails after writing some SSTs, while the others succeed.
+    let l0_sstables = storage.inner.state.read().l0_sstables.clone();
+    let sst = {
+        let state = storage.inner.state.read();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    assert!(sst.first_key().key_ref() >= key_of(750).as_slice());
+    let path = storage.inner.path_of_sst(sst.sst_id());
+    let mut data = std::fs::read(&path).unwrap();
+    data[sst.block_meta_offset - 1] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+    let list_ssts = || {
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name.to_string_lossy().ends_with(".sst"))
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    let ssts = list_ssts();
+    assert_eq!(ssts.len(), l0_sstables.len());
+
+    // None of the output SSTs are left behind.
+    assert!(storage.force_full_compaction().is_err());
+    assert_eq!(list_ssts(), ssts);
+    assert_eq!(storage.inner.state.read().l0_sstables, l0_sstables);
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::{
+        CompactionOptions, CompactionStats, LeveledCompactionOptions,
+        SimpleLeveledCompactionOptions,
+    },
+    lsm_storage::{LsmStorageInner, LsmStorageOptions},
+};
+
+use super::helpers::{key_of, value_of, write_sst};
+
+/// A storage without flush and compaction threads, so that the test drives both.
+fn open(dir: &tempfile::TempDir, compaction_options: CompactionOptions) -> Arc<LsmStorageInner> {
+    let mut options = LsmStorageOptions::default_for_week2_test(compaction_options);
+    options.num_memtable_limit = 100;
+    Arc::new(LsmStorageInner::open(dir, options).unwrap())
+}
+
+fn check_values(storage: &Arc<LsmStorageInner>, expected: &[(usize, usize)]) {
+    for &(idx, version) in expected {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, version)))
+        );
+    }
+}
+
+#[test]
+fn test_trivial_move_simple_leveled() {
+    let dir = tempdir().unwrap();
+    let compaction_options = CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let storage = open(&dir, compaction_options.clone());
+    write_sst(&storage, 100..200, 0);
+    write_sst(&storage, 0..100, 0);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+
+    // L0 SSTs not overlapping each other are moved to the empty L1, sorted by key.
+    storage.trigger_compaction().unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert_eq!(state.levels[0].1, l0_sstables);
+    }
+    let moved_bytes = l0_sstables
+        .iter()
+        .map(|id| storage.state.read().sstables[id].table_size())
+        .sum::<u64>();
+    assert_eq!(
+        storage.compaction_stats(),
+        CompactionStats {
+            trivial_moves: 1,
+            moved_ssts: 2,
+            moved_bytes,
+            ..Default::default()
+        }
+    );
+    for id in &l0_sstables {
+        assert!(storage.path_of_sst(*id).exists());
+    }
+
+    // L1 moves on to the empty L2.
+    storage.trigger_compaction().unwrap();
+    assert_eq!(storage.state.read().levels[1].1, l0_sstables);
+    assert_eq!(storage.compaction_stats().trivial_moves, 2);
+
+    // L0 SSTs overlapping each other are rewritten.
+    write_sst(&storage, 250..300, 1);
+    write_sst(&storage, 200..260, 1);
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!((stats.compactions, stats.input_ssts), (1, 2));
+    assert_eq!(stats.trivial_moves, 2);
+    let expected = (0..200)
+        .map(|idx| (idx, 0))
+        .chain((200..300).map(|idx| (idx, 1)))
+        .collect::<Vec<_>>();
+    check_values(&storage, &expected);
+
+    // The moves are replayed from the manifest.
+    let levels = storage.state.read().levels.clone();
+    drop(storage);
+    let storage = open(&dir, compaction_options);
+    assert_eq!(storage.state.read().levels, levels);
+    check_values(&storage, &expected);
+}
+
+#[test]
+fn test_trivial_move_leveled() {
+    let dir = tempdir().unwrap();
+    let storage = open(
+        &dir,
+        CompactionOptions::Leveled(LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        }),
+    );
+    write_sst(&storage, 0..100, 0);
+    write_sst(&storage, 50..150, 1);
+    storage.trigger_compaction().unwrap();
+    let base_level = storage.state.read().levels[2].1.clone();
+    assert!(!base_level.is_empty());
+    assert_eq!(storage.compaction_stats().compactions, 1);
+
+    // SSTs on both sides of the base level are moved in without touching it.
+    write_sst(&storage, 300..400, 2);
+    write_sst(&storage, 160..170, 2);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!((stats.compactions, stats.trivial_moves), (1, 1));
+    let mut expected_level = base_level.clone();
+    expected_level.push(l0_sstables[0]);
+    expected_level.push(l0_sstables[1]);
+    assert_eq!(storage.state.read().levels[2].1, expected_level);
+
+    // One L0 SST overlapping the base level makes the whole task a rewrite.
+    write_sst(&storage, 140..145, 3);
+    write_sst(&storage, 500..510, 3);
+    storage.trigger_compaction().unwrap();
+    let stats = storage.compaction_stats();
+    assert_eq!((stats.compactions, stats.trivial_moves), (2, 1));
+    let expected = (0..50)
+        .map(|idx| (idx, 0))
+        .chain((50..140).map(|idx| (idx, 1)))
+        .chain((140..145).map(|idx| (idx, 3)))
+        .chain((145..150).map(|idx| (idx, 1)))
+        .chain((160..170).map(|idx| (idx, 2)))
+        .chain((300..400).map(|idx| (idx, 2)))
+        .chain((500..510).map(|idx| (idx, 3)))
+        .collect::<Vec<_>>();
+    check_values(&storage, &expected);
+}
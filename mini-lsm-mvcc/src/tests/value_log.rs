@@ -0,0 +1,221 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    value_log::ValueLog,
+};
+
+use super::helpers::{self, flush, key_of};
+
+fn value_of(idx: usize, version: usize) -> Vec<u8> {
+    // Every fourth value is small enough to stay in the SST.
+    if idx % 4 == 3 {
+        format!("small_{}_{}", idx, version).into_bytes()
+    } else {
+        format!("value_{:03}_{}_", idx, version)
+            .into_bytes()
+            .repeat(4096 / 12)
+    }
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.value_log_threshold = Some(1024);
+    options.value_log_gc_ratio = 0.6;
+    options
+}
+
+fn check(storage: &MiniLsm, expected: &[(Vec<u8>, Option<Vec<u8>>)]) {
+    for (key, value) in expected {
+        assert_eq!(
+            storage.get(key).unwrap(),
+            value.as_ref().map(|value| Bytes::copy_from_slice(value)),
+            "{:?}",
+            String::from_utf8_lossy(key)
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        let Some(value) = value else {
+            continue;
+        };
+        assert_eq!(iter.key(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_value_log_separation() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let mut expected = Vec::new();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        expected.push((key_of(idx), Some(value_of(idx, 0))));
+    }
+    flush(&storage);
+    check(&storage, &expected);
+
+    let blob_ids = storage.inner.value_log.file_ids();
+    assert_eq!(blob_ids.len(), 1);
+    let blob_file = storage.inner.value_log.file(blob_ids[0]).unwrap();
+    let state = storage.inner.state.read().clone();
+    let sst = &state.sstables[&state.l0_sstables[0]];
+    assert_eq!(sst.sst_id(), blob_ids[0]);
+    // The SST only holds the small values and the pointers.
+    assert!(sst.table_size() < 16 << 10);
+    assert!(blob_file.data_size() > 75 * 4000);
+
+    // Compaction moves the pointers, not the values.
+    storage.force_full_compaction().unwrap();
+    check(&storage, &expected);
+    assert_eq!(storage.inner.value_log.file_ids(), blob_ids);
+    assert_eq!(
+        storage
+            .inner
+            .value_log
+            .file(blob_ids[0])
+            .unwrap()
+            .generation(),
+        0
+    );
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage, &expected);
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    let first_blob = storage.inner.value_log.file_ids()[0];
+
+    // Overwrite or delete 3/4 of the keys while a transaction still reads the old versions.
+    let txn = storage.new_txn().unwrap();
+    let mut expected = Vec::new();
+    for idx in 0..100 {
+        if idx % 4 == 0 {
+            expected.push((key_of(idx), Some(value_of(idx, 0))));
+        } else if idx % 4 == 1 {
+            storage.delete(&key_of(idx)).unwrap();
+            expected.push((key_of(idx), None));
+        } else {
+            storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+            expected.push((key_of(idx), Some(value_of(idx, 1))));
+        }
+    }
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    storage.gc_value_log().unwrap();
+    // The old versions are below the watermark, nothing is collected.
+    assert_eq!(storage.inner.value_log.garbage_size(first_blob), 0);
+    let blob_file = storage.inner.value_log.file(first_blob).unwrap();
+    assert_eq!(blob_file.generation(), 0);
+    for idx in 0..100 {
+        assert_eq!(
+            txn.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+    }
+    check(&storage, &expected);
+    drop(txn);
+
+    // Once the transaction is gone, compaction drops the old versions and the blob file is rewritten.
+    storage.force_full_compaction().unwrap();
+    let garbage_size = storage.inner.value_log.garbage_size(first_blob);
+    assert!(garbage_size * 2 > blob_file.data_size());
+    assert_eq!(
+        storage
+            .inner
+            .value_log
+            .file(first_blob)
+            .unwrap()
+            .generation(),
+        0
+    );
+    storage.gc_value_log().unwrap();
+    assert_eq!(storage.inner.value_log.garbage_size(first_blob), 0);
+    let rewritten = storage.inner.value_log.file(first_blob).unwrap();
+    assert_eq!(rewritten.generation(), 1);
+    assert!(rewritten.data_size() * 2 < blob_file.data_size());
+    check(&storage, &expected);
+    assert!(!ValueLog::path_of_blob_static(&dir, first_blob, 0).exists());
+    assert!(ValueLog::path_of_blob_static(&dir, first_blob, 1).exists());
+
+    // The blob file that only held overwritten values is removed.
+    let second_blob = storage.inner.value_log.file_ids()[1];
+    for (idx, (key, value)) in expected.iter_mut().enumerate() {
+        if idx % 4 == 2 {
+            storage.delete(key).unwrap();
+            *value = None;
+        }
+    }
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage, &expected);
+
+    // The garbage recorded by compactions survives a restart.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    let second_blob_file = storage.inner.value_log.file(second_blob).unwrap();
+    assert_eq!(
+        storage.inner.value_log.garbage_size(second_blob),
+        second_blob_file.data_size()
+    );
+    storage.gc_value_log().unwrap();
+    let blob_ids = storage.inner.value_log.file_ids();
+    assert!(blob_ids.contains(&first_blob));
+    assert!(!blob_ids.contains(&second_blob));
+    check(&storage, &expected);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.inner.value_log.file_ids(), blob_ids);
+    check(&storage, &expected);
+}
+
+#[test]
+fn test_value_log_gc_ratio() {
+    let dir = tempdir().unwrap();
+    for ratio in [0.0, 1.5, f64::NAN] {
+        let mut options = options();
+        options.value_log_gc_ratio = ratio;
+        assert!(MiniLsm::open(&dir, options).is_err());
+    }
+
+    // With a ratio of 1, every file with garbage is rewritten, and the files without any are left alone.
+    let mut options = options();
+    options.value_log_gc_ratio = 1.0;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..8 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    flush(&storage);
+    let first_blob = storage.inner.value_log.file_ids()[0];
+    storage.put(&key_of(0), &value_of(0, 1)).unwrap();
+    flush(&storage);
+    let second_blob = storage.inner.value_log.file_ids()[1];
+    storage.force_full_compaction().unwrap();
+    storage.gc_value_log().unwrap();
+    let generation_of = |id| storage.inner.value_log.file(id).unwrap().generation();
+    assert_eq!(generation_of(first_blob), 1);
+    assert_eq!(generation_of(second_blob), 0);
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 1)))
+    );
+}
@@ -0,0 +1,302 @@
+use std::hash::Hasher;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes};
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::{KeySlice, ValueType},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    mem_table::MemTable,
+    table::{
+        bloom::Bloom, FileObject, SsTable, SsTableBuilder, SsTableIterator, SST_FORMAT_VERSION,
+    },
+};
+
+use super::helpers::{self, flush, key_of};
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.enable_wal = true;
+    options
+}
+
+/// Keys divisible by 3 hold an empty value, keys divisible by 3 plus 1 are deleted.
+fn check(storage: &MiniLsm) {
+    for idx in 0..30 {
+        let expected = match idx % 3 {
+            0 => Some(Bytes::new()),
+            1 => None,
+            _ => Some(Bytes::from(key_of(idx))),
+        };
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            expected,
+            "key_{:05}",
+            idx
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in (0..30).filter(|idx| idx % 3 != 1) {
+        assert_eq!(iter.key(), key_of(idx));
+        if idx % 3 == 0 {
+            assert_eq!(iter.value(), b"");
+        } else {
+            assert_eq!(iter.value(), key_of(idx));
+        }
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_empty_values() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..30 {
+        storage.put(&key_of(idx), &key_of(idx)).unwrap();
+    }
+    for idx in 0..30 {
+        match idx % 3 {
+            0 => storage.put(&key_of(idx), b"").unwrap(),
+            1 => storage.delete(&key_of(idx)).unwrap(),
+            _ => {}
+        }
+    }
+    check(&storage);
+
+    // Recover from the WAL.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage);
+
+    // Compacting to the bottom level drops the deletions but keeps the empty values.
+    flush(&storage);
+    check(&storage);
+    storage.force_full_compaction().unwrap();
+    check(&storage);
+    let state = storage.inner.state.read().clone();
+    let mut num_entries = 0;
+    for id in &state.levels[0].1 {
+        let mut iter =
+            SsTableIterator::create_and_seek_to_first(state.sstables[id].clone()).unwrap();
+        while iter.is_valid() {
+            assert_eq!(iter.value_type(), ValueType::Put);
+            num_entries += 1;
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(num_entries, 20);
+
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check(&storage);
+
+    // Transactions and write batches store empty values as well.
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"txn_empty", b"");
+    txn.delete(b"txn_deleted");
+    assert_eq!(txn.get(b"txn_empty").unwrap(), Some(Bytes::new()));
+    assert_eq!(txn.get(b"txn_deleted").unwrap(), None);
+    let mut iter = txn.scan(Bound::Included(b"txn"), Bound::Unbounded).unwrap();
+    assert_eq!(iter.key(), b"txn_empty");
+    assert_eq!(iter.value(), b"");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"txn_empty").unwrap(), Some(Bytes::new()));
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"batch_empty"[..], &b""[..]),
+            WriteBatchRecord::Del(&b"txn_empty"[..]),
+        ])
+        .unwrap();
+    assert_eq!(storage.get(b"batch_empty").unwrap(), Some(Bytes::new()));
+    assert_eq!(storage.get(b"txn_empty").unwrap(), None);
+}
+
+/// Encode an SST the way the original format (version 1) did: blocks of entries with u16 lengths that share a
+/// prefix with the first key of the block, followed by u16 offsets, each block with a checksum, then the block
+/// meta with u16 key lengths and a bloom filter.
+fn encode_sst_v1(entries: &[(&[u8], u64, &[u8])], block_size: usize) -> Vec<u8> {
+    fn finish_block(block: &mut Vec<u8>, offsets: &mut Vec<u16>, data: &mut Vec<u8>) {
+        let mut encoded = std::mem::take(block);
+        for offset in offsets.iter() {
+            encoded.put_u16(*offset);
+        }
+        encoded.put_u16(offsets.len() as u16);
+        offsets.clear();
+        let checksum = crc32fast::hash(&encoded);
+        data.extend(encoded);
+        data.put_u32(checksum);
+    }
+
+    let mut data = Vec::new();
+    let mut meta = Vec::new();
+    let mut block = Vec::new();
+    let mut offsets = Vec::<u16>::new();
+    // The offset, first key and last key of the current block.
+    let mut block_meta = None;
+    for &(key, ts, value) in entries {
+        let estimated_size = 2 + offsets.len() * 2 + block.len();
+        if estimated_size + key.len() + 8 + value.len() + 6 > block_size && !offsets.is_empty() {
+            finish_block(&mut block, &mut offsets, &mut data);
+            meta.extend(block_meta.take());
+        }
+        let (_, (first_key, _), last_key) =
+            block_meta.get_or_insert((data.len(), (key, ts), (key, ts)));
+        *last_key = (key, ts);
+        // The first key of the block is stored in full.
+        let overlap = if offsets.is_empty() {
+            0
+        } else {
+            first_key
+                .iter()
+                .zip(key)
+                .take_while(|(x, y)| x == y)
+                .count()
+        };
+        offsets.push(block.len() as u16);
+        block.put_u16(overlap as u16);
+        block.put_u16((key.len() - overlap) as u16);
+        block.put_slice(&key[overlap..]);
+        block.put_u64(ts);
+        block.put_u16(value.len() as u16);
+        block.put_slice(value);
+    }
+    finish_block(&mut block, &mut offsets, &mut data);
+    meta.extend(block_meta.take());
+
+    let mut buf = data;
+    let meta_offset = buf.len();
+    buf.put_u32(meta.len() as u32);
+    for (offset, (first_key, first_ts), (last_key, last_ts)) in &meta {
+        buf.put_u32(*offset as u32);
+        buf.put_u16(first_key.len() as u16);
+        buf.put_slice(first_key);
+        buf.put_u64(*first_ts);
+        buf.put_u16(last_key.len() as u16);
+        buf.put_slice(last_key);
+        buf.put_u64(*last_ts);
+    }
+    buf.put_u64(entries.iter().map(|(_, ts, _)| *ts).max().unwrap());
+    let checksum = crc32fast::hash(&buf[meta_offset + 4..]);
+    buf.put_u32(checksum);
+    buf.put_u32(meta_offset as u32);
+    let key_hashes = entries
+        .iter()
+        .map(|(key, _, _)| farmhash::fingerprint32(key))
+        .collect::<Vec<_>>();
+    let bloom = Bloom::build_from_key_hashes(
+        &key_hashes,
+        Bloom::bloom_bits_per_key(key_hashes.len(), 0.01),
+    );
+    let bloom_offset = buf.len();
+    bloom.encode(&mut buf);
+    buf.put_u32(bloom_offset as u32);
+    buf
+}
+
+#[test]
+fn test_legacy_sst() {
+    let dir = tempdir().unwrap();
+    // Every third key is deleted, which version 1 stores as an empty value.
+    let keys = (0..30).map(key_of).collect::<Vec<_>>();
+    let values = (0..30)
+        .map(|idx| match idx % 3 {
+            0 => Vec::new(),
+            _ => format!("value_{}", idx).into_bytes(),
+        })
+        .collect::<Vec<_>>();
+    let entries = (0..30)
+        .map(|idx| (&keys[idx][..], idx as u64 + 1, &values[idx][..]))
+        .collect::<Vec<_>>();
+    let path = dir.path().join("1.sst");
+    std::fs::write(&path, encode_sst_v1(&entries, 128)).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.format_version, 1);
+    assert!(sst.num_of_blocks() > 1);
+    assert_eq!(sst.first_key().key_ref(), key_of(0));
+    assert_eq!(sst.last_key().key_ref(), key_of(29));
+    assert_eq!(sst.max_ts(), 30);
+    assert!(sst.range_tombstones.is_empty());
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for &(key, ts, value) in &entries {
+        assert_eq!(iter.key(), KeySlice::from_slice(key, ts));
+        assert_eq!(iter.value(), value);
+        let value_type = if value.is_empty() {
+            ValueType::Delete
+        } else {
+            ValueType::Put
+        };
+        assert_eq!(iter.value_type(), value_type);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_to_key(sst, KeySlice::from_slice(&key_of(17), 18))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(17));
+    assert_eq!(iter.value(), b"value_17");
+
+    // Entries added without a type follow the original formats, so an empty value is only kept with its type.
+    let mut builder = SsTableBuilder::new(128);
+    builder.add_with_type(KeySlice::from_slice(b"a", 1), ValueType::Put, b"");
+    builder.add(KeySlice::from_slice(b"b", 1), b"");
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.format_version, SST_FORMAT_VERSION);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    assert_eq!(iter.value_type(), ValueType::Put);
+    iter.next().unwrap();
+    assert_eq!(iter.value_type(), ValueType::Delete);
+}
+
+#[test]
+fn test_legacy_wal() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    // A WAL of version 1 has no header, and its records hold u16 lengths and no value type.
+    let mut buf = Vec::new();
+    for (key, value) in [(&b"a"[..], &b"value"[..]), (b"b", b"")] {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.write_u16(key.len() as u16);
+        buf.put_u16(key.len() as u16);
+        hasher.write(key);
+        buf.put_slice(key);
+        hasher.write_u64(1);
+        buf.put_u64(1);
+        hasher.write_u16(value.len() as u16);
+        buf.put_u16(value.len() as u16);
+        buf.put_slice(value);
+        hasher.write(value);
+        buf.put_u32(hasher.finalize());
+    }
+    std::fs::write(&path, buf).unwrap();
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    assert_eq!(iter.key().key_ref(), b"a");
+    assert_eq!(iter.value_type(), ValueType::Put);
+    iter.next().unwrap();
+    assert_eq!(iter.key().key_ref(), b"b");
+    assert_eq!(iter.value_type(), ValueType::Delete);
+    // Old WALs are only replayed.
+    assert!(memtable.put(KeySlice::from_slice(b"c", 2), b"").is_err());
+
+    let path = dir.path().join("2.wal");
+    let memtable = MemTable::create_with_wal(2, &path).unwrap();
+    memtable.put(KeySlice::from_slice(b"a", 1), b"").unwrap();
+    memtable.delete(KeySlice::from_slice(b"b", 1)).unwrap();
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+    let memtable = MemTable::recover_from_wal(2, &path).unwrap();
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    assert_eq!(iter.value(), b"");
+    assert_eq!(iter.value_type(), ValueType::Put);
+    iter.next().unwrap();
+    assert_eq!(iter.value_type(), ValueType::Delete);
+}
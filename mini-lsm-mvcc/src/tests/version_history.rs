@@ -0,0 +1,140 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::{KeySlice, ValueType},
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+};
+
+use super::helpers::{self, flush};
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.value_log_threshold = Some(1024);
+    options
+}
+
+fn large_value() -> Vec<u8> {
+    b"large".repeat(1024)
+}
+
+/// Collect the (key, ts, value type, value) versions of an iterator.
+fn collect<I>(mut iter: I) -> Vec<(Vec<u8>, u64, ValueType, Vec<u8>)>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+{
+    let mut versions = Vec::new();
+    while iter.is_valid() {
+        versions.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value_type(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    versions
+}
+
+fn put(key: &[u8], ts: u64, value: &[u8]) -> (Vec<u8>, u64, ValueType, Vec<u8>) {
+    (key.to_vec(), ts, ValueType::Put, value.to_vec())
+}
+
+fn delete(key: &[u8], ts: u64) -> (Vec<u8>, u64, ValueType, Vec<u8>) {
+    (key.to_vec(), ts, ValueType::Delete, Vec::new())
+}
+
+#[test]
+fn test_version_history() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    flush(&storage);
+    storage.put(b"a", &large_value()).unwrap();
+    storage.delete(b"b").unwrap();
+    flush(&storage);
+    storage.delete_range(b"a", b"c").unwrap();
+    storage.put(b"a", b"3").unwrap();
+    storage.put(b"c", b"").unwrap();
+
+    let a_versions = vec![
+        put(b"a", 6, b"3"),
+        delete(b"a", 5),
+        put(b"a", 3, &large_value()),
+        put(b"a", 1, b"1"),
+    ];
+    assert_eq!(
+        collect(storage.key_versions(b"a", 0..=u64::MAX).unwrap()),
+        a_versions
+    );
+    let b_versions = vec![delete(b"b", 5), delete(b"b", 4), put(b"b", 2, b"1")];
+    let all_versions = a_versions
+        .iter()
+        .chain(b_versions.iter())
+        .cloned()
+        .chain([put(b"c", 7, b"")])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        collect(
+            storage
+                .scan_versions(Bound::Unbounded, Bound::Unbounded, 0..=u64::MAX)
+                .unwrap()
+        ),
+        all_versions
+    );
+    assert_eq!(
+        collect(
+            storage
+                .scan_versions(Bound::Excluded(b"a"), Bound::Excluded(b"c"), 0..=u64::MAX)
+                .unwrap()
+        ),
+        b_versions
+    );
+    // Only the versions within the window are yielded.
+    assert_eq!(
+        collect(
+            storage
+                .scan_versions(Bound::Unbounded, Bound::Unbounded, 2..=4)
+                .unwrap()
+        ),
+        vec![
+            put(b"a", 3, &large_value()),
+            delete(b"b", 4),
+            put(b"b", 2, b"1")
+        ]
+    );
+    assert_eq!(
+        collect(storage.key_versions(b"b", 5..=5).unwrap()),
+        vec![delete(b"b", 5)]
+    );
+    assert!(collect(storage.key_versions(b"d", 0..=u64::MAX).unwrap()).is_empty());
+    // An excluded bound skips every version of the key in the memtables as well.
+    let iter = storage
+        .scan(Bound::Excluded(b"a"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.key(), b"c");
+    drop(iter);
+
+    // Compaction keeps only the versions that may still be read.
+    let snapshot = storage.get_snapshot();
+    storage.put(b"c", b"8").unwrap();
+    flush(&storage);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        collect(
+            storage
+                .scan_versions(Bound::Unbounded, Bound::Unbounded, 0..=u64::MAX)
+                .unwrap()
+        ),
+        vec![put(b"a", 6, b"3"), put(b"c", 8, b"8"), put(b"c", 7, b"")]
+    );
+    drop(snapshot);
+    storage.force_full_compaction().unwrap();
+    assert_eq!(
+        collect(storage.key_versions(b"c", 0..=u64::MAX).unwrap()),
+        vec![put(b"c", 8, b"8")]
+    );
+}
@@ -0,0 +1,190 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::ValueType,
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord},
+    mem_table::MemTable,
+    wal::WalRecoveryMode,
+};
+
+use super::helpers::{self, key_of, value_of};
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.enable_wal = true;
+    // The tests tear the tail of the WAL.
+    options.wal_recovery_mode = WalRecoveryMode::TolerateCorruptedTailRecords;
+    options
+}
+
+fn truncate(path: &Path, len: u64) {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_len(len)
+        .unwrap();
+}
+
+/// Collect the (key, ts, value type, value) entries of a memtable.
+fn memtable_entries(memtable: &MemTable) -> Vec<(Vec<u8>, u64, ValueType, Vec<u8>)> {
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((
+            iter.key().key_ref().to_vec(),
+            iter.key().ts(),
+            iter.value_type(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_wal_batch_truncated() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = MemTable::create_with_wal(1, &path).unwrap();
+    memtable
+        .put_batch(
+            1,
+            &[
+                (b"a", ValueType::Put, b"1"),
+                (b"b", ValueType::Put, b""),
+                (b"c", ValueType::Put, b"3"),
+            ],
+        )
+        .unwrap();
+    memtable.sync_wal().unwrap();
+    let first_batch_end = std::fs::metadata(&path).unwrap().len();
+    let first_batch = memtable_entries(&memtable);
+    memtable
+        .put_batch(
+            2,
+            &[
+                (b"a", ValueType::Put, b"11"),
+                (b"b", ValueType::Delete, b""),
+                (b"d", ValueType::Put, b"4"),
+            ],
+        )
+        .unwrap();
+    memtable.sync_wal().unwrap();
+    let all_batches = memtable_entries(&memtable);
+    let wal = std::fs::read(&path).unwrap();
+    drop(memtable);
+
+    let recovered = MemTable::recover_from_wal(1, &path).unwrap();
+    assert_eq!(memtable_entries(&recovered), all_batches);
+    drop(recovered);
+
+    // Wherever the second batch is cut, none of it is recovered.
+    for len in first_batch_end..wal.len() as u64 {
+        let path = dir.path().join(format!("truncated_{}.wal", len));
+        std::fs::write(&path, &wal).unwrap();
+        truncate(&path, len);
+        let (recovered, _) = MemTable::recover_from_wal_with_mode(
+            2,
+            &path,
+            WalRecoveryMode::TolerateCorruptedTailRecords,
+        )
+        .unwrap();
+        assert_eq!(
+            memtable_entries(&recovered),
+            first_batch,
+            "truncated at {}",
+            len
+        );
+    }
+
+    // A damaged batch followed by a valid one is not silently dropped.
+    let path = dir.path().join("corrupted.wal");
+    let mut corrupted = wal.clone();
+    corrupted[first_batch_end as usize - 10] ^= 0xff;
+    std::fs::write(&path, &corrupted).unwrap();
+    assert!(MemTable::recover_from_wal_with_mode(
+        3,
+        &path,
+        WalRecoveryMode::TolerateCorruptedTailRecords
+    )
+    .is_err());
+}
+
+#[test]
+fn test_integration_wal_batch_truncated() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    let batch = (0..10)
+        .map(|idx| {
+            if idx % 2 == 0 {
+                WriteBatchRecord::Put(key_of(idx), value_of(idx, 1))
+            } else {
+                WriteBatchRecord::Del(key_of(idx))
+            }
+        })
+        .collect::<Vec<_>>();
+    storage.write_batch(&batch).unwrap();
+    let memtable_id = storage.inner.state.read().memtable.id();
+    let wal_path = storage.inner.path_of_wal(memtable_id);
+    storage.sync().unwrap();
+    let batch_end = std::fs::metadata(&wal_path).unwrap().len();
+    let txn = storage.new_txn().unwrap();
+    for idx in 5..15 {
+        txn.put(&key_of(idx), &value_of(idx, 2));
+    }
+    txn.commit().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let wal_end = std::fs::metadata(&wal_path).unwrap().len();
+
+    let check_batch_applied = |storage: &MiniLsm| {
+        for idx in 0..10 {
+            let expected = (idx % 2 == 0).then(|| Bytes::from(value_of(idx, 1)));
+            assert_eq!(storage.get(&key_of(idx)).unwrap(), expected);
+        }
+    };
+
+    // Lose the tail of the transaction's commit: none of its writes survive.
+    truncate(&wal_path, (batch_end + wal_end) / 2);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check_batch_applied(&storage);
+    for idx in 10..15 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), None);
+    }
+    storage.close().unwrap();
+    drop(storage);
+
+    // Lose the tail of the write batch: the keys keep their values from before the batch.
+    truncate(&wal_path, batch_end - 10);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..10 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+    }
+    // Writes after recovery are not affected by the torn tail.
+    storage
+        .write_batch(&[WriteBatchRecord::Put(key_of(0), value_of(0, 3))])
+        .unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 3)))
+    );
+    assert_eq!(
+        storage.get(&key_of(1)).unwrap(),
+        Some(Bytes::from(value_of(1, 0)))
+    );
+}
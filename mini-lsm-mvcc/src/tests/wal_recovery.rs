@@ -0,0 +1,200 @@
+use std::ops::Bound;
+use std::path::Path;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    compact::CompactionOptions,
+    iterators::StorageIterator,
+    key::ValueType,
+    lsm_storage::{LsmStorageOptions, MiniLsm},
+    mem_table::MemTable,
+    wal::{DroppedRecords, WalRecoveryMode},
+};
+
+/// Write 4 batches of 3 keys each, returns the content of the WAL and the offset where each batch ends.
+fn generate_wal(path: &Path) -> (Vec<u8>, Vec<usize>) {
+    let memtable = MemTable::create_with_wal(1, path).unwrap();
+    let mut batch_ends = Vec::new();
+    for batch in 0..4 {
+        let keys = (0..3)
+            .map(|idx| format!("key_{}_{}", batch, idx).into_bytes())
+            .collect::<Vec<_>>();
+        let entries = keys
+            .iter()
+            .map(|key| (&key[..], ValueType::Put, &b"value"[..]))
+            .collect::<Vec<_>>();
+        memtable.put_batch(batch as u64 + 1, &entries).unwrap();
+        memtable.sync_wal().unwrap();
+        batch_ends.push(std::fs::metadata(path).unwrap().len() as usize);
+    }
+    (std::fs::read(path).unwrap(), batch_ends)
+}
+
+/// Returns the batches found in the memtable.
+fn recovered_batches(memtable: &MemTable) -> Vec<u64> {
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    let mut batches = Vec::new();
+    while iter.is_valid() {
+        batches.push(iter.key().ts());
+        iter.next().unwrap();
+    }
+    batches.dedup();
+    batches
+}
+
+fn recover(
+    path: &Path,
+    wal: &[u8],
+    recovery_mode: WalRecoveryMode,
+) -> Option<(Vec<u64>, Vec<DroppedRecords>)> {
+    std::fs::write(path, wal).unwrap();
+    let (memtable, report) = MemTable::recover_from_wal_with_mode(1, path, recovery_mode).ok()?;
+    let batches = recovered_batches(&memtable);
+    assert_eq!(report.num_records, batches.len());
+    Some((batches, report.dropped))
+}
+
+#[test]
+fn test_wal_recovery_truncated_tail() {
+    let dir = tempdir().unwrap();
+    let (wal, batch_ends) = generate_wal(&dir.path().join("1.wal"));
+    let path = dir.path().join("2.wal");
+    let truncated = &wal[..batch_ends[2] + 10];
+    assert!(recover(&path, truncated, WalRecoveryMode::AbsoluteConsistency).is_none());
+    for recovery_mode in [
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+        WalRecoveryMode::PointInTime,
+        WalRecoveryMode::SkipAnyCorruptedRecords,
+    ] {
+        let (batches, dropped) = recover(&path, truncated, recovery_mode).unwrap();
+        assert_eq!(batches, vec![1, 2, 3], "{:?}", recovery_mode);
+        assert_eq!(
+            dropped,
+            vec![DroppedRecords {
+                offset: batch_ends[2],
+                len: 10,
+                reason: "incomplete record".to_string(),
+            }]
+        );
+    }
+
+    // Cut within the length of the record.
+    let truncated = &wal[..batch_ends[3] - 2];
+    let (batches, _) = recover(
+        &path,
+        truncated,
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+    )
+    .unwrap();
+    assert_eq!(batches, vec![1, 2, 3]);
+    let truncated = &wal[..batch_ends[2] + 2];
+    let (batches, _) = recover(
+        &path,
+        truncated,
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+    )
+    .unwrap();
+    assert_eq!(batches, vec![1, 2, 3]);
+
+    // A complete but damaged last record is a torn write as well.
+    let mut corrupted = wal.clone();
+    corrupted[batch_ends[3] - 1] ^= 0xff;
+    assert!(recover(&path, &corrupted, WalRecoveryMode::AbsoluteConsistency).is_none());
+    let (batches, dropped) = recover(
+        &path,
+        &corrupted,
+        WalRecoveryMode::TolerateCorruptedTailRecords,
+    )
+    .unwrap();
+    assert_eq!(batches, vec![1, 2, 3]);
+    assert_eq!(dropped[0].reason, "checksum mismatch");
+}
+
+#[test]
+fn test_wal_recovery_corrupted_record() {
+    let dir = tempdir().unwrap();
+    let (wal, batch_ends) = generate_wal(&dir.path().join("1.wal"));
+    let path = dir.path().join("2.wal");
+    // Damage the second batch, and the length of the third one.
+    let mut corrupted = wal.clone();
+    corrupted[batch_ends[0] + 20] ^= 0xff;
+    corrupted[batch_ends[1]] ^= 0xff;
+
+    assert!(recover(&path, &corrupted, WalRecoveryMode::AbsoluteConsistency).is_none());
+    assert!(recover(
+        &path,
+        &corrupted,
+        WalRecoveryMode::TolerateCorruptedTailRecords
+    )
+    .is_none());
+
+    let (batches, dropped) = recover(&path, &corrupted, WalRecoveryMode::PointInTime).unwrap();
+    assert_eq!(batches, vec![1]);
+    assert_eq!(
+        dropped,
+        vec![DroppedRecords {
+            offset: batch_ends[0],
+            len: wal.len() - batch_ends[0],
+            reason: "checksum mismatch".to_string(),
+        }]
+    );
+
+    let (batches, dropped) =
+        recover(&path, &corrupted, WalRecoveryMode::SkipAnyCorruptedRecords).unwrap();
+    assert_eq!(batches, vec![1, 4]);
+    assert_eq!(
+        dropped,
+        vec![DroppedRecords {
+            offset: batch_ends[0],
+            len: batch_ends[2] - batch_ends[0],
+            reason: "checksum mismatch".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_integration_wal_recovery_mode() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::NoCompaction);
+    options.enable_wal = true;
+    // Flushes are driven by the test.
+    options.num_memtable_limit = 100;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..10 {
+        storage
+            .put(format!("key_{}", idx).as_bytes(), b"value")
+            .unwrap();
+    }
+    let memtable_id = storage.inner.state.read().memtable.id();
+    let wal_path = storage.inner.path_of_wal(memtable_id);
+    storage.close().unwrap();
+    drop(storage);
+    let mut wal = std::fs::read(&wal_path).unwrap();
+    // Damage the 9th of the 10 records, which all have the same length, after the 8-byte header.
+    let record_len = (wal.len() - 8) / 10;
+    wal[8 + record_len * 8 + 10] ^= 0xff;
+    std::fs::write(&wal_path, &wal).unwrap();
+
+    options.wal_recovery_mode = WalRecoveryMode::AbsoluteConsistency;
+    assert!(MiniLsm::open(&dir, options.clone()).is_err());
+
+    options.wal_recovery_mode = WalRecoveryMode::PointInTime;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let reports = storage.wal_recovery_reports();
+    let (id, report) = reports
+        .iter()
+        .find(|(_, report)| !report.dropped.is_empty())
+        .unwrap();
+    assert_eq!(*id, memtable_id);
+    assert_eq!(report.num_records, 8);
+    assert_eq!(report.dropped.len(), 1);
+    for idx in 0..10 {
+        let expected = (idx < 8).then(|| Bytes::from_static(b"value"));
+        assert_eq!(
+            storage.get(format!("key_{}", idx).as_bytes()).unwrap(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::ops::Bound;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::{
+    iterators::StorageIterator,
+    key::{KeySlice, ValueType, TS_RANGE_BEGIN, TS_RANGE_END},
+    lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord, WriteOptions},
+    mem_table::MemTable,
+};
+
+use super::helpers::{self, flush, key_of, value_of};
+
+fn options() -> LsmStorageOptions {
+    let mut options = helpers::options();
+    options.enable_wal = true;
+    options
+}
+
+const SYNC: WriteOptions = WriteOptions {
+    sync: true,
+    disable_wal: false,
+};
+
+const NO_WAL: WriteOptions = WriteOptions {
+    sync: false,
+    disable_wal: true,
+};
+
+/// Replay the WAL of the current memtable as a crash would leave it.
+fn recover_current_wal(storage: &MiniLsm) -> MemTable {
+    let memtable_id = storage.inner.state.read().memtable.id();
+    MemTable::recover_from_wal(memtable_id, storage.inner.path_of_wal(memtable_id)).unwrap()
+}
+
+/// The latest value of a key in a memtable, `None` if it is deleted or missing.
+fn latest(memtable: &MemTable, key: &[u8]) -> Option<Bytes> {
+    let iter = memtable.scan(
+        Bound::Included(KeySlice::from_slice(key, TS_RANGE_BEGIN)),
+        Bound::Included(KeySlice::from_slice(key, TS_RANGE_END)),
+    );
+    (iter.is_valid() && iter.value_type() == ValueType::Put)
+        .then(|| Bytes::copy_from_slice(iter.value()))
+}
+
+#[test]
+fn test_write_options_sync() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"unsynced", b"1").unwrap();
+    storage.put_with_options(b"synced", b"2", &SYNC).unwrap();
+    assert_eq!(storage.inner.num_wal_syncs.load(Ordering::Relaxed), 1);
+    // The synced write and every write before it are on disk without `MiniLsm::sync`.
+    let recovered = recover_current_wal(&storage);
+    assert_eq!(
+        latest(&recovered, b"unsynced"),
+        Some(Bytes::from_static(b"1"))
+    );
+    assert_eq!(
+        latest(&recovered, b"synced"),
+        Some(Bytes::from_static(b"2"))
+    );
+
+    storage.delete_with_options(b"synced", &SYNC).unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"txn", b"3");
+    txn.commit_with_options(&SYNC).unwrap();
+    storage
+        .write_batch_with_options(&[WriteBatchRecord::Put(&b"batch"[..], &b"4"[..])], &SYNC)
+        .unwrap();
+    assert_eq!(storage.inner.num_wal_syncs.load(Ordering::Relaxed), 4);
+    let recovered = recover_current_wal(&storage);
+    assert_eq!(latest(&recovered, b"synced"), None);
+    assert_eq!(latest(&recovered, b"txn"), Some(Bytes::from_static(b"3")));
+    assert_eq!(latest(&recovered, b"batch"), Some(Bytes::from_static(b"4")));
+}
+
+#[test]
+fn test_write_options_disable_wal() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_options(b"b", b"2", &NO_WAL).unwrap();
+    storage.put_with_options(b"c", b"3", &NO_WAL).unwrap();
+    storage.delete_with_options(b"a", &NO_WAL).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+
+    // Writes without the WAL are lost on reopen unless their memtable was flushed.
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), Some(Bytes::from_static(b"1")));
+    assert_eq!(storage.get(b"b").unwrap(), None);
+
+    storage.put_with_options(b"b", b"2", &NO_WAL).unwrap();
+    flush(&storage);
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(storage.get(b"b").unwrap(), Some(Bytes::from_static(b"2")));
+}
+
+#[test]
+fn test_group_commit() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(MiniLsm::open(&dir, options()).unwrap());
+    let num_writers = 8;
+    let write_lock = storage.inner.mvcc().write_lock.lock();
+    let handles = (0..num_writers)
+        .map(|idx| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                storage
+                    .put_with_options(&key_of(idx), &value_of(idx, 0), &SYNC)
+                    .unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+    // Let every writer queue its write while the lock is held, so they all commit in one group.
+    while storage.inner.write_queue.lock().len() < num_writers {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    drop(write_lock);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(storage.inner.num_wal_syncs.load(Ordering::Relaxed), 1);
+    assert!(storage.inner.write_queue.lock().is_empty());
+    // Each write of the group has its own commit ts.
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), num_writers as u64);
+    let recovered = recover_current_wal(&storage);
+    for idx in 0..num_writers {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+        assert_eq!(
+            latest(&recovered, &key_of(idx)),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+    }
+}
+
+#[test]
+fn test_group_commit_failure() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(MiniLsm::open(&dir, options()).unwrap());
+    // Commit the writes in one group, in order, with every WAL write failing once buffered data reaches the file.
+    let commit_group = |writes: Vec<(usize, Vec<u8>, WriteOptions)>| {
+        let write_lock = storage.inner.mvcc().write_lock.lock();
+        let handles = writes
+            .into_iter()
+            .enumerate()
+            .map(|(pos, (idx, value, options))| {
+                let writer = storage.clone();
+                let handle = std::thread::spawn(move || {
+                    writer.put_with_options(&key_of(idx), &value, &options)
+                });
+                while storage.inner.write_queue.lock().len() <= pos {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                handle
+            })
+            .collect::<Vec<_>>();
+        let full = File::options().write(true).open("/dev/full").unwrap();
+        storage
+            .inner
+            .state
+            .read()
+            .memtable
+            .redirect_wal_for_test(full);
+        drop(write_lock);
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().is_ok())
+            .collect::<Vec<_>>()
+    };
+
+    // The second batch is too large to be buffered, and fails. The first one is committed, the last one is not
+    // written.
+    let results = commit_group(vec![
+        (0, value_of(0, 0), WriteOptions::default()),
+        (1, b"large".repeat(4096), WriteOptions::default()),
+        (2, value_of(2, 0), WriteOptions::default()),
+    ]);
+    assert_eq!(results, vec![true, false, false]);
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), 1);
+    assert_eq!(
+        storage.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(value_of(0, 0)))
+    );
+    assert_eq!(storage.get(&key_of(1)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(2)).unwrap(), None);
+
+    // A failed sync fails the batches that asked for it, though they are committed like the others.
+    let results = commit_group(vec![
+        (3, value_of(3, 0), SYNC),
+        (4, value_of(4, 0), WriteOptions::default()),
+    ]);
+    assert_eq!(results, vec![false, true]);
+    assert_eq!(storage.inner.mvcc().latest_commit_ts(), 3);
+    for idx in 3..5 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, 0)))
+        );
+    }
+}
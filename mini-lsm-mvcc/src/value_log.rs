@@ -0,0 +1,434 @@
+//! Key-value separation in the style of WiscKey. Values of at least `value_log_threshold` bytes are moved to
+//! blob files when a memtable is flushed, and the SST stores a [`ValuePointer`] to the value instead. Compaction
+//! copies the pointers around without touching the values, and records the ones it drops as garbage. The blob
+//! garbage collector reclaims the space of the files with enough garbage.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::FileObject;
+
+/// Locates a value in a blob file. SSTs store the encoded pointer as the value of a `ValueType::BlobIndex` entry.
+///
+/// The offset is where the record was first written. Rewriting a blob file keeps its id and maps the original
+/// offsets to the new ones, so the pointers held by SSTs stay valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ValuePointer {
+    pub file_id: usize,
+    pub offset: u64,
+    /// The length of the whole record.
+    pub len: u32,
+}
+
+impl ValuePointer {
+    const ENCODED_LEN: usize = 20;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("invalid value pointer of {} bytes", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+}
+
+/// Encode a blob record: key_len (u32) | key | ts (u64) | value_len (u32) | value | checksum (u32). The key is
+/// kept so that a blob file can be related back to the entries it holds values for.
+fn encode_record(key: KeySlice, value: &[u8], buf: &mut Vec<u8>) {
+    let begin = buf.len();
+    buf.put_u32(key.key_len() as u32);
+    buf.put_slice(key.key_ref());
+    buf.put_u64(key.ts());
+    buf.put_u32(value.len() as u32);
+    buf.put_slice(value);
+    let checksum = crc32fast::hash(&buf[begin..]);
+    buf.put_u32(checksum);
+}
+
+/// Decode the record at the beginning of `data`, returns the value and the length of the record.
+fn decode_record(data: &[u8]) -> Result<(&[u8], usize)> {
+    let mut buf = data;
+    let key_len = buf.get_u32() as usize;
+    buf.advance(key_len + std::mem::size_of::<u64>());
+    let value_len = buf.get_u32() as usize;
+    let value = &buf[..value_len];
+    buf.advance(value_len);
+    let len = data.len() - buf.len();
+    if buf.get_u32() != crc32fast::hash(&data[..len]) {
+        bail!("blob record checksum mismatched");
+    }
+    Ok((value, len + std::mem::size_of::<u32>()))
+}
+
+/// A blob file holds the separated values of one flushed SST, and shares its id.
+///
+/// Layout: records | remap entries (original offset u64, offset u64) | number of remap entries (u32) | checksum
+/// of the remap section (u32). A file written by a flush is generation 0 and has no remap entries; each rewrite by
+/// the garbage collector produces the next generation, which only holds the live records.
+pub struct BlobFile {
+    id: usize,
+    generation: usize,
+    file: FileObject,
+    /// Original offset -> offset in this file, sorted by original offset.
+    remap: Vec<(u64, u64)>,
+    data_size: u64,
+}
+
+impl BlobFile {
+    pub fn open(id: usize, generation: usize, file: FileObject) -> Result<Self> {
+        let footer_len = (std::mem::size_of::<u32>() * 2) as u64;
+        if file.size() < footer_len {
+            bail!("blob file {} is too short", id);
+        }
+        let mut footer = &file.read(file.size() - footer_len, footer_len)?[..];
+        let num_remap = footer.get_u32();
+        let checksum = footer.get_u32();
+        let remap_len = num_remap as u64 * 16;
+        let Some(data_size) = (file.size() - footer_len).checked_sub(remap_len) else {
+            bail!("blob file {} has a corrupted footer", id);
+        };
+        let mut remap_section = file.read(data_size, remap_len)?;
+        remap_section.put_u32(num_remap);
+        if checksum != crc32fast::hash(&remap_section) {
+            bail!("blob file {} footer checksum mismatched", id);
+        }
+        let mut buf = &remap_section[..remap_len as usize];
+        let remap = (0..num_remap)
+            .map(|_| (buf.get_u64(), buf.get_u64()))
+            .collect();
+        Ok(Self {
+            id,
+            generation,
+            file,
+            remap,
+            data_size,
+        })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// The total size of the records in the file.
+    pub fn data_size(&self) -> u64 {
+        self.data_size
+    }
+
+    fn physical_offset(&self, original_offset: u64) -> Option<u64> {
+        if self.generation == 0 {
+            return Some(original_offset);
+        }
+        self.remap
+            .binary_search_by_key(&original_offset, |(original, _)| *original)
+            .ok()
+            .map(|idx| self.remap[idx].1)
+    }
+
+    /// Read the value a pointer refers to.
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Bytes> {
+        let Some(offset) = self.physical_offset(pointer.offset) else {
+            bail!(
+                "value at offset {} has been collected from blob file {}",
+                pointer.offset,
+                self.id
+            );
+        };
+        let data = self.file.read(offset, pointer.len as u64)?;
+        let (value, _) = decode_record(&data)?;
+        Ok(Bytes::copy_from_slice(value))
+    }
+
+    /// Copy the records whose original offsets are not in `garbage` into the next generation of the file.
+    fn rewrite(&self, garbage: &HashSet<u64>, path: &Path) -> Result<Self> {
+        let data = self.file.read(0, self.data_size)?;
+        let mut builder = BlobFileBuilder::new(self.id);
+        let mut offset = 0;
+        let mut idx = 0;
+        while offset < data.len() {
+            let (_, len) = decode_record(&data[offset..])?;
+            let original_offset = if self.generation == 0 {
+                offset as u64
+            } else {
+                self.remap[idx].0
+            };
+            if !garbage.contains(&original_offset) {
+                builder
+                    .remap
+                    .push((original_offset, builder.data.len() as u64));
+                builder.data.extend_from_slice(&data[offset..offset + len]);
+            }
+            offset += len;
+            idx += 1;
+        }
+        builder.build_generation(self.generation + 1, path)
+    }
+}
+
+/// Builds a blob file.
+pub struct BlobFileBuilder {
+    id: usize,
+    data: Vec<u8>,
+    remap: Vec<(u64, u64)>,
+}
+
+impl BlobFileBuilder {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            data: Vec::new(),
+            remap: Vec::new(),
+        }
+    }
+
+    /// Append a value to the blob file and return the pointer to it.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> ValuePointer {
+        let offset = self.data.len();
+        encode_record(key, value, &mut self.data);
+        ValuePointer {
+            file_id: self.id,
+            offset: offset as u64,
+            len: (self.data.len() - offset) as u32,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Write the blob file to `path`.
+    pub fn build(self, path: impl AsRef<Path>) -> Result<BlobFile> {
+        self.build_generation(0, path.as_ref())
+    }
+
+    fn build_generation(mut self, generation: usize, path: &Path) -> Result<BlobFile> {
+        let data_size = self.data.len() as u64;
+        let mut buf = self.data;
+        let remap_begin = buf.len();
+        for (original, offset) in &self.remap {
+            buf.put_u64(*original);
+            buf.put_u64(*offset);
+        }
+        buf.put_u32(self.remap.len() as u32);
+        let checksum = crc32fast::hash(&buf[remap_begin..]);
+        buf.put_u32(checksum);
+        Ok(BlobFile {
+            id: self.id,
+            generation,
+            file: FileObject::create(path, buf)?,
+            remap: std::mem::take(&mut self.remap),
+            data_size,
+        })
+    }
+}
+
+/// The set of blob files of the storage engine.
+pub struct ValueLog {
+    path: PathBuf,
+    files: RwLock<HashMap<usize, Arc<BlobFile>>>,
+    /// The records no SST refers to anymore, as original offset -> record length by blob file id.
+    garbage: Mutex<HashMap<usize, HashMap<u64, u32>>>,
+    /// Only one garbage collection runs at a time.
+    gc_lock: Mutex<()>,
+}
+
+impl ValueLog {
+    /// Open the blob files under `path`. Only the latest generation of each file is kept; older generations and
+    /// unfinished rewrites left behind by a crash are removed.
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut latest = HashMap::new();
+        let mut to_remove = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            let Some(name) = file_path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.ends_with(".blob.tmp") {
+                to_remove.push(file_path.clone());
+                continue;
+            }
+            let Some(stem) = name.strip_suffix(".blob") else {
+                continue;
+            };
+            let (id, generation) = match stem.split_once('.') {
+                Some((id, generation)) => (id.parse::<usize>()?, generation.parse::<usize>()?),
+                None => (stem.parse::<usize>()?, 0),
+            };
+            match latest.get(&id) {
+                Some(&(current, _)) if current > generation => to_remove.push(file_path.clone()),
+                _ => {
+                    if let Some((_, old_path)) = latest.insert(id, (generation, file_path.clone()))
+                    {
+                        to_remove.push(old_path);
+                    }
+                }
+            }
+        }
+        for file_path in to_remove {
+            std::fs::remove_file(file_path)?;
+        }
+        let mut files = HashMap::new();
+        for (id, (generation, file_path)) in latest {
+            let file = BlobFile::open(
+                id,
+                generation,
+                FileObject::open(&file_path).context("failed to open blob file")?,
+            )?;
+            files.insert(id, Arc::new(file));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            files: RwLock::new(files),
+            garbage: Mutex::new(HashMap::new()),
+            gc_lock: Mutex::new(()),
+        })
+    }
+
+    pub(crate) fn path_of_blob_static(
+        path: impl AsRef<Path>,
+        id: usize,
+        generation: usize,
+    ) -> PathBuf {
+        if generation == 0 {
+            path.as_ref().join(format!("{:05}.blob", id))
+        } else {
+            path.as_ref().join(format!("{:05}.{}.blob", id, generation))
+        }
+    }
+
+    pub(crate) fn path_of_blob(&self, id: usize, generation: usize) -> PathBuf {
+        Self::path_of_blob_static(&self.path, id, generation)
+    }
+
+    /// Make a newly written blob file visible to readers. Must be called before any SST referring to it is.
+    pub(crate) fn add_file(&self, file: BlobFile) {
+        self.files.write().insert(file.id(), Arc::new(file));
+    }
+
+    pub fn file_ids(&self) -> Vec<usize> {
+        let mut ids = self.files.read().keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn file(&self, id: usize) -> Option<Arc<BlobFile>> {
+        self.files.read().get(&id).cloned()
+    }
+
+    /// Read the value a pointer refers to.
+    pub fn read(&self, pointer: &ValuePointer) -> Result<Bytes> {
+        let Some(file) = self.file(pointer.file_id) else {
+            bail!("blob file {} not found", pointer.file_id);
+        };
+        file.read(pointer)
+    }
+
+    /// Mark the values dropped by a compaction as garbage. Pointers to records that were already collected, as
+    /// replayed from the manifest on open, are skipped.
+    pub(crate) fn add_garbage(&self, pointers: &[ValuePointer]) {
+        let files = self.files.read();
+        let mut garbage = self.garbage.lock();
+        for pointer in pointers {
+            let Some(file) = files.get(&pointer.file_id) else {
+                continue;
+            };
+            if file.physical_offset(pointer.offset).is_some() {
+                garbage
+                    .entry(pointer.file_id)
+                    .or_default()
+                    .insert(pointer.offset, pointer.len);
+            }
+        }
+    }
+
+    /// The total size of the records of a blob file that no SST refers to.
+    pub fn garbage_size(&self, id: usize) -> u64 {
+        self.garbage
+            .lock()
+            .get(&id)
+            .map(|records| records.values().map(|len| *len as u64).sum())
+            .unwrap_or_default()
+    }
+}
+
+impl LsmStorageInner {
+    /// Reclaim the space of the values dropped by compactions. A blob file whose live bytes fall below
+    /// `value_log_gc_ratio` of its size is rewritten with only its live records, or removed if nothing refers to
+    /// it. Only the garbage recorded by compactions is looked at, no SST is read.
+    ///
+    /// Compaction keeps every version that a reader at or above the watermark can see, so a dropped value is never
+    /// read again.
+    pub fn gc_value_log(&self) -> Result<()> {
+        let _gc_lock = self.value_log.gc_lock.lock();
+        let files = self.value_log.files.read().clone();
+        let mut to_remove = Vec::new();
+        for (id, file) in files {
+            let garbage_size = self.value_log.garbage_size(id);
+            if garbage_size >= file.data_size() {
+                self.value_log.files.write().remove(&id);
+                self.value_log.garbage.lock().remove(&id);
+                to_remove.push(file);
+                continue;
+            }
+            let live_size = file.data_size() - garbage_size;
+            if (live_size as f64) >= self.options.value_log_gc_ratio * file.data_size() as f64 {
+                continue;
+            }
+            // Compactions running meanwhile may add more garbage, which stays for the next generation.
+            let Some(garbage) = self
+                .value_log
+                .garbage
+                .lock()
+                .get(&id)
+                .map(|records| records.keys().copied().collect::<HashSet<_>>())
+            else {
+                continue;
+            };
+            let generation = file.generation() + 1;
+            let tmp_path = self
+                .value_log
+                .path_of_blob(id, generation)
+                .with_extension("blob.tmp");
+            let new_file = file.rewrite(&garbage, &tmp_path)?;
+            std::fs::rename(&tmp_path, self.value_log.path_of_blob(id, generation))?;
+            self.sync_dir()?;
+            self.value_log.files.write().insert(id, Arc::new(new_file));
+            if let Some(records) = self.value_log.garbage.lock().get_mut(&id) {
+                records.retain(|offset, _| !garbage.contains(offset));
+            }
+            to_remove.push(file);
+        }
+        if to_remove.is_empty() {
+            return Ok(());
+        }
+        // Readers that already hold a removed file can still read it through the open handle.
+        for file in to_remove {
+            std::fs::remove_file(self.value_log.path_of_blob(file.id(), file.generation()))?;
+        }
+        self.sync_dir()?;
+        Ok(())
+    }
+}
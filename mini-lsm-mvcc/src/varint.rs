@@ -0,0 +1,37 @@
+//! LEB128-style variable-length integers: 7 bits per byte, least significant group first, with the high
+//! bit set on every byte but the last.
+
+use bytes::{Buf, BufMut};
+
+/// Returns the number of bytes `value` takes when varint-encoded.
+pub(crate) fn varint_len(mut value: u32) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+/// Appends `value` to `buf` as a varint.
+pub(crate) fn put_varint(buf: &mut impl BufMut, mut value: u32) {
+    while value >= 0x80 {
+        buf.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Reads a varint from `buf` and advances it past the encoded bytes.
+pub(crate) fn get_varint(buf: &mut impl Buf) -> u32 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
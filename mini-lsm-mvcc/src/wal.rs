@@ -9,27 +9,159 @@ use bytes::{Buf, BufMut, Bytes};
 use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
-use crate::key::{KeyBytes, KeySlice};
+use crate::key::{KeyBytes, ValueType};
+use crate::range_tombstone::RangeTombstone;
+
+/// Written in place of the key length to mark a range tombstone record. Keys never get this long.
+const RANGE_TOMBSTONE_MARKER: u32 = u32::MAX;
+
+/// Starts the header of WALs of version 2 and above, followed by the format version (u32). Version 1 WALs
+/// have no header, and their first record would need a key of 0x4d4c bytes starting with `WA` to look like it.
+const WAL_MAGIC: u32 = 0x4d4c_5741;
+
+/// The WAL format written by this version. Version 3 writes a whole batch as one record. Version 2 records
+/// hold a single entry with u32 lengths and its value type (u8) before the value length, or a range tombstone.
+/// Version 1 records are key_len (u16) | key | ts (u64) | value_len (u16) | value | checksum (u32), with an
+/// empty value as the tombstone.
+const WAL_FORMAT_VERSION: u32 = 3;
+
+/// How to deal with incomplete or corrupted records when replaying a WAL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Fail on any incomplete or corrupted record.
+    #[default]
+    AbsoluteConsistency,
+    /// Drop incomplete or corrupted records at the end of the WAL, as left by a crash in the middle of an
+    /// append. Fail on a bad record that is followed by valid ones.
+    TolerateCorruptedTailRecords,
+    /// Replay the records before the first incomplete or corrupted one, and drop everything from there.
+    PointInTime,
+    /// Drop incomplete or corrupted records, and keep replaying the valid records after them.
+    SkipAnyCorruptedRecords,
+}
+
+/// A range of a WAL that was not replayed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DroppedRecords {
+    /// Offset of the range in the WAL.
+    pub offset: usize,
+    /// Length of the range in bytes.
+    pub len: usize,
+    /// Why the first record of the range could not be replayed.
+    pub reason: String,
+}
+
+/// What was replayed from a WAL, and what was dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WalRecoveryReport {
+    /// Number of records replayed.
+    pub num_records: usize,
+    /// The ranges that were dropped, in the order of the WAL.
+    pub dropped: Vec<DroppedRecords>,
+}
+
+impl WalRecoveryReport {
+    /// Number of bytes that were dropped.
+    pub fn dropped_bytes(&self) -> usize {
+        self.dropped.iter().map(|dropped| dropped.len).sum()
+    }
+}
+
+enum WalRecord {
+    Batch {
+        ts: u64,
+        entries: Vec<(Bytes, ValueType, Bytes)>,
+    },
+    RangeTombstone(RangeTombstone),
+}
+
+enum RecordError {
+    /// The WAL ends in the middle of the record.
+    Incomplete,
+    Corrupted(&'static str),
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::Incomplete => write!(f, "incomplete record"),
+            RecordError::Corrupted(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Reads a record, failing instead of panicking when the buffer is too short.
+struct RecordReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+    fn get_slice(&mut self, len: usize) -> Result<&'a [u8], RecordError> {
+        if self.buf.len() < len {
+            return Err(RecordError::Incomplete);
+        }
+        let (slice, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(slice)
+    }
+
+    fn get_bytes(&mut self, len: usize) -> Result<Bytes, RecordError> {
+        self.get_slice(len).map(Bytes::copy_from_slice)
+    }
+
+    fn get_u8(&mut self) -> Result<u8, RecordError> {
+        Ok(self.get_slice(1)?[0])
+    }
+
+    fn get_u16(&mut self) -> Result<u16, RecordError> {
+        Ok(self.get_slice(2)?.get_u16())
+    }
+
+    fn get_u32(&mut self) -> Result<u32, RecordError> {
+        Ok(self.get_slice(4)?.get_u32())
+    }
+
+    fn get_u64(&mut self) -> Result<u64, RecordError> {
+        Ok(self.get_slice(8)?.get_u64())
+    }
+}
 
 pub struct Wal {
     file: Arc<Mutex<BufWriter<File>>>,
+    format_version: u32,
 }
 
 impl Wal {
     pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = BufWriter::new(
+            OpenOptions::new()
+                .read(true)
+                .create_new(true)
+                .write(true)
+                .open(path)
+                .context("failed to create WAL")?,
+        );
+        Self::write_header(&mut file)?;
         Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(
-                OpenOptions::new()
-                    .read(true)
-                    .create_new(true)
-                    .write(true)
-                    .open(path)
-                    .context("failed to create WAL")?,
-            ))),
+            file: Arc::new(Mutex::new(file)),
+            format_version: WAL_FORMAT_VERSION,
         })
     }
 
-    pub fn recover(path: impl AsRef<Path>, skiplist: &SkipMap<KeyBytes, Bytes>) -> Result<Self> {
+    fn write_header(file: &mut BufWriter<File>) -> Result<()> {
+        let mut buf = Vec::with_capacity(std::mem::size_of::<u32>() * 2);
+        buf.put_u32(WAL_MAGIC);
+        buf.put_u32(WAL_FORMAT_VERSION);
+        file.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn recover(
+        path: impl AsRef<Path>,
+        skiplist: &SkipMap<KeyBytes, (ValueType, Bytes)>,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        recovery_mode: WalRecoveryMode,
+    ) -> Result<(Self, WalRecoveryReport)> {
         let path = path.as_ref();
         let mut file = OpenOptions::new()
             .read(true)
@@ -38,48 +170,260 @@ impl Wal {
             .context("failed to recover from WAL")?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf)?;
-        let mut rbuf: &[u8] = buf.as_slice();
-        while rbuf.has_remaining() {
+        let mut file = BufWriter::new(file);
+        let mut report = WalRecoveryReport::default();
+        if buf.len() < 8 && WAL_MAGIC.to_be_bytes().starts_with(&buf) {
+            // The header of a new WAL may not have completely reached the disk.
+            file.get_mut().set_len(0)?;
+            Self::write_header(&mut file)?;
+            buf.clear();
+        }
+        let (format_version, mut offset) = if buf.is_empty() {
+            (WAL_FORMAT_VERSION, 0)
+        } else if buf.len() >= 8 && (&buf[..4]).get_u32() == WAL_MAGIC {
+            ((&buf[4..8]).get_u32(), 8)
+        } else {
+            (1, 0)
+        };
+        if format_version > WAL_FORMAT_VERSION {
+            bail!("unsupported WAL format version {}", format_version);
+        }
+        while offset < buf.len() {
+            let err = match Self::decode_record(&buf[offset..], format_version) {
+                Ok((record, len)) => {
+                    match record {
+                        WalRecord::Batch { ts, entries } => {
+                            for (key, value_type, value) in entries {
+                                skiplist.insert(
+                                    KeyBytes::from_bytes_with_ts(key, ts),
+                                    (value_type, value),
+                                );
+                            }
+                        }
+                        WalRecord::RangeTombstone(range_tombstone) => {
+                            range_tombstones.push(range_tombstone)
+                        }
+                    }
+                    report.num_records += 1;
+                    offset += len;
+                    continue;
+                }
+                Err(err) => err,
+            };
+            let next_offset = match recovery_mode {
+                WalRecoveryMode::AbsoluteConsistency => None,
+                // A corrupted length may make a record look incomplete, so check that it is really the tail.
+                WalRecoveryMode::TolerateCorruptedTailRecords => {
+                    match Self::find_next_record(&buf, offset + 1, format_version) {
+                        Some(_) => None,
+                        None => Some(buf.len()),
+                    }
+                }
+                WalRecoveryMode::PointInTime => Some(buf.len()),
+                WalRecoveryMode::SkipAnyCorruptedRecords => Some(
+                    Self::find_next_record(&buf, offset + 1, format_version).unwrap_or(buf.len()),
+                ),
+            };
+            let Some(next_offset) = next_offset else {
+                bail!("{} at offset {} of WAL {}", err, offset, path.display());
+            };
+            report.dropped.push(DroppedRecords {
+                offset,
+                len: next_offset - offset,
+                reason: err.to_string(),
+            });
+            offset = next_offset;
+        }
+        Ok((
+            Self {
+                file: Arc::new(Mutex::new(file)),
+                format_version,
+            },
+            report,
+        ))
+    }
+
+    /// Find the offset of the first valid record at or after `offset`. Used to resume replaying after a
+    /// corrupted record, whose length cannot be trusted.
+    fn find_next_record(buf: &[u8], offset: usize, format_version: u32) -> Option<usize> {
+        (offset..buf.len())
+            .find(|&offset| Self::decode_record(&buf[offset..], format_version).is_ok())
+    }
+
+    /// Decode the record at the start of `buf`, returns the record and its length.
+    fn decode_record(buf: &[u8], format_version: u32) -> Result<(WalRecord, usize), RecordError> {
+        if format_version < 2 {
+            return Self::decode_record_v1(buf);
+        }
+        let mut reader = RecordReader { buf };
+        let first = reader.get_u32()?;
+        let record = if first == RANGE_TOMBSTONE_MARKER {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.write_u32(first);
+            let begin_len = reader.get_u32()?;
+            hasher.write_u32(begin_len);
+            let begin = reader.get_bytes(begin_len as usize)?;
+            hasher.write(&begin);
+            let end_len = reader.get_u32()?;
+            hasher.write_u32(end_len);
+            let end = reader.get_bytes(end_len as usize)?;
+            hasher.write(&end);
+            let ts = reader.get_u64()?;
+            hasher.write_u64(ts);
+            if reader.get_u32()? != hasher.finalize() {
+                return Err(RecordError::Corrupted("checksum mismatch"));
+            }
+            WalRecord::RangeTombstone(RangeTombstone { begin, end, ts })
+        } else if format_version >= 3 {
+            let batch_len = first as usize;
+            let batch = reader.get_slice(batch_len)?;
+            if reader.get_u32()? != crc32fast::hash(&buf[..4 + batch_len]) {
+                return Err(RecordError::Corrupted("checksum mismatch"));
+            }
+            // The batch is checksummed, so running out of it means it was written wrongly.
+            Self::decode_batch(batch).map_err(|err| match err {
+                RecordError::Incomplete => RecordError::Corrupted("malformed batch"),
+                err => err,
+            })?
+        } else {
             let mut hasher = crc32fast::Hasher::new();
-            let key_len = rbuf.get_u16() as usize;
-            hasher.write_u16(key_len as u16);
-            let key = Bytes::copy_from_slice(&rbuf[..key_len]);
+            hasher.write_u32(first);
+            let key = reader.get_bytes(first as usize)?;
             hasher.write(&key);
-            rbuf.advance(key_len);
-            let ts = rbuf.get_u64();
+            let ts = reader.get_u64()?;
             hasher.write_u64(ts);
-            let value_len = rbuf.get_u16() as usize;
-            hasher.write_u16(value_len as u16);
-            let value = Bytes::copy_from_slice(&rbuf[..value_len]);
+            let value_type = reader.get_u8()?;
+            hasher.write_u8(value_type);
+            let value_len = reader.get_u32()?;
+            hasher.write_u32(value_len);
+            let value = reader.get_bytes(value_len as usize)?;
             hasher.write(&value);
-            rbuf.advance(value_len);
-            let checksum = rbuf.get_u32();
-            if hasher.finalize() != checksum {
-                bail!("checksum mismatch");
+            if reader.get_u32()? != hasher.finalize() {
+                return Err(RecordError::Corrupted("checksum mismatch"));
             }
-            skiplist.insert(KeyBytes::from_bytes_with_ts(key, ts), value);
+            let value_type = ValueType::try_from_u8(value_type)
+                .ok_or(RecordError::Corrupted("unknown value type"))?;
+            WalRecord::Batch {
+                ts,
+                entries: vec![(key, value_type, value)],
+            }
+        };
+        Ok((record, buf.len() - reader.buf.len()))
+    }
+
+    /// Decode a record of a version 1 WAL, which holds a single entry and no range tombstone.
+    fn decode_record_v1(buf: &[u8]) -> Result<(WalRecord, usize), RecordError> {
+        let mut reader = RecordReader { buf };
+        let mut hasher = crc32fast::Hasher::new();
+        let key_len = reader.get_u16()?;
+        hasher.write_u16(key_len);
+        let key = reader.get_bytes(key_len as usize)?;
+        hasher.write(&key);
+        let ts = reader.get_u64()?;
+        hasher.write_u64(ts);
+        let value_len = reader.get_u16()?;
+        hasher.write_u16(value_len);
+        let value = reader.get_bytes(value_len as usize)?;
+        hasher.write(&value);
+        if reader.get_u32()? != hasher.finalize() {
+            return Err(RecordError::Corrupted("checksum mismatch"));
         }
-        Ok(Self {
-            file: Arc::new(Mutex::new(BufWriter::new(file))),
-        })
+        let value_type = ValueType::of_untyped_value(&value);
+        let record = WalRecord::Batch {
+            ts,
+            entries: vec![(key, value_type, value)],
+        };
+        Ok((record, buf.len() - reader.buf.len()))
+    }
+
+    fn decode_batch(batch: &[u8]) -> Result<WalRecord, RecordError> {
+        let mut reader = RecordReader { buf: batch };
+        let ts = reader.get_u64()?;
+        let num_entries = reader.get_u32()?;
+        let mut entries = Vec::new();
+        for _ in 0..num_entries {
+            let key_len = reader.get_u32()?;
+            let key = reader.get_bytes(key_len as usize)?;
+            let value_type = ValueType::try_from_u8(reader.get_u8()?)
+                .ok_or(RecordError::Corrupted("unknown value type"))?;
+            let value_len = reader.get_u32()?;
+            let value = reader.get_bytes(value_len as usize)?;
+            entries.push((key, value_type, value));
+        }
+        if !reader.buf.is_empty() {
+            return Err(RecordError::Corrupted("malformed batch"));
+        }
+        Ok(WalRecord::Batch { ts, entries })
+    }
+
+    /// Only WALs of the current version are appended to. Older ones are replayed and then replaced on flush.
+    fn check_writable(&self) -> Result<()> {
+        if self.format_version < WAL_FORMAT_VERSION {
+            bail!(
+                "cannot append to a WAL of format version {}",
+                self.format_version
+            );
+        }
+        Ok(())
     }
 
-    pub fn put(&self, key: KeySlice, value: &[u8]) -> Result<()> {
+    /// Record a batch of entries sharing the same timestamp: batch_len (u32) | ts (u64) | num_entries (u32) |
+    /// entries | checksum (u32), where each entry is key_len (u32) | key | value_type (u8) | value_len (u32) |
+    /// value, and the checksum covers everything before it. Recovery applies either the whole batch or none
+    /// of it.
+    pub fn put_batch(&self, ts: u64, entries: &[(&[u8], ValueType, &[u8])]) -> Result<()> {
+        self.check_writable()?;
         let mut file = self.file.lock();
-        let mut buf: Vec<u8> =
-            Vec::with_capacity(key.raw_len() + value.len() + std::mem::size_of::<u16>());
+        let estimated_size = entries
+            .iter()
+            .map(|(key, _, value)| key.len() + value.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .sum::<usize>()
+            + std::mem::size_of::<u32>() * 3
+            + std::mem::size_of::<u64>();
+        let mut buf: Vec<u8> = Vec::with_capacity(estimated_size);
+        // The batch length is filled in once the entries are encoded.
+        buf.put_u32(0);
+        buf.put_u64(ts);
+        buf.put_u32(entries.len() as u32);
+        for (key, value_type, value) in entries {
+            buf.put_u32(key.len() as u32);
+            buf.put_slice(key);
+            buf.put_u8(value_type.to_u8());
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+        }
+        let batch_len = (buf.len() - std::mem::size_of::<u32>()) as u32;
+        buf[..4].copy_from_slice(&batch_len.to_be_bytes());
+        buf.put_u32(crc32fast::hash(&buf));
+        debug_assert_eq!(buf.len(), estimated_size);
+        file.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Record a range tombstone: marker (u32) | begin_len (u32) | begin | end_len (u32) | end | ts (u64) |
+    /// checksum (u32).
+    pub fn delete_range(&self, range_tombstone: &RangeTombstone) -> Result<()> {
+        self.check_writable()?;
+        let mut file = self.file.lock();
+        let mut buf: Vec<u8> = Vec::with_capacity(
+            range_tombstone.begin.len()
+                + range_tombstone.end.len()
+                + std::mem::size_of::<u32>() * 4
+                + std::mem::size_of::<u64>(),
+        );
         let mut hasher = crc32fast::Hasher::new();
-        hasher.write_u16(key.key_len() as u16);
-        buf.put_u16(key.key_len() as u16);
-        hasher.write(key.key_ref());
-        buf.put_slice(key.key_ref());
-        hasher.write_u64(key.ts());
-        buf.put_u64(key.ts());
-        hasher.write_u16(value.len() as u16);
-        buf.put_u16(value.len() as u16);
-        buf.put_slice(value);
-        hasher.write(value);
-        // add checksum: week 2 day 7
+        hasher.write_u32(RANGE_TOMBSTONE_MARKER);
+        buf.put_u32(RANGE_TOMBSTONE_MARKER);
+        hasher.write_u32(range_tombstone.begin.len() as u32);
+        buf.put_u32(range_tombstone.begin.len() as u32);
+        hasher.write(&range_tombstone.begin);
+        buf.put_slice(&range_tombstone.begin);
+        hasher.write_u32(range_tombstone.end.len() as u32);
+        buf.put_u32(range_tombstone.end.len() as u32);
+        hasher.write(&range_tombstone.end);
+        buf.put_slice(&range_tombstone.end);
+        hasher.write_u64(range_tombstone.ts);
+        buf.put_u64(range_tombstone.ts);
         buf.put_u32(hasher.finalize());
         file.write_all(&buf)?;
         Ok(())
@@ -91,4 +435,10 @@ impl Wal {
         file.get_mut().sync_all()?;
         Ok(())
     }
+
+    /// Write to `file` from now on, e.g. to make the writes fail.
+    #[cfg(test)]
+    pub(crate) fn redirect_for_test(&self, file: File) {
+        *self.file.lock() = BufWriter::new(file);
+    }
 }
@@ -29,6 +29,8 @@ enum Args {
         size_ratio_percent: usize,
         #[clap(long, default_value = "50")]
         iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
     },
     Tiered {
         #[clap(long)]
@@ -43,6 +45,8 @@ enum Args {
         min_merge_width: usize,
         #[clap(long, default_value = "50")]
         iterations: usize,
+        #[clap(long, default_value = "32")]
+        sst_size_mb: usize,
     },
     Leveled {
         #[clap(long)]
@@ -67,18 +71,15 @@ pub struct MockStorage {
     next_sst_id: usize,
     /// Maps SST ID to the original flushed SST ID
     file_list: HashMap<usize, usize>,
-    total_flushes: usize,
-    total_writes: usize,
-}
-
-impl Default for MockStorage {
-    fn default() -> Self {
-        Self::new()
-    }
+    /// Bytes flushed, and bytes written by flushes and compactions
+    total_flushes: u64,
+    total_writes: u64,
+    /// Target size of the SSTs; flushes write smaller SSTs
+    sst_size: u64,
 }
 
 impl MockStorage {
-    pub fn new() -> Self {
+    pub fn new(sst_size_mb: usize) -> Self {
         let snapshot = LsmStorageState {
             memtable: Arc::new(MemTable::create(0)),
             imm_memtables: Vec::new(),
@@ -92,6 +93,7 @@ impl MockStorage {
             file_list: Default::default(),
             total_flushes: 0,
             total_writes: 0,
+            sst_size: sst_size_mb as u64 * 1024 * 1024,
         }
     }
 
@@ -101,30 +103,94 @@ impl MockStorage {
         id
     }
 
-    pub fn flush_sst_to_l0(&mut self) -> usize {
+    fn flush_sst(&mut self) -> usize {
+        use rand::Rng;
         let id = self.generate_sst_id();
-        self.snapshot.l0_sstables.push(id);
+        let size = rand::thread_rng().gen_range((1 << 20).min(self.sst_size)..=self.sst_size);
+        let (first_key, last_key) = generate_random_key_range();
+        self.snapshot.sstables.insert(
+            id,
+            Arc::new(SsTable::create_meta_only(id, size, first_key, last_key)),
+        );
         self.file_list.insert(id, id);
-        self.total_flushes += 1;
-        self.total_writes += 1;
+        self.total_flushes += size;
+        self.total_writes += size;
+        id
+    }
+
+    pub fn flush_sst_to_l0(&mut self) -> usize {
+        let id = self.flush_sst();
+        self.snapshot.l0_sstables.push(id);
         id
     }
 
     pub fn flush_sst_to_new_tier(&mut self) {
-        let id = self.generate_sst_id();
+        let id = self.flush_sst();
         self.snapshot.levels.insert(0, (id, vec![id]));
-        self.file_list.insert(id, id);
-        self.total_flushes += 1;
-        self.total_writes += 1;
+    }
+
+    /// Write the output of compacting `input`, as SSTs of the target size splitting the key range of the input.
+    pub fn write_compaction_output(&mut self, input: &[usize]) -> Vec<usize> {
+        let size = self.table_size_of(input);
+        let num_ssts = size.div_ceil(self.sst_size).max(1);
+        let begin = input
+            .iter()
+            .map(|id| self.snapshot.sstables[id].first_key().clone())
+            .min()
+            .unwrap();
+        let end = input
+            .iter()
+            .map(|id| self.snapshot.sstables[id].last_key().clone())
+            .max()
+            .unwrap();
+        let splits = generate_random_split(begin, end, num_ssts as usize);
+        let mut sst_ids = Vec::new();
+        for (idx, (first_key, last_key)) in splits.into_iter().enumerate() {
+            let idx = idx as u64;
+            let sst_size = size * (idx + 1) / num_ssts - size * idx / num_ssts;
+            let new_sst_id = self.generate_sst_id();
+            self.snapshot.sstables.insert(
+                new_sst_id,
+                Arc::new(SsTable::create_meta_only(
+                    new_sst_id, sst_size, first_key, last_key,
+                )),
+            );
+            let file = input[(idx * input.len() as u64 / num_ssts) as usize];
+            self.file_list.insert(new_sst_id, file);
+            self.total_writes += sst_size;
+            sst_ids.push(new_sst_id);
+        }
+        sst_ids
     }
 
     pub fn remove(&mut self, files_to_remove: &[usize]) {
         for file_id in files_to_remove {
             let ret = self.file_list.remove(file_id);
             assert!(ret.is_some(), "failed to remove file {}", file_id);
+            self.snapshot.sstables.remove(file_id);
         }
     }
 
+    /// Bytes of all SSTs, with the inputs of a compaction not applied yet.
+    pub fn space_usage(&self) -> u64 {
+        self.snapshot
+            .sstables
+            .values()
+            .map(|table| table.table_size())
+            .sum()
+    }
+
+    fn table_size_of(&self, files: &[usize]) -> u64 {
+        files
+            .iter()
+            .map(|id| self.snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    fn level_size_mb(&self, files: &[usize]) -> u64 {
+        self.table_size_of(files) / 1024 / 1024
+    }
+
     fn check_keys(&self) {
         for (level, files) in &self.snapshot.levels {
             if files.len() >= 2 {
@@ -151,15 +217,17 @@ impl MockStorage {
     pub fn dump_original_id(&self, always_show_l0: bool, with_key: bool) {
         if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
             println!(
-                "L0 ({}): {:?}",
+                "L0 ({}, {}MB): {:?}",
                 self.snapshot.l0_sstables.len(),
+                self.level_size_mb(&self.snapshot.l0_sstables),
                 self.snapshot.l0_sstables,
             );
         }
         for (level, files) in &self.snapshot.levels {
             println!(
-                "L{level} ({}): {:?}",
+                "L{level} ({}, {}MB): {:?}",
                 files.len(),
+                self.level_size_mb(files),
                 files.iter().map(|x| self.file_list[x]).collect::<Vec<_>>()
             );
         }
@@ -171,13 +239,19 @@ impl MockStorage {
     pub fn dump_real_id(&self, always_show_l0: bool, with_key: bool) {
         if !self.snapshot.l0_sstables.is_empty() || always_show_l0 {
             println!(
-                "L0 ({}): {:?}",
+                "L0 ({}, {}MB): {:?}",
                 self.snapshot.l0_sstables.len(),
+                self.level_size_mb(&self.snapshot.l0_sstables),
                 self.snapshot.l0_sstables,
             );
         }
         for (level, files) in &self.snapshot.levels {
-            println!("L{level} ({}): {:?}", files.len(), files);
+            println!(
+                "L{level} ({}, {}MB): {:?}",
+                files.len(),
+                self.level_size_mb(files),
+                files
+            );
         }
         if with_key {
             self.check_keys();
@@ -235,6 +309,7 @@ fn main() {
             iterations,
             level0_file_num_compaction_trigger,
             max_levels,
+            sst_size_mb,
         } => {
             // TODO(chi): use unified logic for all 3 compactions...
             let controller =
@@ -243,7 +318,7 @@ fn main() {
                     level0_file_num_compaction_trigger,
                     max_levels,
                 });
-            let mut storage = MockStorage::new();
+            let mut storage = MockStorage::new(sst_size_mb);
             for i in 0..max_levels {
                 storage.snapshot.levels.push((i + 1, Vec::new()));
             }
@@ -262,17 +337,13 @@ fn main() {
                     println!("--- Compaction Task ---");
                     controller.generate_compaction_task(&storage.snapshot)
                 } {
-                    let mut sst_ids = Vec::new();
-                    for file in task
+                    let input = task
                         .upper_level_sst_ids
                         .iter()
                         .chain(task.lower_level_sst_ids.iter())
-                    {
-                        let new_sst_id = storage.generate_sst_id();
-                        sst_ids.push(new_sst_id);
-                        storage.file_list.insert(new_sst_id, *file);
-                        storage.total_writes += 1;
-                    }
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let sst_ids = storage.write_compaction_output(&input);
                     print!(
                         "Upper L{} {:?} ",
                         task.upper_level.unwrap_or_default(),
@@ -283,7 +354,7 @@ fn main() {
                         task.lower_level, task.lower_level_sst_ids
                     );
                     println!("-> {:?}", sst_ids);
-                    max_space = max_space.max(storage.file_list.len());
+                    max_space = max_space.max(storage.space_usage());
                     let (snapshot, del) =
                         controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
                     storage.snapshot = snapshot;
@@ -304,18 +375,18 @@ fn main() {
                 } else {
                     println!("{num_compactions} compaction triggered in this iteration");
                 }
-                max_space = max_space.max(storage.file_list.len());
+                max_space = max_space.max(storage.space_usage());
                 println!("--- Statistics ---");
                 println!(
-                    "Write Amplification: {}/{}={:.3}x",
-                    storage.total_writes,
-                    storage.total_flushes,
+                    "Write Amplification: {}MB/{}MB={:.3}x",
+                    storage.total_writes / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     storage.total_writes as f64 / storage.total_flushes as f64
                 );
                 println!(
-                    "Maximum Space Usage: {}/{}={:.3}x",
-                    max_space,
-                    storage.total_flushes,
+                    "Maximum Space Usage: {}MB/{}MB={:.3}x",
+                    max_space / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     max_space as f64 / storage.total_flushes as f64
                 );
                 println!(
@@ -338,6 +409,7 @@ fn main() {
             size_ratio,
             min_merge_width,
             iterations,
+            sst_size_mb,
         } => {
             let controller = TieredCompactionController::new(TieredCompactionOptions {
                 num_tiers: level0_file_num_compaction_trigger,
@@ -345,7 +417,7 @@ fn main() {
                 size_ratio,
                 min_merge_width,
             });
-            let mut storage = MockStorage::new();
+            let mut storage = MockStorage::new(sst_size_mb);
             let mut max_space = 0;
             for i in 0..iterations {
                 println!("=== Iteration {i} ===");
@@ -362,18 +434,14 @@ fn main() {
                     println!("--- Compaction Task ---");
                     controller.generate_compaction_task(&storage.snapshot)
                 } {
-                    let mut sst_ids = Vec::new();
+                    let mut input = Vec::new();
                     for (tier_id, files) in &task.tiers {
-                        for file in files {
-                            let new_sst_id = storage.generate_sst_id();
-                            sst_ids.push(new_sst_id);
-                            storage.file_list.insert(new_sst_id, *file);
-                            storage.total_writes += 1;
-                        }
+                        input.extend(files);
                         print!("L{} {:?} ", tier_id, files);
                     }
+                    let sst_ids = storage.write_compaction_output(&input);
                     println!("-> {:?}", sst_ids);
-                    max_space = max_space.max(storage.file_list.len());
+                    max_space = max_space.max(storage.space_usage());
                     let (snapshot, del) =
                         controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
                     storage.snapshot = snapshot;
@@ -394,18 +462,18 @@ fn main() {
                 } else {
                     println!("{num_compactions} compaction triggered in this iteration");
                 }
-                max_space = max_space.max(storage.file_list.len());
+                max_space = max_space.max(storage.space_usage());
                 println!("--- Statistics ---");
                 println!(
-                    "Write Amplification: {}/{}={:.3}x",
-                    storage.total_writes,
-                    storage.total_flushes,
+                    "Write Amplification: {}MB/{}MB={:.3}x",
+                    storage.total_writes / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     storage.total_writes as f64 / storage.total_flushes as f64
                 );
                 println!(
-                    "Maximum Space Usage: {}/{}={:.3}x",
-                    max_space,
-                    storage.total_flushes,
+                    "Maximum Space Usage: {}MB/{}MB={:.3}x",
+                    max_space / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     max_space as f64 / storage.total_flushes as f64
                 );
                 println!(
@@ -437,24 +505,14 @@ fn main() {
                 base_level_size_mb,
             });
 
-            let mut storage = MockStorage::new();
+            let mut storage = MockStorage::new(sst_size_mb);
             for i in 0..max_levels {
                 storage.snapshot.levels.push((i + 1, Vec::new()));
             }
             let mut max_space = 0;
             for i in 0..iterations {
                 println!("=== Iteration {i} ===");
-                let id = storage.flush_sst_to_l0();
-                let (first_key, last_key) = generate_random_key_range();
-                storage.snapshot.sstables.insert(
-                    id,
-                    Arc::new(SsTable::create_meta_only(
-                        id,
-                        sst_size_mb as u64 * 1024 * 1024,
-                        first_key,
-                        last_key,
-                    )),
-                );
+                storage.flush_sst_to_l0();
                 println!("--- After Flush ---");
                 if dump_real_id {
                     storage.dump_real_id(false, true);
@@ -466,41 +524,13 @@ fn main() {
                     println!("--- Compaction Task ---");
                     controller.generate_compaction_task(&storage.snapshot)
                 } {
-                    let mut sst_ids = Vec::new();
-                    let split_num = task.upper_level_sst_ids.len() + task.lower_level_sst_ids.len();
-                    let mut first_keys = Vec::new();
-                    let mut last_keys = Vec::new();
-                    for file in task
-                        .upper_level_sst_ids
-                        .iter()
-                        .chain(task.lower_level_sst_ids.iter())
-                    {
-                        first_keys.push(storage.snapshot.sstables[file].first_key().clone());
-                        last_keys.push(storage.snapshot.sstables[file].last_key().clone());
-                    }
-                    let begin = first_keys.into_iter().min().unwrap();
-                    let end = last_keys.into_iter().max().unwrap();
-                    let splits = generate_random_split(begin, end, split_num);
-                    for (id, file) in task
+                    let input = task
                         .upper_level_sst_ids
                         .iter()
                         .chain(task.lower_level_sst_ids.iter())
-                        .enumerate()
-                    {
-                        let new_sst_id = storage.generate_sst_id();
-                        sst_ids.push(new_sst_id);
-                        storage.file_list.insert(new_sst_id, *file);
-                        storage.total_writes += 1;
-                        storage.snapshot.sstables.insert(
-                            new_sst_id,
-                            Arc::new(SsTable::create_meta_only(
-                                new_sst_id,
-                                sst_size_mb as u64 * 1024 * 1024,
-                                splits[id].0.clone(),
-                                splits[id].1.clone(),
-                            )),
-                        );
-                    }
+                        .copied()
+                        .collect::<Vec<_>>();
+                    let sst_ids = storage.write_compaction_output(&input);
                     print!(
                         "Upper L{} [{}] ",
                         task.upper_level.unwrap_or_default(),
@@ -560,7 +590,7 @@ fn main() {
                             .collect::<Vec<_>>()
                             .join(", ")
                     );
-                    max_space = max_space.max(storage.file_list.len());
+                    max_space = max_space.max(storage.space_usage());
                     let (snapshot, del) =
                         controller.apply_compaction_result(&storage.snapshot, &task, &sst_ids);
                     storage.snapshot = snapshot;
@@ -581,18 +611,18 @@ fn main() {
                 } else {
                     println!("{num_compactions} compaction triggered in this iteration");
                 }
-                max_space = max_space.max(storage.file_list.len());
+                max_space = max_space.max(storage.space_usage());
                 println!("--- Statistics ---");
                 println!(
-                    "Write Amplification: {}/{}={:.3}x",
-                    storage.total_writes,
-                    storage.total_flushes,
+                    "Write Amplification: {}MB/{}MB={:.3}x",
+                    storage.total_writes / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     storage.total_writes as f64 / storage.total_flushes as f64
                 );
                 println!(
-                    "Maximum Space Usage: {}/{}={:.3}x",
-                    max_space,
-                    storage.total_flushes,
+                    "Maximum Space Usage: {}MB/{}MB={:.3}x",
+                    max_space / 1024 / 1024,
+                    storage.total_flushes / 1024 / 1024,
                     max_space as f64 / storage.total_flushes as f64
                 );
                 println!(
@@ -314,9 +314,6 @@ fn main() -> Result<()> {
     let lsm = MiniLsm::open(
         args.path,
         LsmStorageOptions {
-            block_size: 4096,
-            target_sst_size: 2 << 20, // 2MB
-            num_memtable_limit: 3,
             compaction_options: match args.compaction {
                 CompactionStrategy::None => CompactionOptions::NoCompaction,
                 CompactionStrategy::Simple => {
@@ -343,6 +340,7 @@ fn main() -> Result<()> {
             },
             enable_wal: args.enable_wal,
             serializable: args.serializable,
+            ..Default::default()
         },
     )?;
 
@@ -80,6 +80,19 @@ pub struct LsmStorageOptions {
     pub serializable: bool,
 }
 
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20, // 2MB
+            compaction_options: CompactionOptions::NoCompaction,
+            enable_wal: false,
+            num_memtable_limit: 3,
+            serializable: false,
+        }
+    }
+}
+
 impl LsmStorageOptions {
     pub fn default_for_week1_test() -> Self {
         Self {
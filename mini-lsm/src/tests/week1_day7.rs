@@ -58,8 +58,8 @@ fn test_task2_sst_decode() {
     let path = dir.path().join("1.sst");
     let sst = builder.build_for_test(&path).unwrap();
     let sst2 = SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap();
-    let bloom_1 = sst.bloom.as_ref().unwrap();
-    let bloom_2 = sst2.bloom.as_ref().unwrap();
+    let bloom_1 = sst.bloom().unwrap();
+    let bloom_2 = sst2.bloom().unwrap();
     assert_eq!(bloom_1.k, bloom_2.k);
     assert_eq!(bloom_1.filter, bloom_2.filter);
 }